@@ -4,7 +4,10 @@ extern crate test;
 extern crate rand;
 
 use vegas_rs::state::{Spin, HeisenbergSpin, State};
-use vegas_rs::energy::{Gauge, UniaxialAnisotropy, EnergyComponent, CompoundEnergy};
+use vegas_rs::energy::{Gauge, UniaxialAnisotropy, EnergyComponent, CompoundEnergy,
+                       ExchangeEnergy, ZeemanEnergy};
+use vegas_rs::lattice::{LatticeBuilder, UnitCell};
+use vegas_rs::soa::{PaddedExchange, SoaEnergy, SoaState};
 use rand::thread_rng;
 
 
@@ -41,3 +44,100 @@ fn compound_energy_hit_1k_heisenberg(b: &mut test::Bencher) {
         assert!(energy == 2_000.0);
     })
 }
+
+
+fn cubic_exchange() -> ExchangeEnergy {
+    let lattice = LatticeBuilder::new(UnitCell::cubic()).extent(32, 32, 16).build().unwrap();
+    ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+}
+
+
+fn random_16k_state() -> State<HeisenbergSpin> {
+    State::<HeisenbergSpin>::rand_with_size(16_384, &mut thread_rng())
+}
+
+
+#[bench]
+fn exchange_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let exchange = cubic_exchange();
+    let state = random_16k_state();
+    b.iter(|| exchange.total_energy(&state))
+}
+
+
+#[bench]
+fn soa_exchange_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let exchange = PaddedExchange::new(&cubic_exchange());
+    let state = SoaState::<f64>::from_state(&random_16k_state());
+    b.iter(|| exchange.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn soa_f32_exchange_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let exchange = PaddedExchange::new(&cubic_exchange());
+    let state = SoaState::<f32>::from_state(&random_16k_state());
+    b.iter(|| exchange.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn zeeman_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let zeeman = ZeemanEnergy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = random_16k_state();
+    b.iter(|| zeeman.total_energy(&state))
+}
+
+
+#[bench]
+fn soa_zeeman_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let zeeman = ZeemanEnergy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = SoaState::<f64>::from_state(&random_16k_state());
+    b.iter(|| zeeman.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn soa_f32_zeeman_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let zeeman = ZeemanEnergy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = SoaState::<f32>::from_state(&random_16k_state());
+    b.iter(|| zeeman.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn anisotropy_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = random_16k_state();
+    b.iter(|| anisotropy.total_energy(&state))
+}
+
+
+#[bench]
+fn soa_anisotropy_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = SoaState::<f64>::from_state(&random_16k_state());
+    b.iter(|| anisotropy.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn soa_f32_anisotropy_energy_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::new([1.0, 1.0, 1.0]), 1.0);
+    let state = SoaState::<f32>::from_state(&random_16k_state());
+    b.iter(|| anisotropy.soa_total_energy(&state))
+}
+
+
+#[bench]
+fn magnetization_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let state = random_16k_state();
+    b.iter(|| state.magnetization())
+}
+
+
+#[bench]
+fn soa_magnetization_of_a_16k_heisenberg_state(b: &mut test::Bencher) {
+    let state = SoaState::<f64>::from_state(&random_16k_state());
+    b.iter(|| state.magnetization())
+}
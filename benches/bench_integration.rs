@@ -4,17 +4,20 @@
 extern crate test;
 
 use vegas_rs::state::{Spin, IsingSpin, HeisenbergSpin, State};
-use vegas_rs::energy::{Gauge, UniaxialAnisotropy};
-use vegas_rs::integrator::{Integrator, StateGenerator, MetropolisIntegrator};
+use vegas_rs::energy::{ExchangeEnergy, Gauge, UniaxialAnisotropy};
+use vegas_rs::field::CachedExchange;
+use vegas_rs::integrator::{CheckerboardIntegrator, Integrator, StateGenerator,
+                           MetropolisIntegrator};
+use vegas_rs::lattice::{LatticeBuilder, UnitCell};
 
 
 #[bench]
 fn integration_of_1k_heisenberg_spin_with_gauge(b: &mut test::Bencher) {
     let gauge = Gauge::new(1.0);
-    let mut integrator = MetropolisIntegrator::new(3.0);
+    let mut integrator = MetropolisIntegrator::new(3.0).unwrap();
     let mut state: State<HeisenbergSpin> = integrator.state(1_000);
     b.iter(|| {
-        state = integrator.step(&gauge, &state)
+        state = integrator.step(&gauge, &state).unwrap()
     })
 }
 
@@ -22,10 +25,10 @@ fn integration_of_1k_heisenberg_spin_with_gauge(b: &mut test::Bencher) {
 #[bench]
 fn integration_of_1k_ising_spin_with_gauge(b: &mut test::Bencher) {
     let gauge = Gauge::new(1.0);
-    let mut integrator = MetropolisIntegrator::new(3.0);
+    let mut integrator = MetropolisIntegrator::new(3.0).unwrap();
     let mut state: State<IsingSpin> = integrator.state(1_000);
     b.iter(|| {
-        state = integrator.step(&gauge, &state)
+        state = integrator.step(&gauge, &state).unwrap()
     })
 }
 
@@ -36,9 +39,66 @@ fn integration_of_1k_heisenberg_spin_with_compound_energy(b: &mut test::Bencher)
         Gauge::new(1.0),
         UniaxialAnisotropy::new(HeisenbergSpin::up(), 10.0)
     );
-    let mut integrator = MetropolisIntegrator::new(3.0);
+    let mut integrator = MetropolisIntegrator::new(3.0).unwrap();
     let mut state: State<HeisenbergSpin> = integrator.state(1_000);
     b.iter(|| {
-        state = integrator.step(&hamiltonian, &state)
+        state = integrator.step(&hamiltonian, &state).unwrap()
     })
-}
\ No newline at end of file
+}
+
+
+fn square_exchange() -> ExchangeEnergy {
+    let lattice = LatticeBuilder::new(UnitCell::square()).extent(128, 128, 1).build().unwrap();
+    ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+}
+
+
+#[bench]
+fn integration_of_16k_heisenberg_spin_with_exchange(b: &mut test::Bencher) {
+    let exchange = square_exchange();
+    let mut integrator = MetropolisIntegrator::new(1.0).unwrap();
+    let mut state: State<HeisenbergSpin> = integrator.state(128 * 128);
+    b.iter(|| {
+        state = integrator.step(&exchange, &state).unwrap()
+    })
+}
+
+
+#[bench]
+fn checkerboard_integration_of_16k_heisenberg_spin_with_exchange(b: &mut test::Bencher) {
+    let exchange = square_exchange();
+    let mut integrator = CheckerboardIntegrator::new(1.0, exchange.coloring()).unwrap();
+    let mut state: State<HeisenbergSpin> = integrator.state(128 * 128);
+    b.iter(|| {
+        state = integrator.step(&exchange, &state).unwrap()
+    })
+}
+
+
+fn shells_exchange() -> ExchangeEnergy {
+    let lattice = LatticeBuilder::new(UnitCell::cubic()).extent(16, 16, 16).shells(4).build()
+        .unwrap();
+    ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+}
+
+
+#[bench]
+fn integration_of_4k_heisenberg_spin_with_4_shells(b: &mut test::Bencher) {
+    let exchange = shells_exchange();
+    let mut integrator = MetropolisIntegrator::new(1.0).unwrap();
+    let mut state: State<HeisenbergSpin> = integrator.state(16 * 16 * 16);
+    b.iter(|| {
+        state = integrator.step(&exchange, &state).unwrap()
+    })
+}
+
+
+#[bench]
+fn cached_integration_of_4k_heisenberg_spin_with_4_shells(b: &mut test::Bencher) {
+    let exchange = CachedExchange::new(shells_exchange());
+    let mut integrator = MetropolisIntegrator::new(1.0).unwrap();
+    let mut state: State<HeisenbergSpin> = integrator.state(16 * 16 * 16);
+    b.iter(|| {
+        state = integrator.step(&exchange, &state).unwrap()
+    })
+}
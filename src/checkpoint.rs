@@ -0,0 +1,243 @@
+//! Checkpoints to resume simulations that got interrupted.
+//!
+//! A checkpoint holds everything a simulation needs to carry on as if it
+//! never stopped: the spins, the integrator with its temperature and random
+//! number generator, the schedule with its position, how far it got within
+//! the current temperature, and the stages measured so far. The Hamiltonian
+//! is not saved, callers keep whatever they need to rebuild it in the
+//! context of the checkpoint.
+//!
+//! Checkpoints are JSON files with a format version, they are written to a
+//! temporary file first and then moved over the old checkpoint, so a job
+//! killed while writing leaves the previous checkpoint untouched.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+
+use simulation::{Simulation, Stage};
+use state::Spin;
+
+
+/// Version of the checkpoint format, bumped whenever it changes.
+pub const VERSION: u32 = 3;
+
+
+/// Oldest version that can still be loaded. Versions 1 and 2 kept every
+/// sample of the observables, which are now accumulated instead.
+const OLDEST: u32 = 3;
+
+
+/// Sweeps between checkpoints within a temperature, by default.
+pub const INTERVAL: usize = 1000;
+
+
+#[derive(Debug)]
+pub enum CheckpointError {
+    Io(io::Error),
+    Format(serde_json::Error),
+    Version(u32),
+}
+
+
+impl fmt::Display for CheckpointError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CheckpointError::Io(ref e) => write!(f, "could not access the checkpoint: {}", e),
+            CheckpointError::Format(ref e) => write!(f, "malformed checkpoint: {}", e),
+            CheckpointError::Version(v) => write!(
+                f, "checkpoint format version {} is not supported, expected {}", v, VERSION),
+        }
+    }
+}
+
+
+impl StdError for CheckpointError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            CheckpointError::Io(ref e) => Some(e),
+            CheckpointError::Format(ref e) => Some(e),
+            CheckpointError::Version(_) => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for CheckpointError {
+    fn from(e: io::Error) -> Self {
+        CheckpointError::Io(e)
+    }
+}
+
+
+impl From<serde_json::Error> for CheckpointError {
+    fn from(e: serde_json::Error) -> Self {
+        CheckpointError::Format(e)
+    }
+}
+
+
+#[derive(Serialize)]
+struct Borrowed<'a, I: 'a, S: 'a + Spin> {
+    version: u32,
+    context: &'a Value,
+    simulation: &'a Simulation<I, S>,
+    stages: &'a [Stage],
+}
+
+
+/// Read a checkpoint without picking the types of its simulation, after
+/// checking its version.
+fn read<P: AsRef<Path>>(path: P) -> Result<Value, CheckpointError> {
+    let value: Value = serde_json::from_reader(File::open(path)?)?;
+    let version = value.get("version").and_then(|v| v.as_u64()).unwrap_or(0) as u32;
+    if !(OLDEST..=VERSION).contains(&version) {
+        return Err(CheckpointError::Version(version));
+    }
+    Ok(value)
+}
+
+
+/// Just the context of a checkpoint, to find out what kind of simulation it
+/// holds before loading it.
+pub fn context<P: AsRef<Path>>(path: P) -> Result<Value, CheckpointError> {
+    let mut value = read(path)?;
+    Ok(value.get_mut("context").map(Value::take).unwrap_or(Value::Null))
+}
+
+
+/// A simulation loaded from a checkpoint.
+#[derive(Deserialize)]
+pub struct Checkpoint<I, S: Spin> {
+    version: u32,
+    context: Value,
+    simulation: Simulation<I, S>,
+    stages: Vec<Stage>,
+}
+
+
+impl<I, S> Checkpoint<I, S>
+    where I: Serialize + DeserializeOwned,
+          S: Spin + Serialize + DeserializeOwned
+{
+    /// Save a simulation along with the stages it has gone through, and a
+    /// context to rebuild the rest of the run, replacing the file at `path`
+    /// atomically.
+    pub fn save<P: AsRef<Path>>(path: P, context: &Value, simulation: &Simulation<I, S>,
+                                stages: &[Stage]) -> Result<(), CheckpointError>
+    {
+        let path = path.as_ref();
+        let mut name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+        name.push(".tmp");
+        let temporary = path.with_file_name(name);
+        let checkpoint = Borrowed { version: VERSION, context, simulation, stages };
+        {
+            let mut file = File::create(&temporary)?;
+            serde_json::to_writer(&mut file, &checkpoint)?;
+            file.flush()?;
+            file.sync_all()?;
+        }
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, CheckpointError> {
+        Ok(serde_json::from_value(read(path)?)?)
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn context(&self) -> &Value {
+        &self.context
+    }
+
+    pub fn simulation(&self) -> &Simulation<I, S> {
+        &self.simulation
+    }
+
+    pub fn stages(&self) -> &[Stage] {
+        &self.stages
+    }
+
+    pub fn into_parts(self) -> (Value, Simulation<I, S>, Vec<Stage>) {
+        (self.context, self.simulation, self.stages)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Checkpoint, CheckpointError};
+    use energy::ExchangeEnergy;
+    use integrator::{MetropolisIntegrator, StateGenerator};
+    use lattice::{LatticeBuilder, UnitCell};
+    use observables::Observables;
+    use schedule::{Equilibration, Schedule};
+    use simulation::Simulation;
+    use state::{HeisenbergSpin, State};
+    use std::env;
+    use std::fs;
+
+    fn simulation() -> Simulation<MetropolisIntegrator, HeisenbergSpin> {
+        let schedule = Schedule::adaptive(2.0, 0.5, 0.05, 0.5).unwrap()
+            .with_sweeps(10, 50).unwrap()
+            .with_equilibration(Equilibration::Mser);
+        let mut integrator = MetropolisIntegrator::new(2.0).unwrap();
+        let state: State<HeisenbergSpin> = integrator.state(16);
+        Simulation::new(integrator, state, schedule)
+            .with_observables(Observables::new(16).with_series())
+    }
+
+    #[test]
+    fn resumed_runs_match_uninterrupted_ones() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let mut whole = Vec::new();
+        simulation().run(&exchange, |stage| whole.push(stage)).unwrap();
+
+        let path = env::temp_dir().join(format!("vegas-checkpoint-{}.json", ::std::process::id()));
+        let mut first = simulation();
+        let mut stages = Vec::new();
+        for _ in 0..2 {
+            stages.push(first.advance(&exchange).unwrap().unwrap());
+        }
+        let context = json!({"lattice": "square"});
+        Checkpoint::save(&path, &context, &first, &stages).unwrap();
+        assert_eq!(super::context(&path).unwrap(), context);
+        let checkpoint: Checkpoint<MetropolisIntegrator, HeisenbergSpin> =
+            Checkpoint::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(checkpoint.context(), &context);
+        assert_eq!(checkpoint.simulation().schedule().position(), 2);
+        let (_, mut resumed, mut stages) = checkpoint.into_parts();
+        resumed.run(&exchange, |stage| stages.push(stage)).unwrap();
+
+        assert_eq!(stages.len(), whole.len());
+        for (a, b) in stages.iter().zip(whole.iter()) {
+            assert_eq!(a.temp(), b.temp());
+            assert_eq!(a.discarded(), b.discarded());
+            assert_eq!(a.observables().energies(), b.observables().energies());
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let path = env::temp_dir().join(format!("vegas-version-{}.json", ::std::process::id()));
+        fs::write(&path, "{\"version\": 999}").unwrap();
+        let result: Result<Checkpoint<MetropolisIntegrator, HeisenbergSpin>, _> =
+            Checkpoint::load(&path);
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(CheckpointError::Version(999)) => (),
+            _ => panic!("expected a version error"),
+        }
+    }
+}
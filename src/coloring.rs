@@ -0,0 +1,84 @@
+//! Colorings of interaction graphs, so that sites of the same color can be
+//! updated at the same time.
+
+use serde::{Deserialize, Serialize};
+
+
+/// A partition of the sites into classes where no two sites of a class
+/// interact, a checkerboard on bipartite lattices.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Coloring {
+    colors: Vec<usize>,
+    classes: Vec<Vec<usize>>,
+}
+
+
+impl Coloring {
+    /// Color the `nsites` sites greedily in order, giving every site the
+    /// lowest color none of its `neighbors` has. Sites are never their own
+    /// neighbors, self interactions are ignored.
+    pub fn greedy<F, I>(nsites: usize, neighbors: F) -> Self
+        where F: Fn(usize) -> I,
+              I: IntoIterator<Item = usize>
+    {
+        let mut colors: Vec<Option<usize>> = vec![None; nsites];
+        let mut taken = Vec::new();
+        for site in 0..nsites {
+            taken.clear();
+            taken.extend(neighbors(site)
+                         .into_iter()
+                         .filter(|&nb| nb != site)
+                         .filter_map(|nb| colors[nb]));
+            let color = (0..).find(|c| !taken.contains(c)).unwrap();
+            colors[site] = Some(color);
+        }
+        let colors: Vec<usize> = colors.into_iter().map(|c| c.unwrap()).collect();
+        let ncolors = colors.iter().map(|c| c + 1).max().unwrap_or(0);
+        let mut classes = vec![Vec::new(); ncolors];
+        for (site, &color) in colors.iter().enumerate() {
+            classes[color].push(site);
+        }
+        Self { colors, classes }
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn ncolors(&self) -> usize {
+        self.classes.len()
+    }
+
+    pub fn color(&self, site: usize) -> usize {
+        self.colors[site]
+    }
+
+    /// The sites of every color, in increasing order.
+    pub fn classes(&self) -> &[Vec<usize>] {
+        &self.classes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Coloring;
+
+    #[test]
+    fn rings_get_a_checkerboard_when_even() {
+        let ring = |n: usize| move |i: usize| vec![(i + 1) % n, (i + n - 1) % n];
+        let even = Coloring::greedy(6, ring(6));
+        assert_eq!(even.ncolors(), 2);
+        assert_eq!(even.classes()[0], vec![0, 2, 4]);
+        let odd = Coloring::greedy(5, ring(5));
+        assert_eq!(odd.ncolors(), 3);
+        for i in 0..5 {
+            assert_ne!(odd.color(i), odd.color((i + 1) % 5));
+        }
+        assert_eq!(Coloring::greedy(1, |i| vec![i]).ncolors(), 1);
+    }
+}
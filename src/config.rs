@@ -0,0 +1,810 @@
+//! Declarative description of a whole simulation, read from TOML or JSON.
+//!
+//! A config file names the lattice, either a lattice file or one of the
+//! built-in generators, the spin model, the terms of the Hamiltonian, the
+//! integrator and its seed, the temperature schedule, the observables and
+//! where the output goes. Running the same file twice gives the same
+//! results.
+//!
+//! ```
+//! use vegas_rs::config::Config;
+//!
+//! let config = Config::from_toml(r#"
+//!     seed = 42
+//!     spin = "heisenberg"
+//!
+//!     [lattice]
+//!     cell = "square"
+//!     extent = [8, 8, 1]
+//!
+//!     [[hamiltonian]]
+//!     term = "exchange"
+//!     coupling = 1.0
+//!
+//!     [[hamiltonian]]
+//!     term = "zeeman"
+//!     field = 0.1
+//!     direction = [0.0, 0.0, 1.0]
+//!
+//!     [schedule]
+//!     kind = "linear"
+//!     start = 2.0
+//!     stop = 0.5
+//!     step = 0.1
+//!     measurement = 500
+//!
+//!     [observables]
+//!     topological_charge = true
+//! "#).unwrap();
+//! assert_eq!(config.lattice().unwrap().sites().len(), 64);
+//! ```
+//!
+//! Relative paths in a file loaded with `Config::load` are taken relative
+//! to the directory of the file.
+
+use std::collections::BTreeMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use toml;
+use vegas_lattice::{Axis, Lattice};
+
+use checkpoint;
+use energy::{EnergyComponent, EnergySum, ExchangeEnergy, Gauge, Labeled, UniaxialAnisotropy,
+             ZeemanEnergy};
+use integrator::{CheckerboardIntegrator, MetropolisIntegrator, StateGenerator};
+use lattice::{Geometry, LatticeBuilder, UnitCell};
+use observables::{Observables, OrderParameter};
+use output;
+use schedule::{Equilibration, Schedule};
+use snapshot::{self, Format, Snapshots};
+use state::{OrientableSpin, Spin, State};
+use topology::Triangulation;
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(String),
+    Invalid(String),
+}
+
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::Io(ref e) => write!(f, "could not read the config: {}", e),
+            ConfigError::Parse(ref msg) => write!(f, "malformed config: {}", msg),
+            ConfigError::Invalid(ref msg) => write!(f, "invalid config: {}", msg),
+        }
+    }
+}
+
+
+impl StdError for ConfigError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            ConfigError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for ConfigError {
+    fn from(e: io::Error) -> Self {
+        ConfigError::Io(e)
+    }
+}
+
+
+fn invalid<T>(msg: String) -> Result<T, ConfigError> {
+    Err(ConfigError::Invalid(msg))
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SpinModel {
+    Ising,
+    #[default]
+    Heisenberg,
+}
+
+
+/// A lattice file, or a unit cell repeated along the axes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct LatticeConfig {
+    file: Option<String>,
+    cell: Option<String>,
+    extent: Option<[usize; 3]>,
+    #[serde(default)]
+    open: Vec<String>,
+    shells: Option<usize>,
+}
+
+
+fn z_axis() -> [f64; 3] {
+    [0.0, 0.0, 1.0]
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "term", rename_all = "lowercase", deny_unknown_fields)]
+enum TermConfig {
+    /// A coupling for every bond, or one for every bond tag, like `nn2`.
+    Exchange {
+        coupling: Option<f64>,
+        #[serde(default)]
+        couplings: BTreeMap<String, f64>,
+        label: Option<String>,
+    },
+    Anisotropy {
+        strength: f64,
+        #[serde(default = "z_axis")]
+        axis: [f64; 3],
+        label: Option<String>,
+    },
+    Zeeman {
+        field: f64,
+        #[serde(default = "z_axis")]
+        direction: [f64; 3],
+        label: Option<String>,
+    },
+    Gauge {
+        value: f64,
+        label: Option<String>,
+    },
+}
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IntegratorKind {
+    #[default]
+    Metropolis,
+    /// Metropolis updating the sites of every color class in parallel.
+    Checkerboard,
+}
+
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct IntegratorConfig {
+    #[serde(default)]
+    kind: IntegratorKind,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RampKind {
+    Linear,
+    Geometric,
+    Explicit,
+    Adaptive,
+}
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum EquilibrationKind {
+    Fixed,
+    Mser,
+}
+
+
+fn default_measurement() -> usize {
+    1000
+}
+
+
+fn default_equilibration() -> EquilibrationKind {
+    EquilibrationKind::Mser
+}
+
+
+/// The fields needed depend on the kind of schedule, they get checked on
+/// validation.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleConfig {
+    kind: RampKind,
+    start: Option<f64>,
+    stop: Option<f64>,
+    step: Option<f64>,
+    ratio: Option<f64>,
+    temps: Option<Vec<f64>>,
+    min_step: Option<f64>,
+    max_step: Option<f64>,
+    #[serde(default)]
+    thermalization: usize,
+    #[serde(default = "default_measurement")]
+    measurement: usize,
+    #[serde(default = "default_equilibration")]
+    equilibration: EquilibrationKind,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WaveVector {
+    name: String,
+    q: [f64; 3],
+}
+
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ObservablesConfig {
+    #[serde(default)]
+    sublattices: bool,
+    #[serde(default)]
+    topological_charge: bool,
+    #[serde(default)]
+    wave_vectors: Vec<WaveVector>,
+}
+
+
+fn default_prefix() -> String {
+    "snapshot".to_string()
+}
+
+
+fn default_format() -> String {
+    "vtp".to_string()
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SnapshotConfig {
+    every: usize,
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    #[serde(default = "default_format")]
+    format: String,
+}
+
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputConfig {
+    results: Option<String>,
+    /// After the extension of `results` if missing.
+    format: Option<output::Format>,
+    checkpoint: Option<String>,
+    /// Sweeps between checkpoints within a temperature, 0 for none.
+    checkpoint_every: Option<usize>,
+    snapshots: Option<SnapshotConfig>,
+}
+
+
+fn default_initial() -> String {
+    "random".to_string()
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    seed: u64,
+    #[serde(default)]
+    spin: SpinModel,
+    /// Either `random`, `up`, `down` or the path to a snapshot.
+    #[serde(default = "default_initial")]
+    initial: String,
+    lattice: LatticeConfig,
+    hamiltonian: Vec<TermConfig>,
+    #[serde(default)]
+    integrator: IntegratorConfig,
+    schedule: ScheduleConfig,
+    #[serde(default)]
+    observables: ObservablesConfig,
+    #[serde(default)]
+    output: OutputConfig,
+}
+
+
+fn cell(name: &str) -> Option<UnitCell> {
+    match name {
+        "chain" => Some(UnitCell::chain()),
+        "square" => Some(UnitCell::square()),
+        "triangular" => Some(UnitCell::triangular()),
+        "honeycomb" => Some(UnitCell::honeycomb()),
+        "kagome" => Some(UnitCell::kagome()),
+        "cubic" => Some(UnitCell::cubic()),
+        "bcc" => Some(UnitCell::bcc()),
+        "fcc" => Some(UnitCell::fcc()),
+        "pyrochlore" => Some(UnitCell::pyrochlore()),
+        _ => None,
+    }
+}
+
+
+fn axis(name: &str) -> Option<Axis> {
+    match name {
+        "x" => Some(Axis::X),
+        "y" => Some(Axis::Y),
+        "z" => Some(Axis::Z),
+        _ => None,
+    }
+}
+
+
+fn is_zero(v: &[f64; 3]) -> bool {
+    v.iter().all(|x| *x == 0.0)
+}
+
+
+/// Ising spins only point along z, so their terms only take z directions.
+fn is_along_z(v: &[f64; 3]) -> bool {
+    v[0] == 0.0 && v[1] == 0.0
+}
+
+
+impl Config {
+    pub fn from_toml(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = toml::from_str(text).map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, ConfigError> {
+        let config: Self = serde_json::from_str(text)
+            .map_err(|e| ConfigError::Parse(e.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Read a config file, JSON if the extension says so and TOML
+    /// otherwise.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)?;
+        let json = path.extension().is_some_and(|e| e == "json");
+        let mut config = if json { Self::from_json(&text)? } else { Self::from_toml(&text)? };
+        if let Some(base) = path.parent() {
+            config.resolve(base);
+        }
+        Ok(config)
+    }
+
+    /// Make the paths in the config relative to `base`.
+    fn resolve(&mut self, base: &Path) {
+        let join = |p: &mut String| *p = base.join(&*p).to_string_lossy().into_owned();
+        if let Some(ref mut file) = self.lattice.file {
+            join(file);
+        }
+        if !["random", "up", "down"].contains(&self.initial.as_str()) {
+            join(&mut self.initial);
+        }
+        for path in self.output.results.iter_mut().chain(self.output.checkpoint.iter_mut()) {
+            join(path);
+        }
+        if let Some(ref mut snapshots) = self.output.snapshots {
+            join(&mut snapshots.prefix);
+        }
+    }
+
+    /// Check everything that can be checked without reading other files.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let lattice = &self.lattice;
+        match (&lattice.file, &lattice.cell) {
+            (Some(_), Some(_)) => return invalid(
+                "lattice: give either a `file` or a `cell`, not both".to_string()),
+            (None, None) => return invalid(
+                "lattice: give either a `file` or a `cell`".to_string()),
+            (Some(_), None) => if lattice.extent.is_some() ||
+                                  lattice.shells.is_some() ||
+                                  !lattice.open.is_empty() {
+                return invalid(
+                    "lattice: `extent`, `open` and `shells` only apply to a `cell`".to_string());
+            },
+            (None, Some(name)) => if cell(name).is_none() {
+                return invalid(format!(
+                    "lattice: unknown cell `{}`, expected chain, square, triangular, \
+                     honeycomb, kagome, cubic, bcc, fcc or pyrochlore", name));
+            },
+        }
+        if lattice.extent.is_some_and(|e| e.contains(&0)) {
+            return invalid("lattice: every `extent` must be at least 1".to_string());
+        }
+        if lattice.shells == Some(0) {
+            return invalid("lattice: `shells` must be at least 1".to_string());
+        }
+        if let Some(name) = lattice.open.iter().find(|a| axis(a).is_none()) {
+            return invalid(format!("lattice: unknown axis `{}` in `open`, use x, y or z", name));
+        }
+
+        if self.hamiltonian.is_empty() {
+            return invalid("hamiltonian: there should be at least one term".to_string());
+        }
+        for (i, term) in self.hamiltonian.iter().enumerate() {
+            match *term {
+                TermConfig::Exchange { coupling: None, ref couplings, .. }
+                    if couplings.is_empty() => return invalid(format!(
+                        "hamiltonian term {}: an exchange needs a `coupling` or `couplings`",
+                        i + 1)),
+                TermConfig::Anisotropy { ref axis, .. } if is_zero(axis) => return invalid(
+                    format!("hamiltonian term {}: the anisotropy `axis` is zero", i + 1)),
+                TermConfig::Zeeman { ref direction, .. } if is_zero(direction) => return invalid(
+                    format!("hamiltonian term {}: the field `direction` is zero", i + 1)),
+                TermConfig::Anisotropy { ref axis, .. }
+                    if self.spin == SpinModel::Ising && !is_along_z(axis) => return invalid(
+                        format!("hamiltonian term {}: Ising spins need an `axis` along z", i + 1)),
+                TermConfig::Zeeman { ref direction, .. }
+                    if self.spin == SpinModel::Ising && !is_along_z(direction) => return invalid(
+                        format!("hamiltonian term {}: Ising spins need a `direction` along z",
+                                i + 1)),
+                _ => (),
+            }
+        }
+
+        let schedule = &self.schedule;
+        let require = |value: Option<f64>, name: &str| match value {
+            Some(value) if value > 0.0 && value.is_finite() => Ok(value),
+            Some(_) => invalid(format!("schedule: `{}` must be positive", name)),
+            None => invalid(format!(
+                "schedule: a {:?} schedule needs `{}`", schedule.kind, name).to_lowercase()),
+        };
+        match schedule.kind {
+            RampKind::Linear => {
+                require(schedule.start, "start")?;
+                require(schedule.stop, "stop")?;
+                require(schedule.step, "step")?;
+            },
+            RampKind::Geometric => {
+                require(schedule.start, "start")?;
+                require(schedule.stop, "stop")?;
+                if require(schedule.ratio, "ratio")? == 1.0 {
+                    return invalid("schedule: `ratio` cannot be 1".to_string());
+                }
+            },
+            RampKind::Explicit => match schedule.temps {
+                Some(ref temps) if !temps.is_empty() => {
+                    if temps.iter().any(|t| *t <= 0.0 || t.is_nan()) {
+                        return invalid("schedule: `temps` must be positive".to_string());
+                    }
+                },
+                _ => return invalid("schedule: an explicit schedule needs `temps`".to_string()),
+            },
+            RampKind::Adaptive => {
+                require(schedule.start, "start")?;
+                require(schedule.stop, "stop")?;
+                if require(schedule.min_step, "min_step")? >
+                   require(schedule.max_step, "max_step")? {
+                    return invalid(
+                        "schedule: `min_step` is larger than `max_step`".to_string());
+                }
+            },
+        }
+        if schedule.measurement == 0 {
+            return invalid("schedule: `measurement` must be at least 1".to_string());
+        }
+
+        if let Some(ref snapshots) = self.output.snapshots {
+            if Format::from_extension(&snapshots.format).is_none() {
+                return invalid(format!(
+                    "output.snapshots: unknown format `{}`, use xyz, vtk, vtp or csv",
+                    snapshots.format));
+            }
+        }
+        if !["random", "up", "down"].contains(&self.initial.as_str()) &&
+           Format::from_path(&self.initial).is_err() {
+            return invalid(format!(
+                "initial: `{}` is neither random, up, down nor a snapshot", self.initial));
+        }
+        Ok(())
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn spin(&self) -> SpinModel {
+        self.spin
+    }
+
+    /// Where the results go, standard output if `None`.
+    pub fn results(&self) -> Option<&str> {
+        self.output.results.as_deref()
+    }
+
+    pub fn results_format(&self) -> output::Format {
+        match (self.output.format, self.results()) {
+            (Some(format), _) => format,
+            (None, Some(path)) => output::Format::from_path(path),
+            (None, None) => output::Format::default(),
+        }
+    }
+
+    pub fn checkpoint(&self) -> Option<&str> {
+        self.output.checkpoint.as_deref()
+    }
+
+    /// Sweeps between checkpoints within a temperature, on top of the one
+    /// after every temperature.
+    pub fn checkpoint_every(&self) -> usize {
+        self.output.checkpoint_every.unwrap_or(checkpoint::INTERVAL)
+    }
+
+    pub fn snapshots(&self) -> Option<Snapshots> {
+        self.output.snapshots.as_ref().map(|s| {
+            let format = Format::from_extension(&s.format).expect("validated format");
+            Snapshots::new(&s.prefix, format, s.every)
+        })
+    }
+
+    /// Read or build the lattice.
+    pub fn lattice(&self) -> Result<Lattice, ConfigError> {
+        let lattice = &self.lattice;
+        if let Some(ref file) = lattice.file {
+            let data = fs::read_to_string(file)?;
+            return data.parse()
+                .map_err(|e| ConfigError::Parse(format!("lattice file {}: {}", file, e)));
+        }
+        let name = lattice.cell.as_ref().expect("validated lattice");
+        let extent = lattice.extent.unwrap_or([1, 1, 1]);
+        let mut builder = LatticeBuilder::new(cell(name).expect("validated cell"))
+            .extent(extent[0], extent[1], extent[2])
+            .shells(lattice.shells.unwrap_or(1));
+        for name in lattice.open.iter() {
+            builder = builder.open_along(axis(name).expect("validated axis"));
+        }
+        Ok(builder.build().expect("validated lattice"))
+    }
+
+    /// The terms of the Hamiltonian as written in the config.
+    pub fn terms(&self) -> Value {
+        serde_json::to_value(&self.hamiltonian).expect("terms serialize")
+    }
+
+    pub fn hamiltonian<S>(&self, lattice: &Lattice) -> Result<EnergySum<S>, ConfigError>
+        where S: OrientableSpin + Send + Sync + 'static
+    {
+        let mut hamiltonian = EnergySum::new();
+        for term in self.hamiltonian.iter() {
+            hamiltonian = match *term {
+                TermConfig::Exchange { coupling, ref couplings, ref label } => {
+                    let exchange = ExchangeEnergy::from_lattice(lattice, |bond| {
+                        bond.tags()
+                            .iter()
+                            .filter_map(|tag| couplings.get(tag))
+                            .next()
+                            .cloned()
+                            .or(coupling)
+                            .unwrap_or(0.0)
+                    }).map_err(|e| ConfigError::Invalid(format!("exchange: {}", e)))?;
+                    with_label(hamiltonian, label, exchange)
+                },
+                TermConfig::Anisotropy { strength, axis, ref label } => with_label(
+                    hamiltonian, label, UniaxialAnisotropy::new(S::along(axis), strength)),
+                TermConfig::Zeeman { field, direction, ref label } => with_label(
+                    hamiltonian, label, ZeemanEnergy::new(S::along(direction), field)),
+                TermConfig::Gauge { value, ref label } => with_label(
+                    hamiltonian, label, Gauge::new(value)),
+            };
+        }
+        Ok(hamiltonian)
+    }
+
+    pub fn integrator_kind(&self) -> IntegratorKind {
+        self.integrator.kind
+    }
+
+    /// The Metropolis integrator, whatever the kind in the config.
+    pub fn integrator(&self) -> MetropolisIntegrator {
+        MetropolisIntegrator::new(1.0).expect("positive temperature").with_seed(self.seed)
+    }
+
+    /// The checkerboard integrator, with the sites colored after every bond
+    /// of the lattice.
+    pub fn checkerboard_integrator(&self, lattice: &Lattice)
+        -> Result<CheckerboardIntegrator, ConfigError>
+    {
+        let bonds = ExchangeEnergy::from_lattice(lattice, |_| 1.0)
+            .map_err(|e| ConfigError::Invalid(format!("lattice: {}", e)))?;
+        Ok(CheckerboardIntegrator::new(1.0, bonds.coloring())
+           .expect("positive temperature")
+           .with_seed(self.seed))
+    }
+
+    pub fn schedule(&self) -> Schedule {
+        let s = &self.schedule;
+        let value = |v: Option<f64>| v.expect("validated schedule");
+        let schedule = match s.kind {
+            RampKind::Linear => Schedule::linear(value(s.start), value(s.stop), value(s.step)),
+            RampKind::Geometric => {
+                Schedule::geometric(value(s.start), value(s.stop), value(s.ratio))
+            },
+            RampKind::Explicit => Schedule::explicit(s.temps.clone().unwrap_or_default()),
+            RampKind::Adaptive => Schedule::adaptive(
+                value(s.start), value(s.stop), value(s.min_step), value(s.max_step)),
+        }.expect("validated schedule");
+        let equilibration = match s.equilibration {
+            EquilibrationKind::Fixed => Equilibration::Fixed,
+            EquilibrationKind::Mser => Equilibration::Mser,
+        };
+        schedule.with_sweeps(s.thermalization, s.measurement)
+            .expect("validated schedule")
+            .with_equilibration(equilibration)
+    }
+
+    pub fn observables(&self, geometry: &Geometry) -> Observables {
+        let config = &self.observables;
+        let mut orders = Vec::new();
+        if config.sublattices {
+            orders.extend(OrderParameter::sublattices(geometry));
+        }
+        for wave in config.wave_vectors.iter() {
+            orders.push(OrderParameter::with_wave_vector(&wave.name, geometry, wave.q));
+        }
+        let observables = Observables::new(geometry.len()).with_order_parameters(orders);
+        if config.topological_charge {
+            observables.with_topological_charge(Triangulation::from_geometry(geometry))
+        } else {
+            observables
+        }
+    }
+
+    /// The state to start from, random states are drawn with the
+    /// integrator.
+    pub fn initial_state<S, G>(&self, generator: &mut G, len: usize)
+        -> Result<State<S>, ConfigError>
+        where S: OrientableSpin + Clone,
+              G: StateGenerator<S>
+    {
+        match self.initial.as_str() {
+            "random" => Ok(generator.state(len)),
+            "up" => Ok(State::up_with_size(len)),
+            "down" => Ok(State::down_with_size(len)),
+            path => {
+                let snapshot = snapshot::load(path)
+                    .map_err(|e| ConfigError::Invalid(format!("initial: {}", e)))?;
+                if snapshot.state().len() != len {
+                    return invalid(format!(
+                        "initial: the snapshot has {} spins but the lattice has {} sites",
+                        snapshot.state().len(), len));
+                }
+                let mut state = State::up_with_size(len);
+                for (i, spin) in snapshot.state().spins().iter().enumerate() {
+                    state.set_at(i, S::along(spin.magnetization()));
+                }
+                Ok(state)
+            },
+        }
+    }
+}
+
+
+fn with_label<S, U>(hamiltonian: EnergySum<S>, label: &Option<String>, term: U) -> EnergySum<S>
+    where S: Spin + Send + Sync + 'static,
+          U: EnergyComponent<S> + Send + Sync + 'static
+{
+    match *label {
+        Some(ref label) => hamiltonian.with_term(Labeled::new(label, term)),
+        None => hamiltonian.with_term(term),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, ConfigError, SpinModel};
+    use energy::EnergyComponent;
+    use integrator::{Integrator, MetropolisIntegrator};
+    use lattice::Geometry;
+    use state::{IsingSpin, State};
+
+    const BASE: &str = r#"
+        seed = 7
+        spin = "ising"
+
+        [lattice]
+        cell = "square"
+        extent = [4, 4, 1]
+        shells = 2
+
+        [[hamiltonian]]
+        term = "exchange"
+        couplings = { nn1 = 1.0, nn2 = -0.5 }
+        label = "j"
+
+        [[hamiltonian]]
+        term = "anisotropy"
+        strength = 0.1
+
+        [schedule]
+        kind = "explicit"
+        temps = [2.0, 1.0]
+        measurement = 10
+
+        [observables]
+        sublattices = true
+        wave_vectors = [{ name = "stripes", q = [3.141592653589793, 0.0, 0.0] }]
+    "#;
+
+    fn invalid(text: &str) -> String {
+        match Config::from_toml(text) {
+            Err(ConfigError::Invalid(msg)) | Err(ConfigError::Parse(msg)) => msg,
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("the config should be rejected"),
+        }
+    }
+
+    #[test]
+    fn configs_build_every_piece() {
+        let config = Config::from_toml(BASE).unwrap();
+        assert_eq!(config.spin(), SpinModel::Ising);
+        let lattice = config.lattice().unwrap();
+        let hamiltonian = config.hamiltonian::<IsingSpin>(&lattice).unwrap();
+        let state = State::<IsingSpin>::up_with_size(16);
+        // 32 first neighbor bonds and 32 second neighbor ones.
+        let breakdown = hamiltonian.breakdown(&state);
+        assert_eq!(breakdown[0].0, "j");
+        assert!((breakdown[0].1 - (-32.0 + 16.0)).abs() < 1e-12);
+        assert_eq!(breakdown[1].0, "anisotropy");
+        let observables = config.observables(&Geometry::from_lattice(&lattice));
+        assert_eq!(observables.order_parameters().len(), 2);
+        assert_eq!(config.schedule().collect::<Vec<_>>(), vec![2.0, 1.0]);
+        assert_eq!(config.terms()[0]["couplings"]["nn2"], -0.5);
+        assert_eq!(config.results_format(), ::output::Format::Text);
+        assert_eq!(config.checkpoint_every(), ::checkpoint::INTERVAL);
+        let json = ::serde_json::to_string(&config).unwrap();
+        assert_eq!(Config::from_json(&json).unwrap().seed(), 7);
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let config = Config::from_toml(BASE).unwrap();
+        let lattice = config.lattice().unwrap();
+        let hamiltonian = config.hamiltonian::<IsingSpin>(&lattice).unwrap();
+        let run = || {
+            let mut integrator: MetropolisIntegrator = config.integrator();
+            let mut state: State<IsingSpin> = config.initial_state(&mut integrator, 16).unwrap();
+            for _ in 0..10 {
+                state = integrator.step(&hamiltonian, &state).unwrap();
+            }
+            hamiltonian.total_energy(&state)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn invalid_configs_are_explained() {
+        assert!(invalid(&BASE.replace("cell = \"square\"", "cell = \"hexagon\""))
+                .contains("unknown cell `hexagon`"));
+        assert!(invalid(&BASE.replace("temps = [2.0, 1.0]", ""))
+                .contains("needs `temps`"));
+        assert!(invalid(&BASE.replace("kind = \"explicit\"", "kind = \"linear\""))
+                .contains("needs `start`"));
+        assert!(invalid(&BASE.replace("strength = 0.1", "strength = 0.1\naxis = [0.0, 0.0, 0.0]"))
+                .contains("`axis` is zero"));
+        assert!(invalid(&BASE.replace("strength = 0.1", "strength = 0.1\naxis = [1.0, 0.0, 0.0]"))
+                .contains("`axis` along z"));
+        let tilted = BASE.replace("strength = 0.1", "strength = 0.1\naxis = [0.0, 0.5, 1.0]");
+        assert!(Config::from_toml(&tilted.replace("\"ising\"", "\"heisenberg\"")).is_ok());
+        let field = r#"
+            [[hamiltonian]]
+            term = "zeeman"
+            field = 1.0
+            direction = [0.0, 1.0, 0.0]
+        "#;
+        assert!(invalid(&BASE.replace("[schedule]", &format!("{}\n[schedule]", field)))
+                .contains("`direction` along z"));
+        assert!(invalid(&BASE.replace("seed = 7", "seed = 7\ncolor = \"red\""))
+                .contains("unknown field `color`"));
+        assert!(invalid(&BASE.replace("term = \"anisotropy\"", "term = \"dipolar\""))
+                .contains("dipolar"));
+        assert!(invalid(&BASE.replace("measurement = 10", "measurement = 0"))
+                .contains("`measurement`"));
+    }
+}
@@ -0,0 +1,403 @@
+//! Spin-spin correlation functions and the static structure factor.
+//!
+//! Both measurements take snapshots of a state and average them, site
+//! positions come from a `Geometry`.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use rustfft::{Fft, FftPlanner};
+use rustfft::num_complex::Complex;
+
+use error::{invalid, VegasError};
+use lattice::Geometry;
+use state::{Spin, State};
+
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+
+/// Real space correlation function `<S_i . S_j>`, binned by the distance
+/// between sites.
+#[derive(Clone, Debug)]
+pub struct CorrelationFunction {
+    geometry: Geometry,
+    bin_width: f64,
+    sums: Vec<f64>,
+    counts: Vec<usize>,
+    samples: usize,
+}
+
+
+impl CorrelationFunction {
+    /// New up a correlation function with bins `bin_width` wide, which
+    /// must be positive.
+    pub fn new(geometry: &Geometry, bin_width: f64) -> Result<Self, VegasError> {
+        if !(bin_width > 0.0 && bin_width.is_finite()) {
+            return invalid(format!("the bin width must be positive, got {}", bin_width));
+        }
+        let mut counts = Vec::new();
+        for i in 0..geometry.len() {
+            for j in (i + 1)..geometry.len() {
+                let bin = (geometry.distance(i, j) / bin_width) as usize;
+                if bin >= counts.len() {
+                    counts.resize(bin + 1, 0);
+                }
+                counts[bin] += 1;
+            }
+        }
+        Ok(Self {
+            geometry: geometry.clone(),
+            bin_width,
+            sums: vec![0.0; counts.len()],
+            counts,
+            samples: 0,
+        })
+    }
+
+    /// Add a snapshot of a state.
+    pub fn push<S: Spin>(&mut self, state: &State<S>) {
+        let spins: Vec<[f64; 3]> = state.spins().iter().map(|s| s.magnetization()).collect();
+        for i in 0..spins.len() {
+            for j in (i + 1)..spins.len() {
+                let bin = (self.geometry.distance(i, j) / self.bin_width) as usize;
+                self.sums[bin] += dot(spins[i], spins[j]);
+            }
+        }
+        self.samples += 1;
+    }
+
+    /// Number of snapshots so far.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The mean correlation for every non empty bin, along with the
+    /// distance at the center of the bin.
+    pub fn values(&self) -> Vec<(f64, f64)> {
+        self.sums.iter()
+            .zip(self.counts.iter())
+            .enumerate()
+            .filter(|&(_, (_, &count))| count > 0)
+            .map(|(bin, (sum, &count))| {
+                ((bin as f64 + 0.5) * self.bin_width,
+                 sum / (count * self.samples) as f64)
+            })
+            .collect()
+    }
+}
+
+
+/// The grid used to compute the structure factor with a fast Fourier
+/// transform, along with the grid index of every requested wave vector.
+struct FftGrid {
+    shape: [usize; 3],
+    sites: Vec<usize>,
+    qindices: Vec<usize>,
+    plans: Vec<Arc<dyn Fft<f64>>>,
+}
+
+
+impl FftGrid {
+    fn new(geometry: &Geometry, qpoints: &[[f64; 3]]) -> Option<Self> {
+        let (shape, indices) = geometry.grid()?;
+        let size = geometry.size();
+        let mut qindices = Vec::with_capacity(qpoints.len());
+        for q in qpoints {
+            let mut index = [0; 3];
+            for k in 0..3 {
+                if shape[k] == 1 {
+                    continue;
+                }
+                let m = q[k] * size[k] / (2.0 * PI);
+                if (m - m.round()).abs() > 1e-6 {
+                    return None;
+                }
+                index[k] = (m.round() as i64).rem_euclid(shape[k] as i64) as usize;
+            }
+            qindices.push(flat(shape, index));
+        }
+        let mut planner = FftPlanner::new();
+        Some(Self {
+            shape,
+            sites: indices.into_iter().map(|index| flat(shape, index)).collect(),
+            qindices,
+            plans: shape.iter().map(|&n| planner.plan_fft_forward(n)).collect(),
+        })
+    }
+
+    /// Transform a field in place, one axis at a time.
+    fn transform(&self, data: &mut [Complex<f64>]) {
+        let [nx, ny, nz] = self.shape;
+        let strides = [ny * nz, nz, 1];
+        for (axis, &stride) in strides.iter().enumerate() {
+            let n = self.shape[axis];
+            if n == 1 {
+                continue;
+            }
+            let mut line = vec![Complex::new(0.0, 0.0); n];
+            for start in 0..nx * ny * nz {
+                // Only start lines at sites whose index along this axis is 0.
+                if (start / stride) % n != 0 {
+                    continue;
+                }
+                for (m, value) in line.iter_mut().enumerate() {
+                    *value = data[start + m * stride];
+                }
+                self.plans[axis].process(&mut line);
+                for (m, value) in line.iter().enumerate() {
+                    data[start + m * stride] = *value;
+                }
+            }
+        }
+    }
+}
+
+
+fn flat(shape: [usize; 3], index: [usize; 3]) -> usize {
+    (index[0] * shape[1] + index[1]) * shape[2] + index[2]
+}
+
+
+/// Static structure factor `S(q) = 1/N <|sum_i S_i exp(i q . r_i)|^2>` on
+/// a set of wave vectors.
+///
+/// When the sites fill a periodic grid, like Bravais lattices in their
+/// conventional cell, and every wave vector is commensurate with it, the
+/// sums are computed with a fast Fourier transform, otherwise they are
+/// computed directly.
+pub struct StructureFactor {
+    geometry: Geometry,
+    qpoints: Vec<[f64; 3]>,
+    sums: Vec<f64>,
+    samples: usize,
+    grid: Option<FftGrid>,
+}
+
+
+impl StructureFactor {
+    pub fn new(geometry: &Geometry, qpoints: Vec<[f64; 3]>) -> Self {
+        let grid = FftGrid::new(geometry, &qpoints);
+        Self {
+            grid,
+            ..Self::direct(geometry, qpoints)
+        }
+    }
+
+    /// Always compute the sums directly, whatever the geometry.
+    pub fn direct(geometry: &Geometry, qpoints: Vec<[f64; 3]>) -> Self {
+        Self {
+            geometry: geometry.clone(),
+            sums: vec![0.0; qpoints.len()],
+            qpoints,
+            samples: 0,
+            grid: None,
+        }
+    }
+
+    /// Whether the sums are computed with a fast Fourier transform.
+    pub fn uses_fft(&self) -> bool {
+        self.grid.is_some()
+    }
+
+    /// Add a snapshot of a state.
+    pub fn push<S: Spin>(&mut self, state: &State<S>) {
+        let spins: Vec<[f64; 3]> = state.spins().iter().map(|s| s.magnetization()).collect();
+        let n = spins.len() as f64;
+        match self.grid {
+            Some(ref grid) => {
+                for c in 0..3 {
+                    let mut data = vec![Complex::new(0.0, 0.0); spins.len()];
+                    for (spin, &site) in spins.iter().zip(grid.sites.iter()) {
+                        data[site] = Complex::new(spin[c], 0.0);
+                    }
+                    grid.transform(&mut data);
+                    for (sum, &q) in self.sums.iter_mut().zip(grid.qindices.iter()) {
+                        *sum += data[q].norm_sqr() / n;
+                    }
+                }
+            },
+            None => {
+                let positions = self.geometry.positions();
+                for (sum, &q) in self.sums.iter_mut().zip(self.qpoints.iter()) {
+                    let mut amplitude = [Complex::new(0.0, 0.0); 3];
+                    for (spin, &r) in spins.iter().zip(positions.iter()) {
+                        let phase = Complex::from_polar(1.0, dot(q, r));
+                        for c in 0..3 {
+                            amplitude[c] += phase * spin[c];
+                        }
+                    }
+                    *sum += amplitude.iter().map(|a| a.norm_sqr()).sum::<f64>() / n;
+                }
+            },
+        }
+        self.samples += 1;
+    }
+
+    /// Number of snapshots so far.
+    pub fn samples(&self) -> usize {
+        self.samples
+    }
+
+    /// The mean structure factor at every wave vector.
+    pub fn values(&self) -> Vec<([f64; 3], f64)> {
+        self.qpoints.iter()
+            .zip(self.sums.iter())
+            .map(|(&q, sum)| (q, sum / self.samples as f64))
+            .collect()
+    }
+}
+
+
+/// Wave vectors along straight segments joining the given corners, with
+/// `steps` points per segment.
+pub fn q_path(corners: &[[f64; 3]], steps: usize) -> Vec<[f64; 3]> {
+    let mut path = Vec::new();
+    for pair in corners.windows(2) {
+        let (a, b) = (pair[0], pair[1]);
+        for step in 0..steps {
+            let t = step as f64 / steps as f64;
+            path.push([
+                a[0] + t * (b[0] - a[0]),
+                a[1] + t * (b[1] - a[1]),
+                a[2] + t * (b[2] - a[2]),
+            ]);
+        }
+    }
+    if let Some(&last) = corners.last() {
+        path.push(last);
+    }
+    path
+}
+
+
+/// Every wave vector commensurate with a periodic simulation box with `n`
+/// points along each axis, `2 pi m / L` for `m` in `0..n`. Open axes only
+/// get `q = 0`.
+pub fn q_grid(geometry: &Geometry, n: [usize; 3]) -> Vec<[f64; 3]> {
+    let size = geometry.size();
+    let periodic = geometry.periodic();
+    let axis = |k: usize| -> Vec<f64> {
+        if periodic[k] {
+            (0..n[k]).map(|m| 2.0 * PI * m as f64 / size[k]).collect()
+        } else {
+            vec![0.0]
+        }
+    };
+    let mut grid = Vec::new();
+    for &qx in axis(0).iter() {
+        for &qy in axis(1).iter() {
+            for &qz in axis(2).iter() {
+                grid.push([qx, qy, qz]);
+            }
+        }
+    }
+    grid
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{CorrelationFunction, StructureFactor, q_grid, q_path};
+    use std::f64::consts::PI;
+    use lattice::{Geometry, LatticeBuilder, UnitCell};
+    use rand::{SeedableRng, XorShiftRng};
+    use state::{Spin, State, HeisenbergSpin};
+
+    fn square(n: usize) -> Geometry {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(n, n, 1).build().unwrap();
+        Geometry::from_lattice(&lattice)
+    }
+
+    fn neel(n: usize) -> State<HeisenbergSpin> {
+        let mut state = State::up_with_size(n * n);
+        for i in 0..n {
+            for j in 0..n {
+                if (i + j) % 2 == 1 {
+                    state.set_at(i * n + j, HeisenbergSpin::down());
+                }
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn correlations_of_a_ferromagnet() {
+        let geometry = square(4);
+        let mut correlation = CorrelationFunction::new(&geometry, 0.1).unwrap();
+        correlation.push(&State::<HeisenbergSpin>::up_with_size(16));
+        let values = correlation.values();
+        assert_eq!(values.len(), 5);
+        for (_, c) in values {
+            assert!((c - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn bins_need_a_positive_width() {
+        let geometry = square(2);
+        for &width in [0.0, -1.0, f64::NAN, f64::INFINITY].iter() {
+            assert!(CorrelationFunction::new(&geometry, width).is_err());
+        }
+    }
+
+    #[test]
+    fn correlations_of_an_antiferromagnet() {
+        let geometry = square(4);
+        let mut correlation = CorrelationFunction::new(&geometry, 0.1).unwrap();
+        correlation.push(&neel(4));
+        let values = correlation.values();
+        let expected = [(1.05, -1.0), (1.45, 1.0), (2.05, 1.0), (2.25, -1.0), (2.85, 1.0)];
+        assert_eq!(values.len(), expected.len());
+        for (&(r, c), &(er, ec)) in values.iter().zip(expected.iter()) {
+            assert!((r - er).abs() < 1e-12);
+            assert!((c - ec).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn structure_factor_peaks_at_the_ordering_vector() {
+        let geometry = square(4);
+        let qpoints = vec![[0.0, 0.0, 0.0], [PI, PI, 0.0], [PI, 0.0, 0.0]];
+        let mut fft = StructureFactor::new(&geometry, qpoints.clone());
+        let mut direct = StructureFactor::direct(&geometry, qpoints);
+        assert!(fft.uses_fft());
+        assert!(!direct.uses_fft());
+        fft.push(&neel(4));
+        direct.push(&neel(4));
+        for values in [fft.values(), direct.values()].iter() {
+            assert!(values[0].1.abs() < 1e-12);
+            assert!((values[1].1 - 16.0).abs() < 1e-12);
+            assert!(values[2].1.abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn fft_and_direct_sums_agree() {
+        let geometry = square(6);
+        let qpoints = q_grid(&geometry, [6, 6, 1]);
+        assert_eq!(qpoints.len(), 36);
+        let mut fft = StructureFactor::new(&geometry, qpoints.clone());
+        let mut direct = StructureFactor::direct(&geometry, qpoints);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..3 {
+            let state = State::<HeisenbergSpin>::rand_with_size(36, &mut rng);
+            fft.push(&state);
+            direct.push(&state);
+        }
+        for (a, b) in fft.values().iter().zip(direct.values().iter()) {
+            assert!((a.1 - b.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn incommensurate_wave_vectors_fall_back_to_direct_sums() {
+        let geometry = square(4);
+        let path = q_path(&[[0.0, 0.0, 0.0], [PI, 0.0, 0.0], [PI, PI, 0.0]], 10);
+        assert_eq!(path.len(), 21);
+        let structure = StructureFactor::new(&geometry, path);
+        assert!(!structure.uses_fft());
+    }
+}
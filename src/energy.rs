@@ -1,6 +1,13 @@
-use sprs::CsMat;
+use serde_json;
+use sprs::{CsMat, TriMat};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
 use std::iter::Iterator;
 use std::marker::PhantomData;
+use vegas_lattice::{Axis, Lattice};
+use coloring::Coloring;
+use error::VegasError;
 use state::{Spin, State};
 
 
@@ -13,11 +20,62 @@ pub trait EnergyComponent<T: Spin> {
     /// of the state vector (business as usual)
     fn energy(&self, state: &State<T>, index: usize) -> f64;
 
+    /// Get the energy of a whole state. Terms that couple sites must count
+    /// every coupling once, even though the energy of a site includes all
+    /// of its couplings.
     fn total_energy(&self, state: &State<T>) -> f64 {
         (0..state.len())
             .map(|i| self.energy(state, i))
             .fold(0f64, |s, i| s + i)
     }
+
+    /// Get the change in the total energy if the spin at `index` became
+    /// `spin`. The default compares the total energies of both states,
+    /// terms should override it with something cheaper.
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64
+        where T: Clone
+    {
+        let mut trial = state.clone();
+        trial.set_at(index, spin.clone());
+        self.total_energy(&trial) - self.total_energy(state)
+    }
+
+    /// A short name for this term, used to label energy breakdowns.
+    fn label(&self) -> String {
+        "energy".to_string()
+    }
+
+    /// Get the total energy of a state split into labeled terms, composed
+    /// hamiltonians report one entry per component.
+    fn breakdown(&self, state: &State<T>) -> Vec<(String, f64)> {
+        vec![(self.label(), self.total_energy(state))]
+    }
+
+    /// Number of entries in a breakdown.
+    fn nterms(&self) -> usize {
+        1
+    }
+
+    /// Check that the energy applies to a state, say that it has as many
+    /// sites as the state.
+    fn check(&self, _state: &State<T>) -> Result<(), VegasError> {
+        Ok(())
+    }
+
+    /// Integrators call this with the state they are about to update, so
+    /// that energies caching anything about it can catch up.
+    fn sync(&self, _state: &State<T>) {}
+
+    /// Integrators call this after accepting a move that changed the spin
+    /// at `index` of `state`.
+    fn accept(&self, _state: &State<T>, _index: usize) {}
+
+    /// Whether the term keeps anything about the state between moves,
+    /// through `sync` and `accept`, so that integrators cannot share it
+    /// while they update different states.
+    fn stateful(&self) -> bool {
+        false
+    }
 }
 
 
@@ -30,6 +88,10 @@ impl Gauge {
     pub fn new(val: f64) -> Self {
         Self { value: val }
     }
+
+    pub fn value(&self) -> f64 {
+        self.value
+    }
 }
 
 
@@ -38,6 +100,14 @@ impl<T: Spin> EnergyComponent<T> for Gauge {
         debug_assert!(index < state.len());
         self.value
     }
+
+    fn delta_energy(&self, _state: &State<T>, _index: usize, _spin: &T) -> f64 {
+        0.0
+    }
+
+    fn label(&self) -> String {
+        "gauge".to_string()
+    }
 }
 
 
@@ -53,6 +123,15 @@ impl<T: Spin> UniaxialAnisotropy<T> {
             strength: k,
         }
     }
+
+    /// The easy (or hard) axis.
+    pub fn reference(&self) -> &T {
+        &self.reference
+    }
+
+    pub fn strength(&self) -> f64 {
+        self.strength
+    }
 }
 
 impl<T: Spin> EnergyComponent<T> for UniaxialAnisotropy<T> {
@@ -65,7 +144,16 @@ impl<T: Spin> EnergyComponent<T> for UniaxialAnisotropy<T> {
         state.spins()
             .iter()
             .map(|s| s.interact(&self.reference).powi(2))
-            .fold(0f64, |s, i| s + i)
+            .fold(0f64, |s, i| s + i) * self.strength
+    }
+
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64 {
+        let old = state.at(index).interact(&self.reference).powi(2);
+        (spin.interact(&self.reference).powi(2) - old) * self.strength
+    }
+
+    fn label(&self) -> String {
+        "anisotropy".to_string()
     }
 }
 
@@ -83,6 +171,15 @@ impl<T: Spin> ZeemanEnergy<T> {
             strength: h,
         }
     }
+
+    /// The direction of the field.
+    pub fn reference(&self) -> &T {
+        &self.reference
+    }
+
+    pub fn strength(&self) -> f64 {
+        self.strength
+    }
 }
 
 
@@ -96,7 +193,16 @@ impl<T: Spin> EnergyComponent<T> for ZeemanEnergy<T> {
         - state.spins()
             .iter()
             .map(|s| s.interact(&self.reference))
-            .fold(0f64, |s, i| s + i)
+            .fold(0f64, |s, i| s + i) * self.strength
+    }
+
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64 {
+        let old = state.at(index).interact(&self.reference);
+        - (spin.interact(&self.reference) - old) * self.strength
+    }
+
+    fn label(&self) -> String {
+        "zeeman".to_string()
     }
 }
 
@@ -107,12 +213,178 @@ pub struct ExchangeEnergy {
 
 
 impl ExchangeEnergy {
-    pub fn new(exc: CsMat<f64>) -> Self {
-        Self { exchange: exc }
+    /// New up an exchange energy out of a square matrix of exchange
+    /// constants.
+    pub fn new(exc: CsMat<f64>) -> Result<Self, VegasError> {
+        if exc.rows() != exc.cols() {
+            return Err(VegasError::SizeMismatch(exc.rows(), exc.cols()));
+        }
+        Ok(Self { exchange: exc })
+    }
+
+    /// The exchange constants, with a row for every site.
+    pub fn matrix(&self) -> &CsMat<f64> {
+        &self.exchange
+    }
+
+    /// Color the sites so that no two coupled sites share a color.
+    pub fn coloring(&self) -> Coloring {
+        Coloring::greedy(self.exchange.rows(), |i| {
+            self.exchange
+                .outer_view(i)
+                .map(|row| row.indices().to_vec())
+                .unwrap_or_default()
+        })
+    }
+
+    /// Build the exchange matrix out of the vertices of a lattice, the
+    /// coupling function gets every bond and returns its exchange constant.
+    ///
+    /// A vertex and its reverse (swapped source and target, opposite delta)
+    /// describe the same bond so they are counted once, and must have the
+    /// same coupling. Bonds that wrap around periodic boundaries are kept
+    /// as separate bonds even if they join the same pair of sites.
+    pub fn from_lattice<F>(lattice: &Lattice, coupling: F) -> Result<Self, BondError>
+        where F: Fn(&Bond) -> f64
+    {
+        let nsites = lattice.sites().len();
+        let mut seen = HashMap::new();
+        let mut mat = TriMat::new((nsites, nsites));
+        for vertex in lattice.vertices() {
+            let (source, target) = (vertex.source(), vertex.target());
+            let delta = (vertex.delta_along(Axis::X),
+                         vertex.delta_along(Axis::Y),
+                         vertex.delta_along(Axis::Z));
+            let tags = vertex_tags(vertex)
+                .ok_or(BondError::UnreadableTags(source, target))?;
+            let bond = Bond::new(lattice, source, target, delta, tags);
+            let key = (bond.source, bond.target, bond.delta);
+            if seen.contains_key(&key) {
+                return Err(BondError::DuplicateBond(bond.source, bond.target));
+            }
+            let exchange = coupling(&bond);
+            let (dx, dy, dz) = bond.delta;
+            let reverse = (bond.target, bond.source, (-dx, -dy, -dz));
+            seen.insert(key, exchange);
+            if reverse != key {
+                if let Some(other) = seen.get(&reverse) {
+                    if (other - exchange).abs() > 1e-12 {
+                        return Err(BondError::AsymmetricBond(bond.source, bond.target));
+                    }
+                    continue;
+                }
+            }
+            mat.add_triplet(bond.source, bond.target, exchange);
+            mat.add_triplet(bond.target, bond.source, exchange);
+        }
+        Ok(Self { exchange: mat.to_csr() })
+    }
+}
+
+
+/// A bond between two sites of a lattice, as seen by the coupling function
+/// of `ExchangeEnergy::from_lattice`.
+#[derive(Clone, Debug)]
+pub struct Bond {
+    source: usize,
+    target: usize,
+    source_kind: String,
+    target_kind: String,
+    delta: (i32, i32, i32),
+    tags: Vec<String>,
+}
+
+impl Bond {
+    fn new(lattice: &Lattice,
+           source: usize,
+           target: usize,
+           delta: (i32, i32, i32),
+           tags: Vec<String>) -> Self
+    {
+        let sites = lattice.sites();
+        Self {
+            source,
+            target,
+            source_kind: sites[source].kind(),
+            target_kind: sites[target].kind(),
+            delta,
+            tags,
+        }
+    }
+
+    pub fn source(&self) -> usize {
+        self.source
+    }
+
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    /// The kinds of the source and target sites.
+    pub fn kinds(&self) -> (&str, &str) {
+        (&self.source_kind, &self.target_kind)
+    }
+
+    /// How many times the bond wraps around the lattice along an axis.
+    pub fn delta_along(&self, axis: Axis) -> i32 {
+        match axis {
+            Axis::X => self.delta.0,
+            Axis::Y => self.delta.1,
+            Axis::Z => self.delta.2,
+        }
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
     }
 }
 
 
+/// Vertex tags are not part of the public vegas-lattice api, but they do
+/// serialize. Untagged vertices have no tags.
+fn vertex_tags<V>(vertex: &V) -> Option<Vec<String>>
+    where V: ::serde::Serialize
+{
+    let value = serde_json::to_value(vertex).ok()?;
+    match value.get("tags") {
+        None | Some(serde_json::Value::Null) => Some(Vec::new()),
+        Some(tags) => tags.as_array()?
+            .iter()
+            .map(|t| t.as_str().map(|t| t.to_string()))
+            .collect(),
+    }
+}
+
+
+#[derive(Debug)]
+pub enum BondError {
+    DuplicateBond(usize, usize),
+    AsymmetricBond(usize, usize),
+    UnreadableTags(usize, usize),
+}
+
+
+impl fmt::Display for BondError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BondError::DuplicateBond(s, t) =>
+                write!(f, "the bond {} -> {} is listed twice", s, t),
+            BondError::AsymmetricBond(s, t) =>
+                write!(f, "the bond {} -> {} has a different coupling than its reverse", s, t),
+            BondError::UnreadableTags(s, t) =>
+                write!(f, "the tags of the bond {} -> {} can not be read", s, t),
+        }
+    }
+}
+
+
+impl StdError for BondError {}
+
+
 impl<T: Spin> EnergyComponent<T> for ExchangeEnergy {
     fn energy(&self, state: &State<T>, index: usize) -> f64 {
         debug_assert!(index < state.len());
@@ -120,7 +392,7 @@ impl<T: Spin> EnergyComponent<T> for ExchangeEnergy {
         if let Some(row) = self.exchange.outer_view(index) {
             row.iter()
             .map(|(nbi, exc)| (state.at(nbi), exc))
-            .map(|(nb, exc)| - exc * site.interact(&nb))
+            .map(|(nb, exc)| - exc * site.interact(nb))
             .fold(0f64, |s, i| s + i)
         } else {
             // Just retun 0.0 for out of ranges.
@@ -133,6 +405,33 @@ impl<T: Spin> EnergyComponent<T> for ExchangeEnergy {
             .map(|i| self.energy(state, i))
             .fold(0f64, |s, i| s + i) / 2.0
     }
+
+    /// Every bond of the site changes, but self interactions are counted
+    /// once per site in the total so only half of their change shows.
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64 {
+        let old = state.at(index);
+        match self.exchange.outer_view(index) {
+            Some(row) => row.iter()
+                .map(|(j, exc)| if j == index {
+                    - exc * (spin.interact(spin) - old.interact(old)) / 2.0
+                } else {
+                    - exc * (spin.interact(state.at(j)) - old.interact(state.at(j)))
+                })
+                .sum(),
+            None => 0.0,
+        }
+    }
+
+    fn label(&self) -> String {
+        "exchange".to_string()
+    }
+
+    fn check(&self, state: &State<T>) -> Result<(), VegasError> {
+        if self.exchange.rows() != state.len() {
+            return Err(VegasError::SizeMismatch(self.exchange.rows(), state.len()));
+        }
+        Ok(())
+    }
 }
 
 
@@ -154,11 +453,15 @@ impl<T, U, V> CompoundEnergy<T, U, V>
 {
     pub fn new(a: U, b: V) -> Self {
         Self {
-            a: a,
-            b: b,
+            a,
+            b,
             phantom: PhantomData,
         }
     }
+
+    pub fn terms(&self) -> (&U, &V) {
+        (&self.a, &self.b)
+    }
 }
 
 impl<T, U, V> EnergyComponent<T> for CompoundEnergy<T, U, V>
@@ -167,7 +470,188 @@ impl<T, U, V> EnergyComponent<T> for CompoundEnergy<T, U, V>
           V: EnergyComponent<T>
 {
     fn energy(&self, state: &State<T>, index: usize) -> f64 {
-        self.a.energy(&state, index) + self.b.energy(&state, index)
+        self.a.energy(state, index) + self.b.energy(state, index)
+    }
+
+    fn total_energy(&self, state: &State<T>) -> f64 {
+        self.a.total_energy(state) + self.b.total_energy(state)
+    }
+
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64
+        where T: Clone
+    {
+        self.a.delta_energy(state, index, spin) + self.b.delta_energy(state, index, spin)
+    }
+
+    fn label(&self) -> String {
+        format!("{}+{}", self.a.label(), self.b.label())
+    }
+
+    fn breakdown(&self, state: &State<T>) -> Vec<(String, f64)> {
+        let mut terms = self.a.breakdown(state);
+        terms.extend(self.b.breakdown(state));
+        terms
+    }
+
+    fn nterms(&self) -> usize {
+        self.a.nterms() + self.b.nterms()
+    }
+
+    fn check(&self, state: &State<T>) -> Result<(), VegasError> {
+        self.a.check(state)?;
+        self.b.check(state)
+    }
+
+    fn sync(&self, state: &State<T>) {
+        self.a.sync(state);
+        self.b.sync(state);
+    }
+
+    fn accept(&self, state: &State<T>, index: usize) {
+        self.a.accept(state, index);
+        self.b.accept(state, index);
+    }
+
+    fn stateful(&self) -> bool {
+        self.a.stateful() || self.b.stateful()
+    }
+}
+
+
+/// Give an energy component a custom label, handy to tell apart several
+/// terms of the same kind in a breakdown.
+pub struct Labeled<U> {
+    label: String,
+    inner: U,
+}
+
+impl<U> Labeled<U> {
+    pub fn new(label: &str, inner: U) -> Self {
+        Self {
+            label: label.to_string(),
+            inner,
+        }
+    }
+}
+
+impl<T, U> EnergyComponent<T> for Labeled<U>
+    where T: Spin,
+          U: EnergyComponent<T>
+{
+    fn energy(&self, state: &State<T>, index: usize) -> f64 {
+        self.inner.energy(state, index)
+    }
+
+    fn total_energy(&self, state: &State<T>) -> f64 {
+        self.inner.total_energy(state)
+    }
+
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64
+        where T: Clone
+    {
+        self.inner.delta_energy(state, index, spin)
+    }
+
+    fn label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn check(&self, state: &State<T>) -> Result<(), VegasError> {
+        self.inner.check(state)
+    }
+
+    fn sync(&self, state: &State<T>) {
+        self.inner.sync(state);
+    }
+
+    fn accept(&self, state: &State<T>, index: usize) {
+        self.inner.accept(state, index);
+    }
+
+    fn stateful(&self) -> bool {
+        self.inner.stateful()
+    }
+}
+
+
+/// A sum of energy terms picked at run time, where `CompoundEnergy` needs
+/// them at compile time.
+pub struct EnergySum<T: Spin> {
+    terms: Vec<Box<dyn EnergyComponent<T> + Send + Sync>>,
+}
+
+impl<T: Spin> EnergySum<T> {
+    pub fn new() -> Self {
+        Self { terms: Vec::new() }
+    }
+
+    pub fn with_term<U>(mut self, term: U) -> Self
+        where U: EnergyComponent<T> + Send + Sync + 'static
+    {
+        self.terms.push(Box::new(term));
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+}
+
+impl<T: Spin> Default for EnergySum<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Spin> EnergyComponent<T> for EnergySum<T> {
+    fn energy(&self, state: &State<T>, index: usize) -> f64 {
+        self.terms.iter().map(|t| t.energy(state, index)).sum()
+    }
+
+    fn total_energy(&self, state: &State<T>) -> f64 {
+        self.terms.iter().map(|t| t.total_energy(state)).sum()
+    }
+
+    fn delta_energy(&self, state: &State<T>, index: usize, spin: &T) -> f64
+        where T: Clone
+    {
+        self.terms.iter().map(|t| t.delta_energy(state, index, spin)).sum()
+    }
+
+    fn label(&self) -> String {
+        self.terms.iter().map(|t| t.label()).collect::<Vec<_>>().join("+")
+    }
+
+    fn breakdown(&self, state: &State<T>) -> Vec<(String, f64)> {
+        self.terms.iter().flat_map(|t| t.breakdown(state)).collect()
+    }
+
+    fn nterms(&self) -> usize {
+        self.terms.iter().map(|t| t.nterms()).sum()
+    }
+
+    fn check(&self, state: &State<T>) -> Result<(), VegasError> {
+        self.terms.iter().try_for_each(|t| t.check(state))
+    }
+
+    fn sync(&self, state: &State<T>) {
+        for term in &self.terms {
+            term.sync(state);
+        }
+    }
+
+    fn accept(&self, state: &State<T>, index: usize) {
+        for term in &self.terms {
+            term.accept(state, index);
+        }
+    }
+
+    fn stateful(&self) -> bool {
+        self.terms.iter().any(|t| t.stateful())
     }
 }
 
@@ -213,15 +697,26 @@ mod tests {
         Gauge,
         UniaxialAnisotropy,
         ZeemanEnergy,
-        CompoundEnergy
+        CompoundEnergy,
+        Labeled,
+        EnergySum,
+        ExchangeEnergy,
+        BondError,
     };
-    use state::{Spin, State, HeisenbergSpin};
+    use error::VegasError;
+    use field::CachedExchange;
+    use lattice::{LatticeBuilder, UnitCell};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sprs::TriMat;
+    use std::cell::RefCell;
+    use state::{Spin, State, HeisenbergSpin, IsingSpin};
+    use vegas_lattice::{Axis, Lattice};
 
     #[test]
     fn test_gauge_energy() {
         let ups = State::<HeisenbergSpin>::up_with_size(10);
         let gauge = Gauge::new(10.0);
-        assert!(gauge.total_energy(&ups) - 100.0 < 1e-12)
+        assert!((gauge.total_energy(&ups) - 100.0).abs() < 1e-12)
     }
 
     #[test]
@@ -229,17 +724,35 @@ mod tests {
         let ups = State::<HeisenbergSpin>::up_with_size(10);
         let downs = State::<HeisenbergSpin>::down_with_size(10);
         let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::up(), 1.0);
-        assert!(anisotropy.total_energy(&ups) - 100.0 < 1e-12);
-        assert!(anisotropy.total_energy(&downs) - 100.0 < 1e-12)
+        assert!((anisotropy.total_energy(&ups) - 10.0).abs() < 1e-12);
+        assert!((anisotropy.total_energy(&downs) - 10.0).abs() < 1e-12);
+        let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::up(), -2.5);
+        assert!((anisotropy.total_energy(&ups) + 25.0).abs() < 1e-12)
     }
 
     #[test]
     fn test_zeeman_energy() {
         let ups = State::<HeisenbergSpin>::up_with_size(10);
         let downs = State::<HeisenbergSpin>::down_with_size(10);
-        let anisotropy = ZeemanEnergy::new(HeisenbergSpin::up(), 1.0);
-        assert!(anisotropy.total_energy(&ups) + 10.0 < 1e-12);
-        assert!(anisotropy.total_energy(&downs) - 10.0 < 1e-12)
+        let zeeman = ZeemanEnergy::new(HeisenbergSpin::up(), 1.0);
+        assert!((zeeman.total_energy(&ups) + 10.0).abs() < 1e-12);
+        assert!((zeeman.total_energy(&downs) - 10.0).abs() < 1e-12);
+        let zeeman = ZeemanEnergy::new(HeisenbergSpin::up(), 3.0);
+        assert!((zeeman.total_energy(&ups) + 30.0).abs() < 1e-12)
+    }
+
+    #[test]
+    fn energy_sums_add_up_their_terms() {
+        let state = State::<HeisenbergSpin>::up_with_size(4);
+        let sum = EnergySum::new()
+            .with_term(Gauge::new(1.0))
+            .with_term(Labeled::new("field", ZeemanEnergy::new(HeisenbergSpin::up(), 1.0)));
+        assert_eq!(sum.len(), 2);
+        assert_eq!(sum.label(), "gauge+field");
+        assert!((sum.total_energy(&state) - 0.0).abs() < 1e-12);
+        let breakdown = sum.breakdown(&state);
+        assert_eq!(breakdown[1].0, "field");
+        assert!((breakdown[1].1 + 4.0).abs() < 1e-12);
     }
 
     #[test]
@@ -248,7 +761,7 @@ mod tests {
         let gauge = Gauge::new(10.0);
         let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::up(), 1.0);
         let compound = CompoundEnergy::new(gauge, anisotropy);
-        assert!(compound.total_energy(&ups) - 200.0 < 1e-12);
+        assert!((compound.total_energy(&ups) - 110.0).abs() < 1e-12);
     }
 
     #[test]
@@ -256,6 +769,238 @@ mod tests {
         let state = State::<HeisenbergSpin>::up_with_size(10);
         let hamiltonian = hamiltonian!(UniaxialAnisotropy::new(HeisenbergSpin::up(), 1.0),
                                        Gauge::new(1.0));
-        assert!(hamiltonian.total_energy(&state) - 200.0 < 1e-12);
+        assert!((hamiltonian.total_energy(&state) - 20.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn breakdown_has_one_entry_per_term() {
+        let state = State::<HeisenbergSpin>::up_with_size(10);
+        let hamiltonian = hamiltonian!(UniaxialAnisotropy::new(HeisenbergSpin::up(), 1.0),
+                                       Gauge::new(1.0),
+                                       Labeled::new("offset", Gauge::new(2.0)));
+        let breakdown = hamiltonian.breakdown(&state);
+        let labels: Vec<&str> = breakdown.iter().map(|(l, _)| l.as_str()).collect();
+        assert_eq!(labels, vec!["anisotropy", "gauge", "offset"]);
+        assert!((breakdown[1].1 - 10.0).abs() < 1e-12);
+        assert!((breakdown[2].1 - 20.0).abs() < 1e-12);
+    }
+
+    fn chain(vertices: &str) -> Lattice {
+        let data = format!(r#"{{
+            "size": [1.0, 1.0, 1.0],
+            "sites": [
+                {{"kind": "Fe", "position": [0.0, 0.0, 0.0]}},
+                {{"kind": "Ni", "position": [0.5, 0.0, 0.0]}}
+            ],
+            "vertices": [{}]
+        }}"#, vertices);
+        data.parse().unwrap()
+    }
+
+    #[test]
+    fn exchange_from_a_periodic_lattice() {
+        let lattice = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 1, "target": 0, "delta": [1, 0, 0], "tags": ["weak"]}
+        "#).expand_along(Axis::X, 3);
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |bond| {
+            if bond.has_tag("weak") { 0.5 } else { 1.0 }
+        }).unwrap();
+        let state = State::<HeisenbergSpin>::up_with_size(6);
+        // Every site has a strong and a weak bond, including the ones that
+        // wrap around the boundary.
+        for i in 0..6 {
+            assert!((exchange.energy(&state, i) + 1.5).abs() < 1e-12);
+        }
+        assert!((exchange.total_energy(&state) + 4.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn bonds_carry_the_vertex_data() {
+        let lattice = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 1, "target": 0, "delta": [1, 0, 0], "tags": ["weak", "edge"]}
+        "#);
+        let bonds = RefCell::new(Vec::new());
+        ExchangeEnergy::from_lattice(&lattice, |bond| {
+            bonds.borrow_mut().push(bond.clone());
+            1.0
+        }).unwrap();
+        let bonds = bonds.into_inner();
+        assert_eq!(bonds.len(), 2);
+        assert_eq!((bonds[0].source(), bonds[0].target()), (0, 1));
+        assert!(bonds[0].tags().is_empty());
+        assert_eq!((bonds[1].source(), bonds[1].target()), (1, 0));
+        assert_eq!(bonds[1].delta_along(Axis::X), 1);
+        assert_eq!(bonds[1].delta_along(Axis::Y), 0);
+        assert_eq!(bonds[1].tags(), &["weak".to_string(), "edge".to_string()]);
+    }
+
+    #[test]
+    fn exchange_from_a_small_periodic_lattice_keeps_both_bonds() {
+        let lattice = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 1, "target": 0, "delta": [1, 0, 0]}
+        "#);
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let state = State::<HeisenbergSpin>::up_with_size(2);
+        assert!((exchange.total_energy(&state) + 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exchange_from_lattice_counts_reverse_vertices_once() {
+        let lattice = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 1, "target": 0, "delta": [0, 0, 0]}
+        "#);
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |bond| {
+            match bond.kinds() {
+                ("Fe", "Ni") | ("Ni", "Fe") => 2.0,
+                _ => 0.0,
+            }
+        }).unwrap();
+        let state = State::<HeisenbergSpin>::up_with_size(2);
+        assert!((exchange.total_energy(&state) + 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn exchange_from_lattice_rejects_bad_bonds() {
+        let duplicated = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 0, "target": 1, "delta": [0, 0, 0]}
+        "#);
+        match ExchangeEnergy::from_lattice(&duplicated, |_| 1.0) {
+            Err(BondError::DuplicateBond(0, 1)) => (),
+            _ => panic!("duplicated bond went through"),
+        }
+        let asymmetric = chain(r#"
+            {"source": 0, "target": 1, "delta": [0, 0, 0]},
+            {"source": 1, "target": 0, "delta": [0, 0, 0]}
+        "#);
+        match ExchangeEnergy::from_lattice(&asymmetric, |bond| bond.source() as f64) {
+            Err(BondError::AsymmetricBond(1, 0)) => (),
+            _ => panic!("asymmetric bond went through"),
+        }
+    }
+
+    #[test]
+    fn exchange_sizes_are_checked() {
+        let chain = chain(r#"{"source": 0, "target": 1, "delta": [0, 0, 0]}"#);
+        let exchange = ExchangeEnergy::from_lattice(&chain, |_| 1.0).unwrap();
+        let hamiltonian = CompoundEnergy::new(Gauge::new(1.0), exchange);
+        assert!(hamiltonian.check(&State::<HeisenbergSpin>::up_with_size(2)).is_ok());
+        match hamiltonian.check(&State::<HeisenbergSpin>::up_with_size(3)) {
+            Err(VegasError::SizeMismatch(2, 3)) => (),
+            _ => panic!("a state of the wrong size went through"),
+        }
+        let rectangular = TriMat::<f64>::new((2, 3)).to_csr();
+        assert!(ExchangeEnergy::new(rectangular).is_err());
+    }
+
+    /// Check on random moves that the total energy of a term is its summed
+    /// site energy over `counted`, the number of sites that share every
+    /// coupling, and that `delta_energy` is the change in the total energy.
+    fn assert_consistent<S, T, R>(energy: &T, counted: Option<f64>, mut state: State<S>,
+                                  rng: &mut R)
+        where S: Spin + Clone,
+              T: EnergyComponent<S>,
+              R: Rng
+    {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9 * (1.0 + a.abs().max(b.abs()));
+        energy.sync(&state);
+        for _ in 0..50 {
+            let total = energy.total_energy(&state);
+            if let Some(counted) = counted {
+                let sites: f64 = (0..state.len()).map(|i| energy.energy(&state, i)).sum();
+                assert!(close(total, sites / counted),
+                        "{}: total {} but sites add up to {}", energy.label(), total, sites);
+            }
+            let terms: f64 = energy.breakdown(&state).iter().map(|&(_, e)| e).sum();
+            assert!(close(total, terms), "{}: the breakdown does not add up", energy.label());
+            let index = rng.gen_range(0, state.len());
+            let spin = S::rand(rng);
+            let delta = energy.delta_energy(&state, index, &spin);
+            state.set_at(index, spin);
+            energy.accept(&state, index);
+            let after = energy.total_energy(&state);
+            assert!(close(after - total, delta),
+                    "{}: delta {} but the total changed by {}", energy.label(), delta,
+                    after - total);
+        }
+    }
+
+    fn random_exchanges<R: Rng>(rng: &mut R) -> Vec<ExchangeEnergy> {
+        let builders = [
+            LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1),
+            LatticeBuilder::new(UnitCell::triangular()).extent(3, 3, 1).shells(2),
+            LatticeBuilder::new(UnitCell::honeycomb()).extent(3, 3, 1).open_along(Axis::Y),
+            LatticeBuilder::new(UnitCell::cubic()).extent(3, 3, 3).shells(2),
+        ];
+        let mut exchanges: Vec<ExchangeEnergy> = builders.iter()
+            .map(|builder| {
+                let lattice = builder.build().unwrap();
+                let shift: f64 = rng.gen();
+                // Any coupling symmetric in the sites of the bond.
+                ExchangeEnergy::from_lattice(&lattice, |bond| {
+                    let (s, t) = (bond.source() as f64, bond.target() as f64);
+                    ((s + t) * 0.37 + s * t * 0.11 + shift).sin()
+                }).unwrap()
+            })
+            .collect();
+        // A dense matrix with self interactions.
+        let mut matrix = TriMat::new((6, 6));
+        for i in 0..6 {
+            for j in i..6 {
+                let exchange = rng.gen_range(-1.0, 1.0);
+                matrix.add_triplet(i, j, exchange);
+                if i != j {
+                    matrix.add_triplet(j, i, exchange);
+                }
+            }
+        }
+        exchanges.push(ExchangeEnergy::new(matrix.to_csr()).unwrap());
+        exchanges
+    }
+
+    fn check_components<S, R>(rng: &mut R)
+        where S: Spin + Clone + Send + Sync + 'static,
+              R: Rng
+    {
+        for exchange in random_exchanges(rng) {
+            let nsites = exchange.matrix().rows();
+            let seed = [rng.gen::<u32>() | 1, rng.gen(), rng.gen(), rng.gen()];
+            let state = || State::<S>::rand_with_size(nsites, &mut XorShiftRng::from_seed(seed));
+            let (field, axis) = (S::rand(rng), S::rand(rng));
+            let (h, k): (f64, f64) = (rng.gen_range(-2.0, 2.0), rng.gen_range(-2.0, 2.0));
+            assert_consistent(&Gauge::new(h), Some(1.0), state(), rng);
+            assert_consistent(&ZeemanEnergy::new(field, h), Some(1.0), state(), rng);
+            assert_consistent(&UniaxialAnisotropy::new(axis, k), Some(1.0), state(), rng);
+            assert_consistent(&exchange, Some(2.0), state(), rng);
+            let compound = hamiltonian!(
+                ZeemanEnergy::new(S::rand(rng), h),
+                UniaxialAnisotropy::new(S::rand(rng), k),
+                Labeled::new("offset", Gauge::new(k))
+            );
+            assert_consistent(&compound, None, state(), rng);
+            let sum = EnergySum::new()
+                .with_term(exchange)
+                .with_term(ZeemanEnergy::new(S::rand(rng), h));
+            assert_consistent(&sum, None, state(), rng);
+        }
+    }
+
+    #[test]
+    fn every_component_is_consistent_on_random_states() {
+        let mut rng = XorShiftRng::from_seed([3, 1, 4, 1]);
+        for _ in 0..5 {
+            check_components::<IsingSpin, _>(&mut rng);
+            check_components::<HeisenbergSpin, _>(&mut rng);
+            for exchange in random_exchanges(&mut rng) {
+                let nsites = exchange.matrix().rows();
+                let state = State::<HeisenbergSpin>::rand_with_size(nsites, &mut rng);
+                let cached = CachedExchange::new(exchange);
+                assert_consistent(&cached, Some(2.0), state, &mut rng);
+            }
+        }
     }
 }
@@ -0,0 +1,154 @@
+//! Errors of the library as a whole.
+//!
+//! Constructors validate what they are given and report problems as a
+//! `VegasError`, the errors of the file formats convert into it so that
+//! callers can use `?` across modules.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+
+use vegas_lattice::error::LatticeError;
+
+use checkpoint::CheckpointError;
+use config::ConfigError;
+use energy::BondError;
+use snapshot::SnapshotError;
+
+
+#[derive(Debug)]
+pub enum VegasError {
+    /// A lattice that could not be parsed.
+    Lattice(LatticeError),
+    /// Sizes that should agree but do not, the expected one first.
+    SizeMismatch(usize, usize),
+    /// A parameter out of its range, like a negative temperature.
+    InvalidParameter(String),
+    /// A row of results without a value for every column, the number of
+    /// columns first.
+    ColumnMismatch(usize, usize),
+    Io(io::Error),
+    Bond(BondError),
+    Config(ConfigError),
+    Checkpoint(CheckpointError),
+    Snapshot(SnapshotError),
+}
+
+
+impl fmt::Display for VegasError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VegasError::Lattice(LatticeError::JsonParseError(_)) =>
+                write!(f, "malformed lattice"),
+            VegasError::Lattice(LatticeError::InconsistentVertices) =>
+                write!(f, "malformed lattice: the vertices are inconsistent"),
+            VegasError::Lattice(LatticeError::NegativeSize) =>
+                write!(f, "malformed lattice: negative size"),
+            VegasError::SizeMismatch(expected, found) => write!(
+                f, "size mismatch: expected {} sites, found {}", expected, found),
+            VegasError::InvalidParameter(ref msg) => write!(f, "invalid parameter: {}", msg),
+            VegasError::ColumnMismatch(columns, values) => write!(
+                f, "column mismatch: {} values for {} columns", values, columns),
+            VegasError::Io(_) => write!(f, "I/O error"),
+            VegasError::Bond(_) => write!(f, "invalid bond"),
+            VegasError::Config(_) => write!(f, "invalid config"),
+            VegasError::Checkpoint(_) => write!(f, "unusable checkpoint"),
+            VegasError::Snapshot(_) => write!(f, "unusable snapshot"),
+        }
+    }
+}
+
+
+impl StdError for VegasError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            VegasError::Lattice(LatticeError::JsonParseError(ref e)) => Some(e),
+            VegasError::Lattice(_) => None,
+            VegasError::Io(ref e) => Some(e),
+            VegasError::Bond(ref e) => Some(e),
+            VegasError::Config(ref e) => Some(e),
+            VegasError::Checkpoint(ref e) => Some(e),
+            VegasError::Snapshot(ref e) => Some(e),
+            VegasError::SizeMismatch(..) |
+            VegasError::InvalidParameter(_) |
+            VegasError::ColumnMismatch(..) => None,
+        }
+    }
+}
+
+
+impl From<LatticeError> for VegasError {
+    fn from(e: LatticeError) -> Self {
+        VegasError::Lattice(e)
+    }
+}
+
+
+impl From<io::Error> for VegasError {
+    fn from(e: io::Error) -> Self {
+        VegasError::Io(e)
+    }
+}
+
+
+impl From<BondError> for VegasError {
+    fn from(e: BondError) -> Self {
+        VegasError::Bond(e)
+    }
+}
+
+
+impl From<ConfigError> for VegasError {
+    fn from(e: ConfigError) -> Self {
+        VegasError::Config(e)
+    }
+}
+
+
+impl From<CheckpointError> for VegasError {
+    fn from(e: CheckpointError) -> Self {
+        VegasError::Checkpoint(e)
+    }
+}
+
+
+impl From<SnapshotError> for VegasError {
+    fn from(e: SnapshotError) -> Self {
+        match e {
+            SnapshotError::SizeMismatch(expected, found) => {
+                VegasError::SizeMismatch(expected, found)
+            },
+            e => VegasError::Snapshot(e),
+        }
+    }
+}
+
+
+/// Fail with an invalid parameter error.
+pub fn invalid<T>(msg: String) -> Result<T, VegasError> {
+    Err(VegasError::InvalidParameter(msg))
+}
+
+
+/// Check that a temperature is positive.
+pub fn check_temp(temp: f64) -> Result<f64, VegasError> {
+    if temp <= 0.0 || temp.is_nan() {
+        return invalid(format!("temperatures must be positive, got {}", temp));
+    }
+    Ok(temp)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::VegasError;
+    use std::error::Error as StdError;
+    use std::io;
+
+    #[test]
+    fn wrapped_errors_are_reported_once() {
+        let e = VegasError::from(io::Error::new(io::ErrorKind::NotFound, "no such file"));
+        assert_eq!(e.to_string(), "I/O error");
+        assert_eq!(e.source().unwrap().to_string(), "no such file");
+    }
+}
@@ -0,0 +1,329 @@
+//! Exact thermodynamics of small discrete systems.
+//!
+//! Every configuration of the system is gone through once, in reflected
+//! Gray code order so that consecutive configurations differ in a single
+//! spin and the energy follows from `EnergyComponent::delta_energy`. The
+//! configurations are binned into energy levels, the density of states,
+//! along with the moments of the magnetization at every level, which is
+//! all that is needed for the averages at any temperature.
+
+use std::collections::HashMap;
+
+use energy::EnergyComponent;
+use error::{self, VegasError};
+use state::{DiscreteSpin, State};
+use statistics::log_sum_exp;
+
+
+/// Refuse to go through more configurations than this, which also bounds
+/// the number of levels kept in memory.
+pub const MAX_CONFIGURATIONS: u64 = 1 << 26;
+
+/// Recompute the energy from scratch after this many moves, so that the
+/// rounding errors of the updates do not pile up.
+const RESYNC: u64 = 1 << 12;
+
+/// Energies closer than this fall in the same level.
+const RESOLUTION: f64 = 1e-9;
+
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+
+/// The configurations of a given energy, with the sums of `|M|`, `M^2` and
+/// `M^4` over them.
+#[derive(Clone, Debug)]
+pub struct Level {
+    energy: f64,
+    degeneracy: u64,
+    magnetization: f64,
+    square: f64,
+    fourth: f64,
+}
+
+
+impl Level {
+    fn new(energy: f64) -> Self {
+        Self { energy, degeneracy: 0, magnetization: 0.0, square: 0.0, fourth: 0.0 }
+    }
+
+    fn add(&mut self, magnetization: f64) {
+        let square = magnetization * magnetization;
+        self.degeneracy += 1;
+        self.magnetization += magnetization;
+        self.square += square;
+        self.fourth += square * square;
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.energy
+    }
+
+    /// Number of configurations with this energy.
+    pub fn degeneracy(&self) -> u64 {
+        self.degeneracy
+    }
+
+    /// Mean magnetization magnitude over the configurations of the level.
+    pub fn magnetization(&self) -> f64 {
+        self.magnetization / self.degeneracy as f64
+    }
+}
+
+
+/// The density of states of a system, found by enumeration, and the exact
+/// averages that follow from it.
+#[derive(Clone, Debug)]
+pub struct Enumeration {
+    nsites: usize,
+    /// Sorted by energy.
+    levels: Vec<Level>,
+}
+
+
+impl Enumeration {
+    /// Go through every configuration of `nsites` spins under a
+    /// hamiltonian. Fails when there are more than `MAX_CONFIGURATIONS`, or
+    /// when an energy is not finite.
+    pub fn new<S, T>(hamiltonian: &T, nsites: usize) -> Result<Self, VegasError>
+        where S: DiscreteSpin + Clone,
+              T: EnergyComponent<S>
+    {
+        let values = S::values();
+        let q = values.len();
+        let total = (q as u64).checked_pow(nsites as u32)
+            .filter(|&total| total <= MAX_CONFIGURATIONS);
+        let total = match total {
+            Some(total) if q > 0 => total,
+            _ => return error::invalid(format!(
+                "{} sites of {} values are too many configurations to enumerate", nsites, q)),
+        };
+        let mut state = State::<S>::up_with_size(nsites);
+        hamiltonian.check(&state)?;
+        hamiltonian.sync(&state);
+        let mut energy = hamiltonian.total_energy(&state);
+        let mut magnetization = state.magnetization();
+        let mut levels = HashMap::new();
+        let mut record = |energy: f64, magnetization: [f64; 3]| {
+            if !energy.is_finite() {
+                return error::invalid(format!("the energy of a configuration is {}", energy));
+            }
+            levels.entry((energy / RESOLUTION).round() as i64)
+                .or_insert_with(|| Level::new(energy))
+                .add(norm(magnetization));
+            Ok(())
+        };
+        record(energy, magnetization)?;
+        // The digits of the Gray code and the direction each one moves in.
+        let mut digits = vec![0; nsites];
+        let mut rising = vec![true; nsites];
+        for step in 1..total {
+            let mut site = 0;
+            while (rising[site] && digits[site] + 1 == q) || (!rising[site] && digits[site] == 0) {
+                rising[site] = !rising[site];
+                site += 1;
+            }
+            digits[site] = if rising[site] { digits[site] + 1 } else { digits[site] - 1 };
+            let spin = values[digits[site]].clone();
+            energy += hamiltonian.delta_energy(&state, site, &spin);
+            let (old, new) = (state.at(site).magnetization(), spin.magnetization());
+            for (m, (old, new)) in magnetization.iter_mut().zip(old.iter().zip(new.iter())) {
+                *m += new - old;
+            }
+            state.set_at(site, spin);
+            hamiltonian.accept(&state, site);
+            if step % RESYNC == 0 {
+                energy = hamiltonian.total_energy(&state);
+                magnetization = state.magnetization();
+            }
+            record(energy, magnetization)?;
+        }
+        let mut levels: Vec<Level> = levels.into_values().collect();
+        levels.sort_by(|a, b| a.energy.total_cmp(&b.energy));
+        Ok(Self { nsites, levels })
+    }
+
+    pub fn nsites(&self) -> usize {
+        self.nsites
+    }
+
+    /// The energy levels, from the lowest up.
+    pub fn levels(&self) -> &[Level] {
+        &self.levels
+    }
+
+    /// Number of configurations gone through.
+    pub fn configurations(&self) -> u64 {
+        self.levels.iter().map(|l| l.degeneracy).sum()
+    }
+
+    pub fn ground_energy(&self) -> f64 {
+        self.levels.first().map_or(0.0, |l| l.energy)
+    }
+
+    /// `ln Z` at a temperature.
+    pub fn log_partition_function(&self, temp: f64) -> f64 {
+        log_sum_exp(self.levels.iter().map(|l| (l.degeneracy as f64).ln() - l.energy / temp))
+    }
+
+    /// Helmholtz free energy, `F = - T ln Z`.
+    pub fn free_energy(&self, temp: f64) -> f64 {
+        - temp * self.log_partition_function(temp)
+    }
+
+    /// Entropy, `S = (<E> - F) / T`.
+    pub fn entropy(&self, temp: f64) -> f64 {
+        (self.energy(temp) - self.free_energy(temp)) / temp
+    }
+
+    /// Probability of every energy level at a temperature, in the order of
+    /// `Enumeration::levels`.
+    pub fn distribution(&self, temp: f64) -> Vec<f64> {
+        let log_z = self.log_partition_function(temp);
+        self.levels.iter()
+            .map(|l| ((l.degeneracy as f64).ln() - l.energy / temp - log_z).exp())
+            .collect()
+    }
+
+    /// Thermal average of a sum over the configurations of every level.
+    fn average<F: Fn(&Level) -> f64>(&self, temp: f64, sum: F) -> f64 {
+        let log_z = self.log_partition_function(temp);
+        self.levels.iter()
+            .map(|l| sum(l) * (- l.energy / temp - log_z).exp())
+            .sum()
+    }
+
+    /// Mean total energy, `<E>`.
+    pub fn energy(&self, temp: f64) -> f64 {
+        self.average(temp, |l| l.degeneracy as f64 * l.energy)
+    }
+
+    /// Specific heat per site, `(<E^2> - <E>^2) / (N T^2)`.
+    pub fn specific_heat(&self, temp: f64) -> f64 {
+        let e = self.energy(temp);
+        let e2 = self.average(temp, |l| l.degeneracy as f64 * l.energy * l.energy);
+        (e2 - e * e) / (self.nsites as f64 * temp * temp)
+    }
+
+    /// Mean magnetization magnitude, `<|M|>`.
+    pub fn magnetization(&self, temp: f64) -> f64 {
+        self.average(temp, |l| l.magnetization)
+    }
+
+    /// Magnetic susceptibility per site, `(<M^2> - <|M|>^2) / (N T)`.
+    pub fn susceptibility(&self, temp: f64) -> f64 {
+        let m = self.magnetization(temp);
+        let m2 = self.average(temp, |l| l.square);
+        (m2 - m * m) / (self.nsites as f64 * temp)
+    }
+
+    /// Binder cumulant, `1 - <M^4> / (3 <M^2>^2)`.
+    pub fn binder_cumulant(&self, temp: f64) -> f64 {
+        let m2 = self.average(temp, |l| l.square);
+        let m4 = self.average(temp, |l| l.fourth);
+        1.0 - m4 / (3.0 * m2 * m2)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Enumeration;
+    use energy::{CompoundEnergy, EnergyComponent, ExchangeEnergy, ZeemanEnergy};
+    use error::VegasError;
+    use sprs::TriMat;
+    use state::{IsingSpin, State};
+
+    fn ring(nsites: usize, coupling: f64) -> ExchangeEnergy {
+        let mut matrix = TriMat::new((nsites, nsites));
+        for i in 0..nsites {
+            matrix.add_triplet(i, (i + 1) % nsites, coupling);
+            matrix.add_triplet((i + 1) % nsites, i, coupling);
+        }
+        ExchangeEnergy::new(matrix.to_csr()).unwrap()
+    }
+
+    #[test]
+    fn a_paramagnet_has_binomial_levels() {
+        let zeeman = ZeemanEnergy::new(IsingSpin::Up, 0.5);
+        let enumeration = Enumeration::new::<IsingSpin, _>(&zeeman, 10).unwrap();
+        assert_eq!(enumeration.configurations(), 1 << 10);
+        let degeneracies: Vec<u64> = enumeration.levels().iter().map(|l| l.degeneracy()).collect();
+        assert_eq!(degeneracies, vec![1, 10, 45, 120, 210, 252, 210, 120, 45, 10, 1]);
+        assert_eq!(enumeration.ground_energy(), -5.0);
+        for &temp in [0.3, 1.0, 4.0].iter() {
+            let log_z = 10.0 * (2.0 * (0.5f64 / temp).cosh()).ln();
+            assert!((enumeration.log_partition_function(temp) - log_z).abs() < 1e-9);
+            assert!((enumeration.energy(temp) + 5.0 * (0.5f64 / temp).tanh()).abs() < 1e-9);
+            let probabilities = enumeration.distribution(temp);
+            assert!((probabilities.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn an_ising_ring_matches_the_transfer_matrix() {
+        let (nsites, coupling) = (12, 0.8f64);
+        let exchange = ring(nsites, coupling);
+        let enumeration = Enumeration::new::<IsingSpin, _>(&exchange, nsites).unwrap();
+        for &temp in [0.5, 1.5, 5.0].iter() {
+            let (c, s) = ((coupling / temp).cosh(), (coupling / temp).sinh());
+            let z = (2.0 * c).powi(nsites as i32) + (2.0 * s).powi(nsites as i32);
+            assert!((enumeration.log_partition_function(temp) - z.ln()).abs() < 1e-9);
+            // Entropy from the derivative of the free energy.
+            let h = 1e-5;
+            let slope = (enumeration.free_energy(temp + h) - enumeration.free_energy(temp - h))
+                / (2.0 * h);
+            assert!((enumeration.entropy(temp) + slope).abs() < 1e-5);
+        }
+        // All spins aligned at low temperatures.
+        assert!((enumeration.magnetization(0.05) - nsites as f64).abs() < 1e-6);
+        assert!((enumeration.binder_cumulant(0.05) - 2.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn enumeration_agrees_with_the_total_energy() {
+        let hamiltonian = CompoundEnergy::new(ZeemanEnergy::new(IsingSpin::Up, 0.3), ring(9, -1.1));
+        let enumeration = Enumeration::new(&hamiltonian, 9).unwrap();
+        let temp = 1.3;
+        let mut state = State::<IsingSpin>::up_with_size(9);
+        let (mut z, mut energy, mut magnetization) = (0.0, 0.0, 0.0);
+        for config in 0..(1 << 9) {
+            for i in 0..9 {
+                let spin = if config & (1 << i) == 0 { IsingSpin::Up } else { IsingSpin::Down };
+                state.set_at(i, spin);
+            }
+            let e = hamiltonian.total_energy(&state);
+            let weight = (- e / temp).exp();
+            z += weight;
+            energy += weight * e;
+            magnetization += weight * state.magnetization()[2].abs();
+        }
+        assert!((enumeration.log_partition_function(temp) - z.ln()).abs() < 1e-9);
+        assert!((enumeration.energy(temp) - energy / z).abs() < 1e-9);
+        assert!((enumeration.magnetization(temp) - magnetization / z).abs() < 1e-9);
+    }
+
+    #[test]
+    fn energies_must_be_finite() {
+        let zeeman = ZeemanEnergy::new(IsingSpin::Up, f64::NAN);
+        match Enumeration::new::<IsingSpin, _>(&zeeman, 4) {
+            Err(VegasError::InvalidParameter(_)) => (),
+            _ => panic!("NaN energies cannot be binned"),
+        }
+    }
+
+    #[test]
+    fn large_systems_are_refused() {
+        match Enumeration::new::<IsingSpin, _>(&ring(40, 1.0), 40) {
+            Err(VegasError::InvalidParameter(_)) => (),
+            _ => panic!("40 Ising spins should be too many"),
+        }
+        assert!(Enumeration::new::<IsingSpin, _>(&ring(27, 1.0), 27).is_err());
+        match Enumeration::new::<IsingSpin, _>(&ring(4, 1.0), 5) {
+            Err(VegasError::SizeMismatch(4, 5)) => (),
+            _ => panic!("the exchange is for another system"),
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! Local exchange fields cached between moves.
+//!
+//! The exchange energy of a Heisenberg spin is `- s_i · h_i`, where the
+//! local field `h_i = Σ_j J_ij s_j` only depends on the neighbors. Keeping
+//! the fields around turns a trial energy into a single dot product, and
+//! accepting a move only touches the fields of the neighbors of the site,
+//! the sites along a column of the exchange matrix.
+
+use sprs::CsMat;
+use std::sync::RwLock;
+
+use energy::{EnergyComponent, ExchangeEnergy};
+use error::VegasError;
+use state::{HeisenbergSpin, Spin, State};
+
+
+/// The spins the fields were computed for, and the fields themselves
+/// without self interactions.
+struct Fields {
+    spins: Vec<[f64; 3]>,
+    fields: Vec<[f64; 3]>,
+}
+
+
+/// An exchange energy for Heisenberg spins that caches the local field of
+/// every site.
+///
+/// Integrators keep the cache up to date through `EnergyComponent::sync`
+/// and `EnergyComponent::accept`, so it drops in wherever an
+/// `ExchangeEnergy` goes. Site energies are those of the state last synced,
+/// with the moves accepted since.
+pub struct CachedExchange {
+    exchange: ExchangeEnergy,
+    /// The transposed exchange matrix, its rows are the sites whose field
+    /// changes with a spin.
+    columns: CsMat<f64>,
+    diagonal: Vec<f64>,
+    cache: RwLock<Option<Fields>>,
+}
+
+
+impl CachedExchange {
+    pub fn new(exchange: ExchangeEnergy) -> Self {
+        let matrix = exchange.matrix();
+        let columns = matrix.transpose_view().to_other_storage();
+        let diagonal = (0..matrix.rows())
+            .map(|i| matrix.get(i, i).cloned().unwrap_or(0.0))
+            .collect();
+        Self {
+            exchange,
+            columns,
+            diagonal,
+            cache: RwLock::new(None),
+        }
+    }
+
+    pub fn exchange(&self) -> &ExchangeEnergy {
+        &self.exchange
+    }
+
+    /// The cached field at a site, `None` before the first sync.
+    pub fn field(&self, index: usize) -> Option<[f64; 3]> {
+        let cache = self.cache.read().expect("poisoned local fields");
+        cache.as_ref().map(|cache| cache.fields[index])
+    }
+
+    fn compute(&self, state: &State<HeisenbergSpin>) -> Fields {
+        let spins: Vec<[f64; 3]> = state.spins().iter().map(|s| s.magnetization()).collect();
+        let mut fields = vec![[0.0; 3]; spins.len()];
+        let matrix = self.exchange.matrix();
+        for (i, field) in fields.iter_mut().enumerate() {
+            if let Some(row) = matrix.outer_view(i) {
+                for (j, &exchange) in row.iter().filter(|&(j, _)| j != i) {
+                    for (field, spin) in field.iter_mut().zip(&spins[j]) {
+                        *field += exchange * spin;
+                    }
+                }
+            }
+        }
+        Fields { spins, fields }
+    }
+
+    /// Move the spin of `index` to `spin`, propagating the change to the
+    /// fields of the sites it couples to.
+    fn propagate(&self, cache: &mut Fields, index: usize, spin: [f64; 3]) {
+        let old = cache.spins[index];
+        if old == spin {
+            return;
+        }
+        let delta = [spin[0] - old[0], spin[1] - old[1], spin[2] - old[2]];
+        if let Some(column) = self.columns.outer_view(index) {
+            for (j, &exchange) in column.iter().filter(|&(j, _)| j != index) {
+                for (field, delta) in cache.fields[j].iter_mut().zip(&delta) {
+                    *field += exchange * delta;
+                }
+            }
+        }
+        cache.spins[index] = spin;
+    }
+}
+
+
+impl EnergyComponent<HeisenbergSpin> for CachedExchange {
+    fn energy(&self, state: &State<HeisenbergSpin>, index: usize) -> f64 {
+        let cache = self.cache.read().expect("poisoned local fields");
+        match *cache {
+            Some(ref cache) => {
+                let spin = state.at(index).magnetization();
+                let field = cache.fields[index];
+                let own = self.diagonal.get(index).cloned().unwrap_or(0.0);
+                - (0..3).map(|k| spin[k] * (field[k] + own * spin[k])).sum::<f64>()
+            },
+            None => self.exchange.energy(state, index),
+        }
+    }
+
+    fn total_energy(&self, state: &State<HeisenbergSpin>) -> f64 {
+        self.exchange.total_energy(state)
+    }
+
+    fn delta_energy(&self, state: &State<HeisenbergSpin>, index: usize, spin: &HeisenbergSpin)
+        -> f64
+    {
+        let cache = self.cache.read().expect("poisoned local fields");
+        match *cache {
+            Some(ref cache) => {
+                let (new, old) = (spin.magnetization(), state.at(index).magnetization());
+                let field = cache.fields[index];
+                let own = self.diagonal.get(index).cloned().unwrap_or(0.0);
+                - (0..3).map(|k| {
+                    (new[k] - old[k]) * field[k] + own * (new[k] * new[k] - old[k] * old[k]) / 2.0
+                }).sum::<f64>()
+            },
+            None => self.exchange.delta_energy(state, index, spin),
+        }
+    }
+
+    fn label(&self) -> String {
+        EnergyComponent::<HeisenbergSpin>::label(&self.exchange)
+    }
+
+    fn check(&self, state: &State<HeisenbergSpin>) -> Result<(), VegasError> {
+        self.exchange.check(state)
+    }
+
+    fn sync(&self, state: &State<HeisenbergSpin>) {
+        let mut cache = self.cache.write().expect("poisoned local fields");
+        let stale = match *cache {
+            Some(ref cache) => cache.spins.len() != state.len(),
+            None => true,
+        };
+        if stale {
+            *cache = Some(self.compute(state));
+            return;
+        }
+        if let Some(ref mut cache) = *cache {
+            for (i, spin) in state.spins().iter().enumerate() {
+                self.propagate(cache, i, spin.magnetization());
+            }
+        }
+    }
+
+    fn accept(&self, state: &State<HeisenbergSpin>, index: usize) {
+        let mut cache = self.cache.write().expect("poisoned local fields");
+        if let Some(ref mut cache) = *cache {
+            self.propagate(cache, index, state.at(index).magnetization());
+        }
+    }
+
+    fn stateful(&self) -> bool {
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::CachedExchange;
+    use energy::{EnergyComponent, ExchangeEnergy};
+    use integrator::{CheckerboardIntegrator, Integrator, MetropolisIntegrator, StateGenerator};
+    use lattice::{LatticeBuilder, UnitCell};
+    use rand::{SeedableRng, XorShiftRng};
+    use state::{HeisenbergSpin, Spin, State};
+
+    #[test]
+    fn cached_fields_follow_single_spin_updates() {
+        let lattice = LatticeBuilder::new(UnitCell::triangular()).extent(5, 5, 1).build().unwrap();
+        let nsites = lattice.sites().len();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |b| b.source() as f64 * 0.1 + 1.0)
+            .unwrap();
+        let cached = CachedExchange::new(exchange);
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let mut state = State::<HeisenbergSpin>::rand_with_size(nsites, &mut rng);
+        assert!(cached.field(0).is_none());
+        cached.sync(&state);
+        for step in 0..200 {
+            let site = (step * 7) % nsites;
+            state.set_at(site, HeisenbergSpin::rand(&mut rng));
+            cached.accept(&state, site);
+        }
+        for i in 0..nsites {
+            let expected = cached.exchange().energy(&state, i);
+            assert!((cached.energy(&state, i) - expected).abs() < 1e-9);
+        }
+        // Changes behind the back of the cache are picked up by a sync.
+        state.set_at(3, HeisenbergSpin::up());
+        cached.sync(&state);
+        assert!((cached.energy(&state, 4) - cached.exchange().energy(&state, 4)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn integrators_use_the_cache_transparently() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(8, 8, 1).build().unwrap();
+        let exchange = || ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let cached = CachedExchange::new(exchange());
+        let plain = exchange();
+        // Same seeds, so the same moves are proposed and accepted.
+        let mut a = MetropolisIntegrator::new(0.8).unwrap().with_seed(5);
+        let mut b = MetropolisIntegrator::new(0.8).unwrap().with_seed(5);
+        let mut sa: State<HeisenbergSpin> = a.state(64);
+        let mut sb: State<HeisenbergSpin> = b.state(64);
+        for _ in 0..50 {
+            sa = a.step(&cached, &sa).unwrap();
+            sb = b.step(&plain, &sb).unwrap();
+        }
+        assert!((plain.total_energy(&sa) - plain.total_energy(&sb)).abs() < 1e-6);
+
+        let mut a = CheckerboardIntegrator::new(0.8, plain.coloring()).unwrap().with_seed(5);
+        let mut b = CheckerboardIntegrator::new(0.8, plain.coloring()).unwrap().with_seed(5);
+        let mut sa: State<HeisenbergSpin> = a.state(64);
+        let mut sb: State<HeisenbergSpin> = b.state(64);
+        for _ in 0..50 {
+            sa = a.step(&cached, &sa).unwrap();
+            sb = b.step(&plain, &sb).unwrap();
+        }
+        assert!((plain.total_energy(&sa) - plain.total_energy(&sb)).abs() < 1e-6);
+    }
+}
@@ -1,71 +1,179 @@
 extern crate rand;
 
 use rand::distributions::{IndependentSample, Range};
-use rand::{Rng, XorShiftRng};
+use rand::Rng;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
 
+use coloring::Coloring;
 use state::{Spin, State};
 use energy::EnergyComponent;
+use error::{check_temp, VegasError};
+use rng::XorShift128;
+
+
+/// Sites of a color class that share a random number stream in the
+/// checkerboard integrator.
+const CHUNK: usize = 256;
 
 
 pub trait Integrator<S: Spin, T: EnergyComponent<S>> {
-    fn step(&mut self, energy: &T, state: &State<S>) -> State<S>;
-}
+    /// Sweep once through `state`. Fails if the energy does not apply to
+    /// the state, see `EnergyComponent::check`.
+    fn step(&mut self, energy: &T, state: &State<S>) -> Result<State<S>, VegasError>;
 
-pub trait StateGenerator<S: Spin> {
-    fn state(&mut self, nsites: usize) -> State<S>;
+    /// Like `step`, but also bring the `totals` of `state` up to date for
+    /// the new state. Integrators that know the change of every move use
+    /// it, the rest compute the totals from scratch.
+    fn step_tracking(&mut self, energy: &T, state: &State<S>, totals: &mut Totals)
+        -> Result<State<S>, VegasError>
+    {
+        let state = self.step(energy, state)?;
+        *totals = Totals::of(energy, &state);
+        Ok(state)
+    }
 }
 
 
-pub struct MetropolisIntegrator {
-    rng: XorShiftRng,
-    temp: f64,
+/// Total energy and magnetization of a state, kept up to date move by move
+/// so that measuring them does not need a pass over the whole state.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    energy: f64,
+    magnetization: [f64; 3],
 }
 
 
-impl MetropolisIntegrator {
-    pub fn new(temp: f64) -> Self {
+impl Totals {
+    /// Compute the totals of a state from scratch.
+    pub fn of<S, T>(hamiltonian: &T, state: &State<S>) -> Self
+        where S: Spin,
+              T: EnergyComponent<S>
+    {
         Self {
-            temp: temp,
-            rng: XorShiftRng::new_unseeded(),
+            energy: hamiltonian.total_energy(state),
+            magnetization: state.magnetization(),
         }
     }
 
-    pub fn temp(&self) -> f64 {
-        self.temp
+    pub fn energy(&self) -> f64 {
+        self.energy
+    }
+
+    pub fn magnetization(&self) -> [f64; 3] {
+        self.magnetization
     }
 
-    pub fn heat(&mut self, delta: f64) {
-        self.temp += delta;
+    /// Account for a spin that went from `old` to `new`, changing the
+    /// energy by `delta`.
+    pub fn update<S: Spin>(&mut self, delta: f64, old: &S, new: &S) {
+        self.energy += delta;
+        let (old, new) = (old.magnetization(), new.magnetization());
+        for k in 0..3 {
+            self.magnetization[k] += new[k] - old[k];
+        }
     }
 
-    pub fn cool(&mut self, delta: f64) {
-        self.heat( - delta);
+    /// The largest difference with other totals, to tell how far running
+    /// totals drifted.
+    pub fn distance(&self, other: &Self) -> f64 {
+        (0..3).map(|k| (self.magnetization[k] - other.magnetization[k]).abs())
+            .fold((self.energy - other.energy).abs(), f64::max)
     }
 }
 
+pub trait StateGenerator<S: Spin> {
+    fn state(&mut self, nsites: usize) -> State<S>;
+}
 
-impl<S, T> Integrator<S, T> for MetropolisIntegrator where
-    S: Spin + Clone,
-    T: EnergyComponent<S>
-{
-    fn step(&mut self, energy: &T, state: &State<S>) -> State<S> {
+/// Integrators that sample at a temperature that can be changed.
+pub trait Thermostat {
+    fn temp(&self) -> f64;
+
+    /// Fails, leaving the temperature alone, unless `temp` is positive.
+    fn set_temp(&mut self, temp: f64) -> Result<(), VegasError>;
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MetropolisIntegrator {
+    rng: XorShift128,
+    temp: f64,
+}
+
+
+impl MetropolisIntegrator {
+    /// New up an integrator at a positive temperature.
+    pub fn new(temp: f64) -> Result<Self, VegasError> {
+        Ok(Self {
+            temp: check_temp(temp)?,
+            rng: XorShift128::new_unseeded(),
+        })
+    }
+
+    /// Seed the random number generator, runs with the same seed are
+    /// identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift128::seed_from_u64(seed);
+        self
+    }
+}
+
+
+impl MetropolisIntegrator {
+    fn sweep<S, T>(&mut self, energy: &T, state: &State<S>, mut totals: Option<&mut Totals>)
+        -> Result<State<S>, VegasError>
+        where S: Spin + Clone,
+              T: EnergyComponent<S>
+    {
+        energy.check(state)?;
         let mut new_state = (*state).clone();
+        energy.sync(&new_state);
         let sites = Range::new(0, new_state.len());
         for _ in 0..new_state.len() {
             let site = sites.ind_sample(&mut self.rng);
+            let old = new_state.at(site).clone();
             let old_energy = energy.energy(&new_state, site);
             new_state.set_at(site, Spin::rand(&mut self.rng));
             let new_energy = energy.energy(&new_state, site);
             let delta = new_energy - old_energy;
-            if delta < 0.0 {
-                continue
-            }
-            if self.rng.gen::<f64>() < (- delta / self.temp).exp() {
+            if delta < 0.0 || self.rng.gen::<f64>() < (- delta / self.temp).exp() {
+                if let Some(ref mut totals) = totals {
+                    totals.update(delta, &old, new_state.at(site));
+                }
+                energy.accept(&new_state, site);
                 continue
             }
-            new_state.set_at(site, state.at(site).clone());
+            new_state.set_at(site, old);
         }
-        new_state
+        Ok(new_state)
+    }
+}
+
+
+impl<S, T> Integrator<S, T> for MetropolisIntegrator where
+    S: Spin + Clone,
+    T: EnergyComponent<S>
+{
+    fn step(&mut self, energy: &T, state: &State<S>) -> Result<State<S>, VegasError> {
+        self.sweep(energy, state, None)
+    }
+
+    fn step_tracking(&mut self, energy: &T, state: &State<S>, totals: &mut Totals)
+        -> Result<State<S>, VegasError>
+    {
+        self.sweep(energy, state, Some(totals))
+    }
+}
+
+impl Thermostat for MetropolisIntegrator {
+    fn temp(&self) -> f64 {
+        self.temp
+    }
+
+    fn set_temp(&mut self, temp: f64) -> Result<(), VegasError> {
+        self.temp = check_temp(temp)?;
+        Ok(())
     }
 }
 
@@ -76,3 +184,287 @@ impl<S> StateGenerator<S> for MetropolisIntegrator where
         State::rand_with_size(nsites, &mut self.rng)
     }
 }
+
+
+/// A Metropolis integrator that updates all the sites of a color class at
+/// once, in parallel, a checkerboard decomposition on bipartite lattices.
+///
+/// Sites of a class do not interact, so updating them together is the same
+/// as updating them one after the other and every class update keeps
+/// detailed balance. The coloring only knows about the exchange, other
+/// terms of the Hamiltonian must depend on a single site. Classes are cut
+/// in chunks with a random number stream each, so runs are the same no
+/// matter how many threads there are.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CheckerboardIntegrator {
+    coloring: Coloring,
+    rng: XorShift128,
+    streams: Vec<XorShift128>,
+    temp: f64,
+}
+
+
+impl CheckerboardIntegrator {
+    /// New up an integrator for a system colored by `coloring`, use
+    /// `ExchangeEnergy::coloring` to get one.
+    pub fn new(temp: f64, coloring: Coloring) -> Result<Self, VegasError> {
+        let mut integrator = Self {
+            coloring,
+            rng: XorShift128::new_unseeded(),
+            streams: Vec::new(),
+            temp: check_temp(temp)?,
+        };
+        integrator.split_streams();
+        Ok(integrator)
+    }
+
+    /// Seed the random number generators, runs with the same seed are
+    /// identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift128::seed_from_u64(seed);
+        self.split_streams();
+        self
+    }
+
+    /// One stream for every chunk of the largest class, seeded out of the
+    /// main generator.
+    fn split_streams(&mut self) {
+        let largest = self.coloring.classes().iter().map(|c| c.len()).max().unwrap_or(0);
+        let rng = &mut self.rng;
+        self.streams = (0..largest.div_ceil(CHUNK))
+            .map(|_| XorShift128::seed_from_u64(rng.next_u64()))
+            .collect();
+    }
+
+    pub fn coloring(&self) -> &Coloring {
+        &self.coloring
+    }
+}
+
+
+impl CheckerboardIntegrator {
+    fn sweep<S, T>(&mut self, energy: &T, state: &State<S>, mut totals: Option<&mut Totals>)
+        -> Result<State<S>, VegasError>
+        where S: Spin + Clone + Send + Sync,
+              T: EnergyComponent<S> + Sync
+    {
+        if state.len() != self.coloring.len() {
+            return Err(VegasError::SizeMismatch(self.coloring.len(), state.len()));
+        }
+        energy.check(state)?;
+        let temp = self.temp;
+        let mut order: Vec<usize> = (0..self.coloring.ncolors()).collect();
+        self.rng.shuffle(&mut order);
+        let streams = &mut self.streams;
+        let mut state = state.clone();
+        energy.sync(&state);
+        // Same as `state` but for the proposals of the class at hand.
+        let mut trial = state.clone();
+        for color in order {
+            let class = &self.coloring.classes()[color];
+            let current = &state;
+            let proposals: Vec<Vec<(f64, S)>> = class.par_chunks(CHUNK)
+                .zip(streams.par_iter_mut())
+                .map(|(sites, rng)| {
+                    sites.iter()
+                        .map(|&site| (energy.energy(current, site), S::rand(rng)))
+                        .collect()
+                })
+                .collect();
+            for (&site, (_, spin)) in class.iter().zip(proposals.iter().flatten()) {
+                trial.set_at(site, spin.clone());
+            }
+            let proposed = &trial;
+            let accepted: Vec<Vec<Option<f64>>> = class.par_chunks(CHUNK)
+                .zip(streams.par_iter_mut())
+                .zip(proposals.par_iter())
+                .map(|((sites, rng), proposals)| {
+                    sites.iter()
+                        .zip(proposals.iter())
+                        .map(|(&site, &(old_energy, _))| {
+                            let delta = energy.energy(proposed, site) - old_energy;
+                            let accept = delta < 0.0 ||
+                                         rng.gen::<f64>() < (- delta / temp).exp();
+                            if accept { Some(delta) } else { None }
+                        })
+                        .collect()
+                })
+                .collect();
+            for (&site, accept) in class.iter().zip(accepted.into_iter().flatten()) {
+                match accept {
+                    Some(delta) => {
+                        if let Some(ref mut totals) = totals {
+                            totals.update(delta, state.at(site), trial.at(site));
+                        }
+                        state.set_at(site, trial.at(site).clone());
+                        energy.accept(&state, site);
+                    },
+                    None => trial.set_at(site, state.at(site).clone()),
+                }
+            }
+        }
+        Ok(state)
+    }
+}
+
+
+impl<S, T> Integrator<S, T> for CheckerboardIntegrator where
+    S: Spin + Clone + Send + Sync,
+    T: EnergyComponent<S> + Sync
+{
+    fn step(&mut self, energy: &T, state: &State<S>) -> Result<State<S>, VegasError> {
+        self.sweep(energy, state, None)
+    }
+
+    fn step_tracking(&mut self, energy: &T, state: &State<S>, totals: &mut Totals)
+        -> Result<State<S>, VegasError>
+    {
+        self.sweep(energy, state, Some(totals))
+    }
+}
+
+
+impl Thermostat for CheckerboardIntegrator {
+    fn temp(&self) -> f64 {
+        self.temp
+    }
+
+    fn set_temp(&mut self, temp: f64) -> Result<(), VegasError> {
+        self.temp = check_temp(temp)?;
+        Ok(())
+    }
+}
+
+
+impl<S> StateGenerator<S> for CheckerboardIntegrator where
+    S: Spin + Clone,
+{
+    fn state(&mut self, nsites: usize) -> State<S> {
+        State::rand_with_size(nsites, &mut self.rng)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{CheckerboardIntegrator, Integrator, MetropolisIntegrator, StateGenerator,
+                Thermostat, Totals};
+    use coloring::Coloring;
+    use energy::{CompoundEnergy, EnergyComponent, ExchangeEnergy, ZeemanEnergy};
+    use lattice::{LatticeBuilder, UnitCell};
+    use rayon::ThreadPoolBuilder;
+    use state::{HeisenbergSpin, IsingSpin, Spin, State};
+
+    #[test]
+    fn square_lattices_get_a_checkerboard() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(8, 8, 1).build().unwrap();
+        let coloring = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap().coloring();
+        assert_eq!(coloring.ncolors(), 2);
+        assert_eq!(coloring.classes()[0].len(), 32);
+        let lattice = LatticeBuilder::new(UnitCell::triangular()).extent(6, 6, 1).build().unwrap();
+        let coloring = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap().coloring();
+        assert_eq!(coloring.ncolors(), 3);
+    }
+
+    #[test]
+    fn runs_do_not_depend_on_the_number_of_threads() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(32, 32, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let run = |threads: usize| {
+            let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let mut integrator = CheckerboardIntegrator::new(2.0, exchange.coloring()).unwrap()
+                    .with_seed(11);
+                let mut state: State<IsingSpin> = integrator.state(1024);
+                for _ in 0..20 {
+                    state = integrator.step(&exchange, &state).unwrap();
+                }
+                state.magnetization()
+            })
+        };
+        assert_eq!(run(1), run(4));
+    }
+
+    #[test]
+    fn checkerboard_sweeps_sample_the_boltzmann_distribution() {
+        // Exact mean energy of a 4x4 periodic Ising ferromagnet at T = 2.5.
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let temp = 2.5;
+        let (mut z, mut sum) = (0.0, 0.0);
+        let mut state = State::<IsingSpin>::up_with_size(16);
+        for config in 0..(1 << 16) {
+            for i in 0..16 {
+                let spin = if config & (1 << i) == 0 { IsingSpin::Up } else { IsingSpin::Down };
+                state.set_at(i, spin);
+            }
+            let energy = exchange.total_energy(&state);
+            let weight = (- energy / temp).exp();
+            z += weight;
+            sum += weight * energy;
+        }
+        let exact = sum / z;
+
+        let mut integrator = CheckerboardIntegrator::new(temp, exchange.coloring()).unwrap()
+            .with_seed(3);
+        let mut state: State<IsingSpin> = integrator.state(16);
+        let sweeps = 40_000;
+        let mut mean = 0.0;
+        for _ in 0..sweeps {
+            state = integrator.step(&exchange, &state).unwrap();
+            mean += exchange.total_energy(&state) / sweeps as f64;
+        }
+        assert!((mean - exact).abs() < 0.3, "sampled {} exact {}", mean, exact);
+    }
+
+    #[test]
+    fn running_totals_follow_the_accepted_moves() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(12, 12, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let coloring = exchange.coloring();
+        let zeeman = ZeemanEnergy::new(HeisenbergSpin::up(), 1.0);
+        let hamiltonian = CompoundEnergy::new(exchange, zeeman);
+
+        let mut metropolis = MetropolisIntegrator::new(1.5).unwrap().with_seed(7);
+        let mut state: State<HeisenbergSpin> = metropolis.state(144);
+        let mut totals = Totals::of(&hamiltonian, &state);
+        for _ in 0..200 {
+            state = metropolis.step_tracking(&hamiltonian, &state, &mut totals).unwrap();
+        }
+        assert!(totals.distance(&Totals::of(&hamiltonian, &state)) < 1e-9);
+
+        let mut checkerboard = CheckerboardIntegrator::new(1.5, coloring).unwrap().with_seed(7);
+        let mut state: State<HeisenbergSpin> = checkerboard.state(144);
+        let mut totals = Totals::of(&hamiltonian, &state);
+        for _ in 0..200 {
+            state = checkerboard.step_tracking(&hamiltonian, &state, &mut totals).unwrap();
+        }
+        assert!(totals.distance(&Totals::of(&hamiltonian, &state)) < 1e-9);
+    }
+
+    #[test]
+    fn integrators_need_a_positive_temperature() {
+        assert!(MetropolisIntegrator::new(-1.0).is_err());
+        assert!(MetropolisIntegrator::new(0.0).is_err());
+        let coloring = Coloring::greedy(2, |i| vec![1 - i]);
+        assert!(CheckerboardIntegrator::new(f64::NAN, coloring.clone()).is_err());
+        let mut metropolis = MetropolisIntegrator::new(2.0).unwrap();
+        assert!(metropolis.set_temp(-1.0).is_err());
+        assert_eq!(metropolis.temp(), 2.0);
+        let mut checkerboard = CheckerboardIntegrator::new(2.0, coloring).unwrap();
+        assert!(checkerboard.set_temp(f64::NAN).is_err());
+        assert!(checkerboard.set_temp(1.0).is_ok());
+        assert_eq!(checkerboard.temp(), 1.0);
+    }
+
+    #[test]
+    fn integrators_reject_states_of_the_wrong_size() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let mut metropolis = MetropolisIntegrator::new(2.0).unwrap().with_seed(3);
+        let state: State<IsingSpin> = metropolis.state(9);
+        assert!(metropolis.step(&exchange, &state).is_err());
+        let mut checkerboard = CheckerboardIntegrator::new(2.0, exchange.coloring()).unwrap();
+        assert!(checkerboard.step(&exchange, &state).is_err());
+    }
+}
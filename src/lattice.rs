@@ -0,0 +1,524 @@
+//! Generators for common lattices, so simple simulations do not need a
+//! hand written lattice file.
+//!
+//! Lattices are built out of a rectangular unit cell, neighbors are found up
+//! to a given number of shells and every vertex is tagged with its shell
+//! (`"nn1"`, `"nn2"`, ...), so they can be told apart when building an
+//! `ExchangeEnergy`.
+//!
+//! Examples:
+//!
+//! ```
+//! extern crate vegas_rs;
+//! use vegas_rs::lattice::{LatticeBuilder, UnitCell};
+//! use vegas_rs::energy::ExchangeEnergy;
+//! use vegas_rs::state::{HeisenbergSpin, State};
+//! fn main() {
+//!     let lattice = LatticeBuilder::new(UnitCell::square())
+//!         .extent(8, 8, 1)
+//!         .shells(2)
+//!         .build()
+//!         .unwrap();
+//!     let _exchange = ExchangeEnergy::from_lattice(&lattice, |bond| {
+//!         if bond.has_tag("nn1") { 1.0 } else { -0.2 }
+//!     }).unwrap();
+//!     let _state = State::<HeisenbergSpin>::up_with_size(lattice.sites().len());
+//! }
+//! ```
+
+use vegas_lattice::{Axis, Lattice};
+
+use error::{invalid, VegasError};
+
+
+const TOLERANCE: f64 = 1e-8;
+
+
+/// A vertex of a unit cell as `(source, target, delta, shell)`.
+type CellVertex = (usize, usize, (i32, i32, i32), usize);
+
+
+/// A rectangular unit cell with a basis of sites.
+#[derive(Clone, Debug)]
+pub struct UnitCell {
+    size: (f64, f64, f64),
+    periodic: (bool, bool, bool),
+    sites: Vec<(String, (f64, f64, f64))>,
+}
+
+
+impl UnitCell {
+    /// New up an empty unit cell, `periodic` tells along which axes the cell
+    /// repeats, a square cell for instance only repeats along x and y.
+    pub fn new(size: (f64, f64, f64), periodic: (bool, bool, bool)) -> Self {
+        Self {
+            size,
+            periodic,
+            sites: Vec::new(),
+        }
+    }
+
+    /// Add a site to the basis of the cell.
+    pub fn with_site(mut self, kind: &str, position: (f64, f64, f64)) -> Self {
+        self.sites.push((kind.to_string(), position));
+        self
+    }
+
+    pub fn size(&self) -> (f64, f64, f64) {
+        self.size
+    }
+
+    pub fn sites(&self) -> &[(String, (f64, f64, f64))] {
+        &self.sites
+    }
+
+    /// A linear chain with unit spacing.
+    pub fn chain() -> Self {
+        Self::new((1.0, 1.0, 1.0), (true, false, false))
+            .with_site("A", (0.0, 0.0, 0.0))
+    }
+
+    /// A square lattice with unit spacing.
+    pub fn square() -> Self {
+        Self::new((1.0, 1.0, 1.0), (true, true, false))
+            .with_site("A", (0.0, 0.0, 0.0))
+    }
+
+    /// A triangular lattice with unit spacing, in its rectangular cell.
+    pub fn triangular() -> Self {
+        let h = 3f64.sqrt();
+        Self::new((1.0, h, 1.0), (true, true, false))
+            .with_site("A", (0.0, 0.0, 0.0))
+            .with_site("A", (0.5, 0.5 * h, 0.0))
+    }
+
+    /// A honeycomb lattice with unit bond length, sites are labeled by
+    /// sublattice (`"A"` and `"B"`).
+    pub fn honeycomb() -> Self {
+        let h = 3f64.sqrt();
+        Self::new((h, 3.0, 1.0), (true, true, false))
+            .with_site("A", (0.0, 0.0, 0.0))
+            .with_site("B", (0.0, 1.0, 0.0))
+            .with_site("A", (0.5 * h, 1.5, 0.0))
+            .with_site("B", (0.5 * h, 2.5, 0.0))
+    }
+
+    /// A kagome lattice with unit bond length, sites are labeled by
+    /// sublattice (`"A"`, `"B"` and `"C"`).
+    pub fn kagome() -> Self {
+        let h = 3f64.sqrt();
+        Self::new((2.0, 2.0 * h, 1.0), (true, true, false))
+            .with_site("A", (0.0, 0.0, 0.0))
+            .with_site("B", (1.0, 0.0, 0.0))
+            .with_site("C", (0.5, 0.5 * h, 0.0))
+            .with_site("A", (1.0, h, 0.0))
+            .with_site("B", (0.0, h, 0.0))
+            .with_site("C", (1.5, 1.5 * h, 0.0))
+    }
+
+    /// A simple cubic lattice with unit spacing.
+    pub fn cubic() -> Self {
+        Self::new((1.0, 1.0, 1.0), (true, true, true))
+            .with_site("A", (0.0, 0.0, 0.0))
+    }
+
+    /// A body centered cubic lattice in its conventional unit cell, corners
+    /// are labeled `"A"` and centers `"B"`.
+    pub fn bcc() -> Self {
+        Self::new((1.0, 1.0, 1.0), (true, true, true))
+            .with_site("A", (0.0, 0.0, 0.0))
+            .with_site("B", (0.5, 0.5, 0.5))
+    }
+
+    /// A face centered cubic lattice in its conventional unit cell, with
+    /// its four simple cubic sublattices labeled `"A"` to `"D"`.
+    pub fn fcc() -> Self {
+        Self::new((1.0, 1.0, 1.0), (true, true, true))
+            .with_site("A", (0.0, 0.0, 0.0))
+            .with_site("B", (0.5, 0.5, 0.0))
+            .with_site("C", (0.5, 0.0, 0.5))
+            .with_site("D", (0.0, 0.5, 0.5))
+    }
+
+    /// A pyrochlore lattice, corner sharing tetrahedra on an fcc lattice,
+    /// in its conventional cubic cell. The four corners of each tetrahedron
+    /// are labeled `"A"` to `"D"`.
+    pub fn pyrochlore() -> Self {
+        let fcc = [(0.0, 0.0, 0.0), (0.5, 0.5, 0.0), (0.5, 0.0, 0.5), (0.0, 0.5, 0.5)];
+        let tetrahedron = [
+            ("A", (0.0, 0.0, 0.0)),
+            ("B", (0.25, 0.25, 0.0)),
+            ("C", (0.25, 0.0, 0.25)),
+            ("D", (0.0, 0.25, 0.25)),
+        ];
+        let mut cell = Self::new((1.0, 1.0, 1.0), (true, true, true));
+        for &(x, y, z) in fcc.iter() {
+            for &(kind, (dx, dy, dz)) in tetrahedron.iter() {
+                cell = cell.with_site(kind, (x + dx, y + dy, z + dz));
+            }
+        }
+        cell
+    }
+
+    /// Find every vertex within `shells` neighbor shells, only one of a
+    /// vertex and its reverse is listed.
+    fn vertices(&self, shells: usize) -> Vec<CellVertex> {
+        let reach = shells as i32 + 1;
+        let range = |periodic: bool| if periodic { -reach..reach + 1 } else { 0..1 };
+        let mut candidates = Vec::new();
+        for (source, &(_, a)) in self.sites.iter().enumerate() {
+            for (target, &(_, b)) in self.sites.iter().enumerate() {
+                for dx in range(self.periodic.0) {
+                    for dy in range(self.periodic.1) {
+                        for dz in range(self.periodic.2) {
+                            let delta = (dx, dy, dz);
+                            if target < source || (target == source && delta <= (0, 0, 0)) {
+                                continue;
+                            }
+                            let distance = (
+                                (b.0 + dx as f64 * self.size.0 - a.0).powi(2) +
+                                (b.1 + dy as f64 * self.size.1 - a.1).powi(2) +
+                                (b.2 + dz as f64 * self.size.2 - a.2).powi(2)
+                            ).sqrt();
+                            candidates.push((source, target, delta, distance));
+                        }
+                    }
+                }
+            }
+        }
+        let mut distances: Vec<f64> = Vec::new();
+        let mut sorted: Vec<f64> = candidates.iter().map(|c| c.3).collect();
+        sorted.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        for distance in sorted {
+            if distances.last().is_none_or(|last| distance - last > TOLERANCE) {
+                distances.push(distance);
+            }
+        }
+        distances.truncate(shells);
+        candidates
+            .into_iter()
+            .filter_map(|(source, target, delta, distance)| {
+                distances.iter()
+                    .position(|d| (d - distance).abs() < TOLERANCE)
+                    .map(|shell| (source, target, delta, shell + 1))
+            })
+            .collect()
+    }
+}
+
+
+/// Build a lattice by repeating a unit cell.
+#[derive(Clone, Debug)]
+pub struct LatticeBuilder {
+    cell: UnitCell,
+    extent: (usize, usize, usize),
+    open: (bool, bool, bool),
+    shells: usize,
+}
+
+
+impl LatticeBuilder {
+    /// New up a builder for a single periodic cell with nearest neighbors.
+    pub fn new(cell: UnitCell) -> Self {
+        Self {
+            cell,
+            extent: (1, 1, 1),
+            open: (false, false, false),
+            shells: 1,
+        }
+    }
+
+    /// How many times to repeat the unit cell along each axis.
+    pub fn extent(mut self, nx: usize, ny: usize, nz: usize) -> Self {
+        self.extent = (nx, ny, nz);
+        self
+    }
+
+    /// Use open boundary conditions along an axis, boundaries are periodic
+    /// by default.
+    pub fn open_along(mut self, axis: Axis) -> Self {
+        match axis {
+            Axis::X => self.open.0 = true,
+            Axis::Y => self.open.1 = true,
+            Axis::Z => self.open.2 = true,
+        };
+        self
+    }
+
+    /// How many neighbor shells to connect, 1 for nearest neighbors only.
+    pub fn shells(mut self, shells: usize) -> Self {
+        self.shells = shells;
+        self
+    }
+
+    /// Build the lattice, every axis must hold at least one cell and at
+    /// least one shell must be connected.
+    pub fn build(&self) -> Result<Lattice, VegasError> {
+        let (nx, ny, nz) = self.extent;
+        if nx == 0 || ny == 0 || nz == 0 {
+            return invalid(format!("every axis needs at least one cell, got {}x{}x{}",
+                                   nx, ny, nz));
+        }
+        if self.shells == 0 {
+            return invalid("at least one neighbor shell is needed".to_string());
+        }
+        let sites: Vec<_> = self.cell.sites
+            .iter()
+            .map(|&(ref kind, position)| json!({"kind": kind, "position": position}))
+            .collect();
+        let vertices: Vec<_> = self.cell.vertices(self.shells)
+            .into_iter()
+            .map(|(source, target, delta, shell)| json!({
+                "source": source,
+                "target": target,
+                "delta": delta,
+                "tags": [format!("nn{}", shell)],
+            }))
+            .collect();
+        let cell = json!({
+            "size": self.cell.size,
+            "sites": sites,
+            "vertices": vertices,
+        });
+        let mut lattice: Lattice = cell.to_string()
+            .parse()
+            .expect("generated lattices are always valid");
+        let axes = [
+            (Axis::X, self.extent.0, self.open.0),
+            (Axis::Y, self.extent.1, self.open.1),
+            (Axis::Z, self.extent.2, self.open.2),
+        ];
+        for &(axis, extent, open) in axes.iter() {
+            lattice = lattice.expand_along(axis, extent);
+            if open {
+                lattice = lattice.drop(axis);
+            }
+        }
+        Ok(lattice)
+    }
+}
+
+
+/// Positions and kinds of the sites of a lattice, with the periodicity of
+/// the simulation box, for measurements that depend on where sites are.
+#[derive(Clone, Debug)]
+pub struct Geometry {
+    positions: Vec<[f64; 3]>,
+    kinds: Vec<String>,
+    size: [f64; 3],
+    periodic: [bool; 3],
+}
+
+
+impl Geometry {
+    pub fn new(positions: Vec<[f64; 3]>, size: [f64; 3], periodic: [bool; 3]) -> Self {
+        let kinds = vec!["A".to_string(); positions.len()];
+        Self {
+            positions,
+            kinds,
+            size,
+            periodic,
+        }
+    }
+
+    /// Read the geometry of a lattice, an axis is taken as periodic if any
+    /// vertex wraps around it.
+    pub fn from_lattice(lattice: &Lattice) -> Self {
+        let (x, y, z) = lattice.size();
+        let wraps = |axis: Axis| lattice.vertices().iter().any(|v| v.delta_along(axis) != 0);
+        Self {
+            positions: lattice.sites()
+                .iter()
+                .map(|s| {
+                    let (x, y, z) = s.position();
+                    [x, y, z]
+                })
+                .collect(),
+            kinds: lattice.sites().iter().map(|s| s.kind()).collect(),
+            size: [x, y, z],
+            periodic: [wraps(Axis::X), wraps(Axis::Y), wraps(Axis::Z)],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn positions(&self) -> &[[f64; 3]] {
+        &self.positions
+    }
+
+    pub fn kinds(&self) -> &[String] {
+        &self.kinds
+    }
+
+    pub fn size(&self) -> [f64; 3] {
+        self.size
+    }
+
+    pub fn periodic(&self) -> [bool; 3] {
+        self.periodic
+    }
+
+    /// The vector from site `i` to site `j`, using the closest periodic
+    /// image of `j`.
+    pub fn displacement(&self, i: usize, j: usize) -> [f64; 3] {
+        let mut d = [0.0; 3];
+        for (k, d) in d.iter_mut().enumerate() {
+            *d = self.positions[j][k] - self.positions[i][k];
+            if self.periodic[k] && self.size[k] > 0.0 {
+                *d -= self.size[k] * (*d / self.size[k]).round();
+            }
+        }
+        d
+    }
+
+    pub fn distance(&self, i: usize, j: usize) -> f64 {
+        let d = self.displacement(i, j);
+        (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt()
+    }
+
+    /// If the sites fill a regular grid, with an axis being either periodic
+    /// or a single layer thick, get the grid shape and the grid index of
+    /// every site.
+    pub fn grid(&self) -> Option<([usize; 3], Vec<[usize; 3]>)> {
+        if self.positions.is_empty() {
+            return None;
+        }
+        let mut shape = [1; 3];
+        let mut origin = [0.0; 3];
+        for k in 0..3 {
+            let mut coords: Vec<f64> = self.positions.iter().map(|p| p[k]).collect();
+            coords.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            coords.dedup_by(|a, b| (*a - *b).abs() < TOLERANCE);
+            if coords.len() > 1 && !self.periodic[k] {
+                return None;
+            }
+            shape[k] = coords.len();
+            origin[k] = coords[0];
+        }
+        if shape.iter().product::<usize>() != self.positions.len() {
+            return None;
+        }
+        let mut seen = vec![false; self.positions.len()];
+        let mut indices = Vec::with_capacity(self.positions.len());
+        for p in self.positions.iter() {
+            let mut index = [0; 3];
+            for k in 0..3 {
+                if shape[k] == 1 {
+                    continue;
+                }
+                let spacing = self.size[k] / shape[k] as f64;
+                let m = (p[k] - origin[k]) / spacing;
+                if (m - m.round()).abs() > 1e-6 || m.round() as usize >= shape[k] {
+                    return None;
+                }
+                index[k] = m.round() as usize;
+            }
+            let flat = (index[0] * shape[1] + index[1]) * shape[2] + index[2];
+            if seen[flat] {
+                return None;
+            }
+            seen[flat] = true;
+            indices.push(index);
+        }
+        Some((shape, indices))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Geometry, LatticeBuilder, UnitCell};
+    use energy::{EnergyComponent, ExchangeEnergy};
+    use state::{HeisenbergSpin, State};
+    use vegas_lattice::Axis;
+
+    fn coordination(builder: LatticeBuilder, shell: &str) -> Vec<f64> {
+        let lattice = builder.build().unwrap();
+        let tag = shell.to_string();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |bond| {
+            if bond.has_tag(&tag) { 1.0 } else { 0.0 }
+        }).unwrap();
+        let state = State::<HeisenbergSpin>::up_with_size(lattice.sites().len());
+        (0..state.len()).map(|i| - exchange.energy(&state, i)).collect()
+    }
+
+    fn assert_coordination(cell: UnitCell, extent: (usize, usize, usize), z: f64) {
+        let builder = LatticeBuilder::new(cell).extent(extent.0, extent.1, extent.2);
+        for n in coordination(builder, "nn1") {
+            assert!((n - z).abs() < 1e-12, "expected {} neighbors, got {}", z, n);
+        }
+    }
+
+    #[test]
+    fn coordination_numbers_of_common_lattices() {
+        assert_coordination(UnitCell::chain(), (5, 1, 1), 2.0);
+        assert_coordination(UnitCell::square(), (5, 5, 1), 4.0);
+        assert_coordination(UnitCell::triangular(), (5, 3, 1), 6.0);
+        assert_coordination(UnitCell::honeycomb(), (4, 3, 1), 3.0);
+        assert_coordination(UnitCell::kagome(), (3, 3, 1), 4.0);
+        assert_coordination(UnitCell::cubic(), (4, 4, 4), 6.0);
+        assert_coordination(UnitCell::bcc(), (3, 3, 3), 8.0);
+        assert_coordination(UnitCell::fcc(), (3, 3, 3), 12.0);
+        assert_coordination(UnitCell::pyrochlore(), (2, 2, 2), 6.0);
+    }
+
+    #[test]
+    fn further_neighbor_shells_are_tagged() {
+        let builder = LatticeBuilder::new(UnitCell::square()).extent(6, 6, 1).shells(3);
+        for n in coordination(builder.clone(), "nn2") {
+            assert!((n - 4.0).abs() < 1e-12);
+        }
+        for n in coordination(builder, "nn3") {
+            assert!((n - 4.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn open_boundaries_drop_wrapping_bonds() {
+        let builder = LatticeBuilder::new(UnitCell::square())
+            .extent(4, 4, 1)
+            .open_along(Axis::X);
+        let lattice = builder.build().unwrap();
+        assert_eq!(lattice.sites().len(), 16);
+        assert_eq!(lattice.vertices().len(), 28);
+        let total: f64 = coordination(builder, "nn1").iter().sum();
+        assert!((total - 56.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn geometry_of_a_generated_lattice() {
+        let lattice = LatticeBuilder::new(UnitCell::square())
+            .extent(4, 3, 1)
+            .open_along(Axis::Y)
+            .build().unwrap();
+        let geometry = Geometry::from_lattice(&lattice);
+        assert_eq!(geometry.len(), 12);
+        assert_eq!(geometry.periodic(), [true, false, false]);
+        // Sites 0 and 3 sit at the ends of the periodic x axis, while 0 and 8
+        // are at the ends of the open y axis.
+        assert!((geometry.distance(0, 3) - 1.0).abs() < 1e-12);
+        assert!((geometry.distance(0, 8) - 2.0).abs() < 1e-12);
+        assert!(geometry.grid().is_none());
+        let periodic = Geometry::from_lattice(&LatticeBuilder::new(UnitCell::square())
+            .extent(4, 3, 1)
+            .build().unwrap());
+        let (shape, _) = periodic.grid().unwrap();
+        assert_eq!(shape, [4, 3, 1]);
+        let triangular = Geometry::from_lattice(&LatticeBuilder::new(UnitCell::triangular())
+            .extent(4, 2, 1)
+            .build().unwrap());
+        assert!(triangular.grid().is_none());
+    }
+
+    #[test]
+    fn empty_axes_are_rejected() {
+        assert!(LatticeBuilder::new(UnitCell::square()).extent(4, 0, 1).build().is_err());
+        assert!(LatticeBuilder::new(UnitCell::square()).shells(0).build().is_err());
+    }
+}
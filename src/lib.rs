@@ -1,8 +1,34 @@
 //! Library to create Monte Carlo simulations.
 
 extern crate rand;
+extern crate rayon;
+extern crate rustfft;
+extern crate serde;
+#[macro_use] extern crate serde_json;
 extern crate sprs;
+extern crate toml;
+extern crate vegas_lattice;
 
 pub mod state;
 pub mod energy;
 pub mod integrator;
+pub mod lattice;
+pub mod checkpoint;
+pub mod coloring;
+pub mod config;
+pub mod correlation;
+pub mod error;
+pub mod exact;
+pub mod field;
+pub mod multicanonical;
+pub mod observables;
+pub mod output;
+pub mod population;
+pub mod reweighting;
+pub mod rng;
+pub mod schedule;
+pub mod simulation;
+pub mod snapshot;
+pub mod soa;
+pub mod statistics;
+pub mod topology;
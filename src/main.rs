@@ -1,109 +1,608 @@
 #[macro_use] extern crate vegas_rs;
 extern crate docopt;
+extern crate serde;
+#[macro_use] extern crate serde_json;
 extern crate vegas_lattice;
-extern crate sprs;
 
 
 use std::error::Error;
-use std::fs::File;
-use std::io::Read;
+use std::fmt::Display;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::str::FromStr;
 
-use docopt::Docopt;
-use sprs::TriMat;
+use docopt::{ArgvMap, Docopt};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 use vegas_lattice::Lattice;
 
-use vegas_rs::state::{State, HeisenbergSpin};
-use vegas_rs::energy::{EnergyComponent, Gauge, ExchangeEnergy};
-use vegas_rs::integrator::{Integrator, StateGenerator, MetropolisIntegrator};
+use vegas_rs::checkpoint::{self, Checkpoint, CheckpointError};
+use vegas_rs::config::{Config, IntegratorKind, SpinModel};
+use vegas_rs::state::{HeisenbergSpin, IsingSpin, OrientableSpin, Spin, State};
+use vegas_rs::energy::{EnergyComponent, EnergySum, Gauge, ExchangeEnergy};
+use vegas_rs::error::VegasError;
+use vegas_rs::integrator::{CheckerboardIntegrator, Integrator, MetropolisIntegrator,
+                           StateGenerator, Thermostat};
+use vegas_rs::lattice::Geometry;
+use vegas_rs::observables::{Observables, OrderParameter};
+use vegas_rs::output::{self, Metadata, ResultsWriter};
+use vegas_rs::reweighting::Reweighting;
+use vegas_rs::schedule::{Equilibration, Schedule};
+use vegas_rs::simulation::{Advance, Simulation, Stage};
+use vegas_rs::snapshot::{self, Format, Snapshots};
+use vegas_rs::topology::Triangulation;
 
 
-const USAGE: &'static str = "
+const USAGE: &str = "
 Vegas rust.
 
 Usage:
-  vegas bench
-  vegas lattice <lattice>
+  vegas bench [options]
+  vegas lattice <lattice> [options]
+  vegas run <config>
+  vegas resume <checkpoint>
   vegas (-h | --help)
   vegas --version
 
 Options:
-  -h --help     Show this screen.
-  --version     Show version.
+  -h --help                   Show this screen.
+  --version                   Show version.
+  --schedule=<kind>           Temperature schedule: linear, geometric, adaptive
+                              or explicit [default: linear].
+  --start=<temp>              Starting temperature [default: 3.0].
+  --stop=<temp>               Final temperature [default: 0.1].
+  --step=<step>               Temperature step, the ratio between temperatures
+                              for geometric schedules and the largest step for
+                              adaptive ones [default: 0.1].
+  --temps=<temps>             Comma separated temperatures of an explicit
+                              schedule [default: 3.0,2.0,1.0].
+  --thermalization=<sweeps>   Sweeps discarded at every temperature before
+                              measuring [default: 0].
+  --measurement=<sweeps>      Sweeps measured at every temperature [default: 1000].
+  --equilibration=<method>    Drop the transient left after thermalizing,
+                              fixed or mser [default: mser].
+  --checkpoint=<file>         Save a checkpoint after every temperature, resume
+                              from it with `vegas resume <file>`.
+  --checkpoint-every=<n>      Also save a checkpoint every <n> sweeps within a
+                              temperature, 0 for never [default: 1000].
+  --snapshots=<sweeps>        Save a snapshot of the spins every so many sweeps,
+                              0 for never [default: 0].
+  --snapshot-prefix=<prefix>  Snapshots go to <prefix>-<sweep>.<format>
+                              [default: snapshot].
+  --snapshot-format=<format>  Snapshot format: xyz, vtk, vtp or csv
+                              [default: vtp].
+  --initial=<snapshot>        Start from the spins of a snapshot instead of
+                              random ones.
+  --topological-charge        Track the topological charge of a planar lattice.
+  --output=<file>             Write the results to a file instead of the
+                              standard output.
+  --format=<format>           Results format: text, csv, jsonl or binary, after
+                              the extension of the output if not given.
 ";
 
-const VERSION: &'static str = "
+const VERSION: &str = "
 Vegas rust, version: 0.1.0
 ";
 
 
-fn cool_down<T: EnergyComponent<HeisenbergSpin>>(hamiltonian: T, len: usize) {
-    let mut integrator = MetropolisIntegrator::new(3.0);
-    let mut state: State<HeisenbergSpin> = integrator.state(len);
+/// The systems the program knows how to simulate, kept in checkpoints to
+/// rebuild the Hamiltonian when resuming.
+#[derive(Serialize, Deserialize)]
+enum Model {
+    Bench,
+    Lattice(Lattice),
+    Run(Box<Config>),
+}
+
+
+/// What a run needs besides the simulation itself, kept in checkpoints to
+/// carry on when resuming.
+#[derive(Serialize, Deserialize)]
+struct Context {
+    model: Model,
+    snapshots: Option<Snapshots>,
+    #[serde(default)]
+    results: Option<String>,
+    #[serde(default)]
+    format: output::Format,
+    /// Sweeps between checkpoints within a temperature.
+    #[serde(default = "default_checkpoint_every")]
+    checkpoint_every: usize,
+}
+
+
+fn default_checkpoint_every() -> usize {
+    checkpoint::INTERVAL
+}
+
+
+/// Where the results, checkpoints and snapshots of a run go.
+struct Run {
+    context: Value,
+    metadata: Metadata,
+    output: Box<dyn Write>,
+    format: output::Format,
+    /// Whether the output still needs the metadata and column names.
+    header: bool,
+    checkpoint: Option<String>,
+    checkpoint_every: usize,
+    geometry: Geometry,
+    snapshots: Option<Snapshots>,
+}
+
+
+fn anneal<I, S, T>(hamiltonian: T, mut simulation: Simulation<I, S>, mut stages: Vec<Stage>,
+                   run: &mut Run) -> Result<(), VegasError>
+    where I: Integrator<S, T> + Thermostat + Serialize + DeserializeOwned,
+          S: Spin + Clone + Serialize + DeserializeOwned,
+          T: EnergyComponent<S>
+{
+    hamiltonian.check(simulation.state())?;
+    let labels: Vec<String> = hamiltonian.breakdown(simulation.state())
+        .into_iter()
+        .map(|(label, _)| label)
+        .collect();
+    let columns = output::columns(&labels, simulation.observables());
+    let mut results = ResultsWriter::new(&mut run.output, run.format, columns);
+    if !stages.is_empty() {
+        eprintln!("# Resuming after {} temperatures", stages.len());
+    }
+    if run.header {
+        results.header(&run.metadata)?;
+    }
+    let (snapshots, geometry) = (&run.snapshots, &run.geometry);
+    let every = if run.checkpoint.is_some() && run.checkpoint_every > 0 {
+        run.checkpoint_every
+    } else {
+        usize::MAX
+    };
+    let mut failure = None;
     loop {
-        let steps = 1000;
-        let mut energy_sum = 0.0;
-        for _ in 0..steps {
-            state = integrator.step(&hamiltonian, &state);
-            energy_sum += hamiltonian.total_energy(&state)
+        let advance = simulation.advance_for(&hamiltonian, every, |sweep, state| {
+            if let Some(ref snapshots) = *snapshots {
+                if let Err(e) = snapshots.observe(sweep, geometry, state) {
+                    failure.get_or_insert(e);
+                }
+            }
+        })?;
+        if let Some(e) = failure.take() {
+            return Err(e.into());
+        }
+        match advance {
+            Advance::Stage(stage) => {
+                results.row(&output::values(&stage))?;
+                stages.push(*stage);
+                drop_series(&mut stages);
+            },
+            Advance::Paused => (),
+            Advance::Finished => break,
+        }
+        if let Some(ref path) = run.checkpoint {
+            Checkpoint::save(path, &run.context, &simulation, &stages)?;
+        }
+    }
+    report_peaks(&mut results, &stages)?;
+    Ok(())
+}
+
+
+fn invalid<E: Display>(option: &str, e: E) -> VegasError {
+    VegasError::InvalidParameter(format!("{}: {}", option, e))
+}
+
+
+/// Parse the value of a command line option.
+fn parse<T>(args: &ArgvMap, option: &str) -> Result<T, VegasError>
+    where T: FromStr,
+          T::Err: Display
+{
+    args.get_str(option).parse().map_err(|e| invalid(option, e))
+}
+
+
+/// Build the temperature schedule out of the command line options.
+fn schedule(args: &ArgvMap) -> Result<Schedule, VegasError> {
+    let start: f64 = parse(args, "--start")?;
+    let stop: f64 = parse(args, "--stop")?;
+    let step: f64 = parse(args, "--step")?;
+    let thermalization: usize = parse(args, "--thermalization")?;
+    let measurement: usize = parse(args, "--measurement")?;
+    let schedule = match args.get_str("--schedule") {
+        "linear" => Schedule::linear(start, stop, step)?,
+        "geometric" => Schedule::geometric(start, stop, step)?,
+        "adaptive" => Schedule::adaptive(start, stop, step / 10.0, step)?,
+        "explicit" => {
+            let temps = args.get_str("--temps")
+                .split(',')
+                .map(|t| t.trim().parse())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|e| invalid("--temps", e))?;
+            Schedule::explicit(temps)?
+        },
+        other => return Err(invalid("--schedule", format!("unknown schedule {}", other))),
+    };
+    let equilibration = match args.get_str("--equilibration") {
+        "fixed" => Equilibration::Fixed,
+        "mser" => Equilibration::Mser,
+        other => {
+            return Err(invalid("--equilibration", format!("unknown equilibration {}", other)))
+        },
+    };
+    Ok(schedule.with_sweeps(thermalization, measurement)?.with_equilibration(equilibration))
+}
+
+
+/// Stages on either side of the largest specific heat that get reweighted.
+const PEAK_WINDOW: usize = 2;
+
+
+/// The stage with the largest finite specific heat.
+fn peak(stages: &[Stage]) -> Option<usize> {
+    let cvs: Vec<f64> = stages.iter().map(|s| s.observables().specific_heat(s.temp())).collect();
+    (0..cvs.len())
+        .filter(|&k| cvs[k].is_finite())
+        .max_by(|&a, &b| cvs[a].total_cmp(&cvs[b]))
+}
+
+
+/// Forget the samples of the stages that cannot end up reweighted, so that
+/// memory and checkpoints stay bounded. Those are the stages away from the
+/// peak so far, except the last few, which a later peak would take.
+fn drop_series(stages: &mut [Stage]) {
+    let best = match peak(stages) {
+        Some(best) => best,
+        None => return,
+    };
+    let recent = stages.len().saturating_sub(PEAK_WINDOW);
+    for (k, stage) in stages.iter_mut().enumerate() {
+        if k + PEAK_WINDOW < best || (k > best + PEAK_WINDOW && k < recent) {
+            stage.drop_series();
         }
-        println!("{} {}", integrator.temp(), energy_sum / steps as f64);
-        if integrator.temp() < 0.1 { break }
-        integrator.cool(0.1);
     }
 }
 
 
+/// Locate the specific heat and susceptibility peaks by reweighting the runs
+/// around the largest measured specific heat.
+fn report_peaks<W: Write>(results: &mut ResultsWriter<W>, stages: &[Stage])
+    -> Result<(), VegasError>
+{
+    let best = match peak(stages) {
+        Some(best) => best,
+        None => return Ok(()),
+    };
+    let window: Vec<(f64, &Observables)> = stages.iter()
+        .skip(best.saturating_sub(PEAK_WINDOW))
+        .take(2 * PEAK_WINDOW + 1)
+        .map(|s| (s.temp(), s.observables()))
+        .collect();
+    let reweighting = Reweighting::multiple(&window)?;
+    let (cv_temp, cv) = reweighting.specific_heat_peak();
+    let (chi_temp, chi) = reweighting.susceptibility_peak();
+    results.summary(&[
+        ("specific_heat_peak_temp", cv_temp),
+        ("specific_heat_peak", cv),
+        ("susceptibility_peak_temp", chi_temp),
+        ("susceptibility_peak", chi),
+    ])?;
+    Ok(())
+}
+
+
+
+/// A fresh simulation of `len` Heisenberg spins, random unless the options
+/// point to a snapshot to start from. The samples are kept to reweight the
+/// peaks.
+fn simulation(args: &ArgvMap, len: usize, observables: Observables)
+    -> Result<Simulation<MetropolisIntegrator, HeisenbergSpin>, VegasError>
+{
+    let mut integrator = MetropolisIntegrator::new(3.0)?;
+    let state: State<HeisenbergSpin> = match args.get_str("--initial") {
+        "" => integrator.state(len),
+        path => {
+            let state = snapshot::load(path)?.into_state();
+            if state.len() != len {
+                return Err(VegasError::SizeMismatch(len, state.len()));
+            }
+            state
+        },
+    };
+    let schedule = schedule(args)?;
+    Ok(Simulation::new(integrator, state, schedule).with_observables(observables.with_series()))
+}
+
 
-fn bench() {
+fn bench<I, S>(simulation: Simulation<I, S>, stages: Vec<Stage>, run: &mut Run)
+    -> Result<(), VegasError>
+    where I: Integrator<S, Gauge> + Thermostat + Serialize + DeserializeOwned,
+          S: Spin + Clone + Serialize + DeserializeOwned
+{
     let hamiltonian = hamiltonian!(
         Gauge::new(10.0)
     );
-    cool_down(hamiltonian, 100);
+    anneal(hamiltonian, simulation, stages, run)
 }
 
 
-fn bench_lattice(input: &str) -> Result<(), Box<Error>> {
+fn read_lattice(input: &str) -> Result<Lattice, VegasError> {
     let mut data = String::new();
     let mut file = File::open(input)?;
     file.read_to_string(&mut data)?;
     let lattice: Lattice = data.parse()?;
-    println!("# Successfuly read the lattice!");
+    eprintln!("# Successfuly read the lattice!");
+    Ok(lattice)
+}
+
+
+/// Observables for a lattice, resolving the magnetization by sublattice when
+/// there is more than one, and tracking the topological charge when asked
+/// to, which only planar lattices have.
+fn lattice_observables(lattice: &Lattice, topological_charge: bool)
+    -> Result<Observables, VegasError>
+{
+    let geometry = Geometry::from_lattice(lattice);
+    let sublattices = OrderParameter::sublattices(&geometry);
+    let orders = if sublattices.len() > 1 { sublattices } else { Vec::new() };
+    let observables = Observables::new(geometry.len()).with_order_parameters(orders);
+    if !topological_charge {
+        return Ok(observables);
+    }
+    let planar = geometry.positions().iter().all(|p| p[2] == geometry.positions()[0][2]);
+    if !planar {
+        return Err(invalid("--topological-charge", "the lattice is not planar"));
+    }
+    Ok(observables.with_topological_charge(Triangulation::from_geometry(&geometry)))
+}
 
+
+fn bench_lattice<I, S>(lattice: &Lattice, simulation: Simulation<I, S>, stages: Vec<Stage>,
+                       run: &mut Run) -> Result<(), VegasError>
+    where I: Integrator<S, ExchangeEnergy> + Thermostat + Serialize + DeserializeOwned,
+          S: Spin + Clone + Serialize + DeserializeOwned
+{
     let nsites = lattice.sites().len();
+    let exchange = ExchangeEnergy::from_lattice(lattice, |_| 1.0)?;
 
-    let mut mat = TriMat::new((nsites, nsites));
-    for vertex in lattice.vertices() {
-        if vertex.source() <= vertex.target() {
-            mat.add_triplet(vertex.source(), vertex.target(), 1.0);
-            mat.add_triplet(vertex.target(), vertex.source(), 1.0);
-        }
+    eprintln!("# Simulating with {} sites", nsites);
+    eprintln!("# Simulating with {} exchanges", lattice.vertices().len());
+
+    let hamiltonian = hamiltonian!(exchange);
+    anneal(hamiltonian, simulation, stages, run)
+}
+
+
+/// Anneal the system described by a config file.
+fn run_config<I, S>(config: &Config, lattice: &Lattice, simulation: Simulation<I, S>,
+                    stages: Vec<Stage>, run: &mut Run) -> Result<(), VegasError>
+    where I: Integrator<S, EnergySum<S>> + Thermostat + Serialize + DeserializeOwned,
+          S: OrientableSpin + Clone + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    let hamiltonian = config.hamiltonian::<S>(lattice)?;
+    anneal(hamiltonian, simulation, stages, run)
+}
+
+
+/// Results go to the results file, appended to when resuming, and to the
+/// standard output otherwise.
+fn output(context: &Context, resuming: bool) -> Result<Box<dyn Write>, VegasError> {
+    match context.results {
+        Some(ref path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(resuming)
+                .truncate(!resuming)
+                .open(path)?;
+            Ok(Box::new(BufWriter::new(file)))
+        },
+        None => Ok(Box::new(io::stdout())),
+    }
+}
+
+
+/// What the results say about the run.
+fn metadata(context: &Context, lattice: Option<&Lattice>, sites: usize) -> Metadata {
+    match context.model {
+        Model::Bench => Metadata::new(json!([{"term": "gauge", "value": 10.0}]), sites),
+        Model::Lattice(ref lattice) => {
+            Metadata::new(json!([{"term": "exchange", "coupling": 1.0}]), sites)
+                .with_lattice(lattice)
+        },
+        Model::Run(ref config) => Metadata::new(config.terms(), sites)
+            .with_seed(config.seed())
+            .with_lattice(lattice.expect("config runs build their lattice")),
     }
+}
 
-    let csr = mat.to_csr();
 
-    println!("# Simulating with {} sites", nsites);
-    println!("# Simulating with {} exchanges", lattice.vertices().len());
+/// The integrators of the program, able to integrate every model.
+trait ModelIntegrator<S: Spin>: Integrator<S, Gauge> + Integrator<S, ExchangeEnergy> +
+                                Integrator<S, EnergySum<S>> + Thermostat +
+                                Serialize + DeserializeOwned {}
 
-    let hamiltonian = hamiltonian!(
-        ExchangeEnergy::new(csr)
-    );
 
-    cool_down(hamiltonian, nsites);
-    Ok(())
+impl<I, S> ModelIntegrator<S> for I
+    where S: Spin,
+          I: Integrator<S, Gauge> + Integrator<S, ExchangeEnergy> +
+             Integrator<S, EnergySum<S>> + Thermostat + Serialize + DeserializeOwned
+{}
+
+
+fn simulate<I, S>(context: Context, simulation: Simulation<I, S>, stages: Vec<Stage>,
+                  checkpoint: Option<String>) -> Result<(), VegasError>
+    where I: ModelIntegrator<S>,
+          S: OrientableSpin + Clone + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    let lattice = match context.model {
+        Model::Run(ref config) => Some(config.lattice()?),
+        _ => None,
+    };
+    let geometry = match context.model {
+        Model::Bench => {
+            let len = simulation.state().len();
+            let positions = (0..len).map(|i| [i as f64, 0.0, 0.0]).collect();
+            Geometry::new(positions, [len as f64, 1.0, 1.0], [true, false, false])
+        },
+        Model::Lattice(ref lattice) => Geometry::from_lattice(lattice),
+        Model::Run(_) => Geometry::from_lattice(lattice.as_ref().unwrap()),
+    };
+    let resuming = !stages.is_empty();
+    let mut run = Run {
+        context: serde_json::to_value(&context).expect("contexts serialize"),
+        metadata: metadata(&context, lattice.as_ref(), simulation.state().len()),
+        output: output(&context, resuming)?,
+        format: context.format,
+        header: !resuming || context.results.is_none(),
+        checkpoint,
+        checkpoint_every: context.checkpoint_every,
+        geometry,
+        snapshots: context.snapshots.clone(),
+    };
+    match context.model {
+        Model::Bench => bench(simulation, stages, &mut run),
+        Model::Lattice(ref lattice) => bench_lattice(lattice, simulation, stages, &mut run),
+        Model::Run(ref config) => {
+            run_config(config, lattice.as_ref().unwrap(), simulation, stages, &mut run)
+        },
+    }
+}
+
+
+/// Start the run described by a config file, from scratch.
+fn start_config<I, S>(config: Config, lattice: Lattice, mut integrator: I)
+    -> Result<(), VegasError>
+    where I: ModelIntegrator<S> + StateGenerator<S>,
+          S: OrientableSpin + Clone + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    let geometry = Geometry::from_lattice(&lattice);
+    let state: State<S> = config.initial_state(&mut integrator, geometry.len())?;
+    let simulation = Simulation::new(integrator, state, config.schedule())
+        .with_observables(config.observables(&geometry).with_series());
+    let checkpoint = config.checkpoint().map(|path| path.to_string());
+    let context = Context {
+        snapshots: config.snapshots(),
+        results: config.results().map(|path| path.to_string()),
+        format: config.results_format(),
+        checkpoint_every: config.checkpoint_every(),
+        model: Model::Run(Box::new(config)),
+    };
+    simulate(context, simulation, Vec::new(), checkpoint)
+}
+
+
+fn start_spin<S>(config: Config) -> Result<(), VegasError>
+    where S: OrientableSpin + Clone + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    let lattice = config.lattice()?;
+    match config.integrator_kind() {
+        IntegratorKind::Metropolis => {
+            let integrator = config.integrator();
+            start_config::<_, S>(config, lattice, integrator)
+        },
+        IntegratorKind::Checkerboard => {
+            let integrator = config.checkerboard_integrator(&lattice)?;
+            start_config::<_, S>(config, lattice, integrator)
+        },
+    }
+}
+
+
+fn start_run(path: &str) -> Result<(), VegasError> {
+    let config = Config::load(path)?;
+    match config.spin() {
+        SpinModel::Ising => start_spin::<IsingSpin>(config),
+        SpinModel::Heisenberg => start_spin::<HeisenbergSpin>(config),
+    }
+}
+
+
+fn start(args: &ArgvMap) -> Result<(), VegasError> {
+    let checkpoint = Some(args.get_str("--checkpoint"))
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_string());
+    let results = Some(args.get_str("--output"))
+        .filter(|path| !path.is_empty())
+        .map(|path| path.to_string());
+    let format = match args.get_str("--format") {
+        "" => results.as_ref().map(output::Format::from_path).unwrap_or_default(),
+        name => output::Format::from_name(name)
+            .ok_or_else(|| invalid("--format", format!("unknown results format {}", name)))?,
+    };
+    let checkpoint_every: usize = parse(args, "--checkpoint-every")?;
+    let interval: usize = parse(args, "--snapshots")?;
+    let snapshots = if interval > 0 {
+        let format = args.get_str("--snapshot-format");
+        let format = Format::from_extension(format)
+            .ok_or_else(|| {
+                invalid("--snapshot-format", format!("unknown snapshot format {}", format))
+            })?;
+        Some(Snapshots::new(args.get_str("--snapshot-prefix"), format, interval))
+    } else {
+        None
+    };
+    if args.get_bool("lattice") {
+        let lattice = read_lattice(args.get_str("<lattice>"))?;
+        let observables = lattice_observables(&lattice, args.get_bool("--topological-charge"))?;
+        let simulation = simulation(args, lattice.sites().len(), observables)?;
+        let context = Context {
+            model: Model::Lattice(lattice),
+            snapshots,
+            results,
+            format,
+            checkpoint_every,
+        };
+        simulate(context, simulation, Vec::new(), checkpoint)
+    } else {
+        let simulation = simulation(args, 100, Observables::new(100))?;
+        let context = Context { model: Model::Bench, snapshots, results, format, checkpoint_every };
+        simulate(context, simulation, Vec::new(), checkpoint)
+    }
 }
 
 
-fn check_error(res: Result<(), Box<Error>>) {
-    match res {
-        Err(e) => {
-            eprintln!("Error: {}", e.description());
-            match e.cause() {
-                Some(cause) => eprintln!("Cause: {}", cause),
-                _ => ()
-            };
-            std::process::exit(1);
+fn resume_as<I, S>(path: &str) -> Result<(), VegasError>
+    where I: ModelIntegrator<S>,
+          S: OrientableSpin + Clone + Send + Sync + Serialize + DeserializeOwned + 'static
+{
+    let checkpoint: Checkpoint<I, S> = Checkpoint::load(path)?;
+    let (context, simulation, stages) = checkpoint.into_parts();
+    let context: Context = serde_json::from_value(context).map_err(CheckpointError::Format)?;
+    simulate(context, simulation, stages, Some(path.to_string()))
+}
+
+
+fn resume(path: &str) -> Result<(), VegasError> {
+    let context: Context = serde_json::from_value(checkpoint::context(path)?)
+        .map_err(CheckpointError::Format)?;
+    let (spin, kind) = match context.model {
+        Model::Run(ref config) => (config.spin(), config.integrator_kind()),
+        _ => (SpinModel::Heisenberg, IntegratorKind::Metropolis),
+    };
+    match (spin, kind) {
+        (SpinModel::Ising, IntegratorKind::Metropolis) => {
+            resume_as::<MetropolisIntegrator, IsingSpin>(path)
+        },
+        (SpinModel::Ising, IntegratorKind::Checkerboard) => {
+            resume_as::<CheckerboardIntegrator, IsingSpin>(path)
+        },
+        (SpinModel::Heisenberg, IntegratorKind::Metropolis) => {
+            resume_as::<MetropolisIntegrator, HeisenbergSpin>(path)
         },
-        _ => {},
+        (SpinModel::Heisenberg, IntegratorKind::Checkerboard) => {
+            resume_as::<CheckerboardIntegrator, HeisenbergSpin>(path)
+        },
+    }
+}
+
+
+fn check_error(res: Result<(), VegasError>) {
+    if let Err(e) = res {
+        eprintln!("Error: {}", e);
+        if let Some(cause) = e.source() {
+            eprintln!("Cause: {}", cause);
+        }
+        std::process::exit(1);
     }
 }
 
@@ -113,9 +612,11 @@ pub fn main() {
     let args = Docopt::new(USAGE)
         .and_then(|doc| doc.version(Some(version)).parse())
         .unwrap_or_else(|e| e.exit());
-    if args.get_bool("bench") {
-        bench()
-    } else if args.get_bool("lattice") {
-        check_error(bench_lattice(args.get_str("<lattice>")))
+    if args.get_bool("resume") {
+        check_error(resume(args.get_str("<checkpoint>")))
+    } else if args.get_bool("run") {
+        check_error(start_run(args.get_str("<config>")))
+    } else if args.get_bool("bench") || args.get_bool("lattice") {
+        check_error(start(&args))
     }
 }
@@ -0,0 +1,493 @@
+//! Multicanonical sampling, after Berg and Neuhaus.
+//!
+//! Moves are accepted with weights `W(E)` rather than Boltzmann factors,
+//! and the weights are refined over iterations until every energy in a
+//! window is visited about as often as any other. A flat histogram walks
+//! right through the suppressed region between the ordered and disordered
+//! peaks of a first order transition, which canonical runs only cross
+//! exponentially rarely in the size of the system. Canonical averages at
+//! any temperature within the window follow by reweighting the samples with
+//! `exp(-E / T) / W(E)`.
+
+use rand::distributions::{IndependentSample, Range};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use energy::EnergyComponent;
+use error::{self, VegasError};
+use integrator::{Integrator, StateGenerator};
+use observables::Observables;
+use rng::XorShift128;
+use state::{Spin, State};
+use statistics::log_sum_exp;
+
+
+/// Stop bisecting for the equal height temperature once the bracket is
+/// this narrow, relative to the temperature.
+const TOLERANCE: f64 = 1e-8;
+
+
+/// An integrator that samples with binned multicanonical weights.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MulticanonicalIntegrator {
+    rng: XorShift128,
+    /// The energy window is split into bins of `width` from `emin` up.
+    emin: f64,
+    width: f64,
+    /// `ln W(E)` of every bin.
+    weights: Vec<f64>,
+    /// Visits to every bin since the last refinement.
+    histogram: Vec<u64>,
+    /// Bins visited in any iteration so far.
+    visited: Vec<bool>,
+    /// How much the statistics of past iterations weigh on the difference
+    /// between the weights of every visited bin and the next visited one.
+    accumulated: Vec<f64>,
+}
+
+
+impl MulticanonicalIntegrator {
+    /// New up an integrator for the energies from `emin` to `emax`, in bins
+    /// of `width`, starting with flat weights, that is at infinite
+    /// temperature.
+    pub fn new(emin: f64, emax: f64, width: f64) -> Result<Self, VegasError> {
+        if emin >= emax || !(emax - emin).is_finite() {
+            return error::invalid(format!("invalid energy window from {} to {}", emin, emax));
+        }
+        if width <= 0.0 || width.is_nan() {
+            return error::invalid(format!("bin widths must be positive, got {}", width));
+        }
+        let nbins = ((emax - emin) / width).ceil() as usize;
+        Ok(Self {
+            rng: XorShift128::new_unseeded(),
+            emin,
+            width,
+            weights: vec![0.0; nbins],
+            histogram: vec![0; nbins],
+            visited: vec![false; nbins],
+            accumulated: vec![0.0; nbins],
+        })
+    }
+
+    /// Seed the random number generator, runs with the same seed are
+    /// identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift128::seed_from_u64(seed);
+        self
+    }
+
+    /// Start from the canonical weights at a temperature, a better guess
+    /// than flat weights when the window is well below the disordered
+    /// phase.
+    pub fn with_temp(mut self, temp: f64) -> Result<Self, VegasError> {
+        let temp = error::check_temp(temp)?;
+        for (bin, weight) in self.weights.iter_mut().enumerate() {
+            *weight = - (self.emin + (bin as f64 + 0.5) * self.width) / temp;
+        }
+        Ok(self)
+    }
+
+    /// Number of energy bins.
+    pub fn nbins(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// The energy at the middle of a bin.
+    pub fn energy(&self, bin: usize) -> f64 {
+        self.emin + (bin as f64 + 0.5) * self.width
+    }
+
+    /// The bin of an energy, `None` outside of the window.
+    pub fn bin(&self, energy: f64) -> Option<usize> {
+        let bin = ((energy - self.emin) / self.width).floor();
+        if bin >= 0.0 && (bin as usize) < self.nbins() { Some(bin as usize) } else { None }
+    }
+
+    /// `ln W(E)` of every bin.
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+
+    /// Visits to every bin since the last refinement.
+    pub fn histogram(&self) -> &[u64] {
+        &self.histogram
+    }
+
+    /// The smallest count of the histogram over its mean, over the bins
+    /// visited so far. Close to one once the weights have converged.
+    pub fn flatness(&self) -> f64 {
+        let visited: Vec<f64> = self.histogram.iter()
+            .filter(|&&h| h > 0)
+            .map(|&h| h as f64)
+            .collect();
+        if visited.is_empty() {
+            return 0.0;
+        }
+        let mean = visited.iter().sum::<f64>() / visited.len() as f64;
+        visited.iter().cloned().fold(f64::MAX, f64::min) / mean
+    }
+
+    /// `ln W(E)` at an energy, that of the nearest bin outside the window.
+    fn weight(&self, energy: f64) -> f64 {
+        let bin = ((energy - self.emin) / self.width).floor().max(0.0) as usize;
+        self.weights[bin.min(self.nbins() - 1)]
+    }
+
+    /// How far an energy is from the window.
+    fn distance(&self, energy: f64) -> f64 {
+        let emax = self.emin + self.nbins() as f64 * self.width;
+        (self.emin - energy).max(energy - emax).max(0.0)
+    }
+
+    fn accepts(&mut self, current: f64, proposed: f64) -> bool {
+        match (self.bin(current), self.bin(proposed)) {
+            (Some(a), Some(b)) => {
+                let delta = self.weights[b] - self.weights[a];
+                delta >= 0.0 || self.rng.gen::<f64>() < delta.exp()
+            },
+            (Some(_), None) => false,
+            // Walk into the window from wherever the state started.
+            (None, _) => self.distance(proposed) <= self.distance(current),
+        }
+    }
+
+    /// Update the weights with the histogram since the last refinement and
+    /// clear it, after Berg's recursion: going through the bins visited so
+    /// far, the difference between the weights of neighbors moves by the
+    /// log of the ratio of their counts, trusted in proportion to the
+    /// statistics behind it relative to that of past iterations. Bins never
+    /// visited keep their offset to the closest visited bin below, the
+    /// lowest one for those below all, so the walk carries on into them
+    /// with the initial weights.
+    pub fn refine(&mut self) {
+        for (visited, &h) in self.visited.iter_mut().zip(self.histogram.iter()) {
+            *visited |= h > 0;
+        }
+        let bins: Vec<usize> = (0..self.nbins()).filter(|&bin| self.visited[bin]).collect();
+        if let Some(&first) = bins.first() {
+            let old = self.weights.clone();
+            for pair in bins.windows(2) {
+                let (a, b) = (pair[0], pair[1]);
+                let mut difference = old[b] - old[a];
+                let (lower, upper) = (self.histogram[a] as f64, self.histogram[b] as f64);
+                if lower > 0.0 && upper > 0.0 {
+                    let g = lower * upper / (lower + upper);
+                    self.accumulated[a] += g;
+                    difference += g / self.accumulated[a] * (lower / upper).ln();
+                }
+                self.weights[b] = self.weights[a] + difference;
+            }
+            let mut anchor = first;
+            for bin in 0..self.nbins() {
+                if self.visited[bin] {
+                    anchor = bin;
+                } else {
+                    self.weights[bin] = self.weights[anchor] + old[bin] - old[anchor];
+                }
+            }
+        }
+        for h in self.histogram.iter_mut() {
+            *h = 0;
+        }
+    }
+
+    /// Refine the weights `iterations` times, after `sweeps` sweeps each.
+    /// Returns the last state.
+    pub fn iterate<S, T>(&mut self, energy: &T, state: &State<S>, sweeps: usize,
+                         iterations: usize) -> Result<State<S>, VegasError>
+        where S: Spin + Clone,
+              T: EnergyComponent<S>
+    {
+        let mut state = state.clone();
+        for _ in 0..iterations {
+            for _ in 0..sweeps {
+                state = self.step(energy, &state)?;
+            }
+            self.refine();
+        }
+        Ok(state)
+    }
+
+    /// Canonical averages out of a run with the current weights, the
+    /// weights should not be refined in between. Fails unless the
+    /// observables kept their samples.
+    pub fn averages(&self, observables: &Observables)
+        -> Result<MulticanonicalAverages, VegasError>
+    {
+        if !observables.keeps_series() {
+            return error::invalid("multicanonical averages need the samples kept".to_string());
+        }
+        Ok(MulticanonicalAverages {
+            nsites: observables.nsites(),
+            energies: observables.energies().to_vec(),
+            magnetizations: observables.magnetizations().to_vec(),
+            weights: observables.energies().iter().map(|&e| - self.weight(e)).collect(),
+            emin: self.emin,
+            width: self.width,
+            nbins: self.nbins(),
+        })
+    }
+}
+
+
+impl<S, T> Integrator<S, T> for MulticanonicalIntegrator where
+    S: Spin + Clone,
+    T: EnergyComponent<S>
+{
+    fn step(&mut self, energy: &T, state: &State<S>) -> Result<State<S>, VegasError> {
+        energy.check(state)?;
+        let mut state = state.clone();
+        energy.sync(&state);
+        let mut current = energy.total_energy(&state);
+        let sites = Range::new(0, state.len());
+        for _ in 0..state.len() {
+            let site = sites.ind_sample(&mut self.rng);
+            let spin = S::rand(&mut self.rng);
+            let proposed = current + energy.delta_energy(&state, site, &spin);
+            if self.accepts(current, proposed) {
+                state.set_at(site, spin);
+                energy.accept(&state, site);
+                current = proposed;
+            }
+            if let Some(bin) = self.bin(current) {
+                self.histogram[bin] += 1;
+            }
+        }
+        Ok(state)
+    }
+}
+
+
+impl<S> StateGenerator<S> for MulticanonicalIntegrator where
+    S: Spin + Clone,
+{
+    fn state(&mut self, nsites: usize) -> State<S> {
+        State::rand_with_size(nsites, &mut self.rng)
+    }
+}
+
+
+/// Canonical averages reweighted out of the samples of a multicanonical
+/// run.
+#[derive(Clone, Debug)]
+pub struct MulticanonicalAverages {
+    nsites: usize,
+    energies: Vec<f64>,
+    magnetizations: Vec<f64>,
+    /// `- ln W(E)` of every sample, which undoes the multicanonical bias.
+    weights: Vec<f64>,
+    emin: f64,
+    width: f64,
+    nbins: usize,
+}
+
+
+impl MulticanonicalAverages {
+    /// Normalized canonical weights of every sample at a temperature.
+    fn canonical(&self, temp: f64) -> Vec<f64> {
+        let logs: Vec<f64> = self.energies.iter()
+            .zip(self.weights.iter())
+            .map(|(e, w)| w - e / temp)
+            .collect();
+        let log_z = log_sum_exp(logs.iter().cloned());
+        logs.iter().map(|l| (l - log_z).exp()).collect()
+    }
+
+    /// The first two moments of a series at a temperature.
+    fn moments(&self, series: &[f64], temp: f64) -> (f64, f64) {
+        self.canonical(temp)
+            .iter()
+            .zip(series.iter())
+            .fold((0.0, 0.0), |(m, m2), (w, x)| (m + w * x, m2 + w * x * x))
+    }
+
+    /// Mean total energy at a temperature.
+    pub fn energy(&self, temp: f64) -> f64 {
+        self.moments(&self.energies, temp).0
+    }
+
+    /// Specific heat per site at a temperature.
+    pub fn specific_heat(&self, temp: f64) -> f64 {
+        let (e, e2) = self.moments(&self.energies, temp);
+        (e2 - e * e) / (self.nsites as f64 * temp * temp)
+    }
+
+    /// Mean magnetization magnitude at a temperature.
+    pub fn magnetization(&self, temp: f64) -> f64 {
+        self.moments(&self.magnetizations, temp).0
+    }
+
+    /// Magnetic susceptibility per site at a temperature.
+    pub fn susceptibility(&self, temp: f64) -> f64 {
+        let (m, m2) = self.moments(&self.magnetizations, temp);
+        (m2 - m * m) / (self.nsites as f64 * temp)
+    }
+
+    /// The canonical energy distribution at a temperature, over the bins of
+    /// the integrator.
+    pub fn distribution(&self, temp: f64) -> Vec<f64> {
+        let mut distribution = vec![0.0; self.nbins];
+        for (e, w) in self.energies.iter().zip(self.canonical(temp)) {
+            let bin = ((e - self.emin) / self.width).floor().max(0.0) as usize;
+            distribution[bin.min(self.nbins - 1)] += w;
+        }
+        distribution
+    }
+
+    /// The two peaks of the distribution at a temperature, the ordered one
+    /// below the mean energy and the disordered one above, and the dip
+    /// between them, as indices of bins.
+    fn peaks(&self, distribution: &[f64], temp: f64) -> (usize, usize, usize) {
+        let split = ((self.energy(temp) - self.emin) / self.width).max(0.0) as usize;
+        let split = split.min(self.nbins - 1);
+        let argmax = |bins: ::std::ops::Range<usize>| bins
+            .fold(None, |best: Option<usize>, bin| match best {
+                Some(b) if distribution[b] >= distribution[bin] => Some(b),
+                _ => Some(bin),
+            });
+        let ordered = argmax(0..split).unwrap_or(split);
+        let disordered = argmax(split..self.nbins).unwrap_or(split);
+        let dip = (ordered..disordered + 1)
+            .fold(ordered, |b, bin| if distribution[bin] < distribution[b] { bin } else { b });
+        (ordered, disordered, dip)
+    }
+
+    /// `ln(P_o / P_d)`, how much higher the ordered peak of the energy
+    /// distribution is than the disordered one.
+    fn asymmetry(&self, temp: f64) -> f64 {
+        let distribution = self.distribution(temp);
+        let (ordered, disordered, _) = self.peaks(&distribution, temp);
+        (distribution[ordered] / distribution[disordered]).ln()
+    }
+
+    /// The temperature between `lo` and `hi` where the ordered and
+    /// disordered peaks of the energy distribution are equally high, the
+    /// transition temperature of a finite system.
+    pub fn equal_height_temperature(&self, lo: f64, hi: f64) -> f64 {
+        let (mut lo, mut hi) = (lo, hi);
+        // The ordered peak wins at low temperatures.
+        while hi - lo > TOLERANCE * hi {
+            let mid = (lo + hi) / 2.0;
+            if self.asymmetry(mid) > 0.0 {
+                lo = mid;
+            } else {
+                hi = mid;
+            }
+        }
+        (lo + hi) / 2.0
+    }
+
+    /// The free energy of the interfaces between the ordered and
+    /// disordered phases over the temperature, `ln(P_max / P_min)`, with
+    /// `P_max` the geometric mean of the two peaks of the energy
+    /// distribution and `P_min` the dip between them. With periodic
+    /// boundaries there are two interfaces, so at the equal height
+    /// temperature the interface tension is this over `2 L^(d - 1)`.
+    pub fn interface_free_energy(&self, temp: f64) -> f64 {
+        let distribution = self.distribution(temp);
+        let (ordered, disordered, dip) = self.peaks(&distribution, temp);
+        let top = (distribution[ordered] * distribution[disordered]).sqrt();
+        (top / distribution[dip]).ln()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::MulticanonicalIntegrator;
+    use energy::{EnergyComponent, ExchangeEnergy};
+    use exact::Enumeration;
+    use integrator::{Integrator, StateGenerator};
+    use lattice::{LatticeBuilder, UnitCell};
+    use observables::Observables;
+    use state::{PottsSpin, Spin, State};
+
+    fn square(size: usize) -> ExchangeEnergy {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(size, size, 1).build()
+            .unwrap();
+        ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+    }
+
+    /// A production run of `sweeps` sweeps with the current weights.
+    fn produce<S: Spin + Clone, T: EnergyComponent<S>>(
+        integrator: &mut MulticanonicalIntegrator, hamiltonian: &T, mut state: State<S>,
+        sweeps: usize) -> Observables
+    {
+        let mut observables = Observables::new(state.len()).with_series();
+        for _ in 0..sweeps {
+            state = integrator.step(hamiltonian, &state).unwrap();
+            observables.measure(hamiltonian, &state);
+        }
+        observables
+    }
+
+    #[test]
+    fn windows_and_bins_are_checked() {
+        assert!(MulticanonicalIntegrator::new(1.0, 0.0, 1.0).is_err());
+        assert!(MulticanonicalIntegrator::new(0.0, 1.0, 0.0).is_err());
+        assert!(MulticanonicalIntegrator::new(0.0, 1.0, 0.1).unwrap().with_temp(-1.0).is_err());
+        let integrator = MulticanonicalIntegrator::new(-8.0, 0.0, 1.0).unwrap();
+        assert_eq!(integrator.nbins(), 8);
+        assert_eq!(integrator.bin(-8.0), Some(0));
+        assert_eq!(integrator.bin(-0.5), Some(7));
+        assert_eq!(integrator.bin(0.0), None);
+        assert_eq!(integrator.energy(0), -7.5);
+    }
+
+    #[test]
+    fn reweighted_averages_match_enumeration() {
+        let exchange = square(3);
+        let exact = Enumeration::new::<PottsSpin<3>, _>(&exchange, 9).unwrap();
+        let mut integrator = MulticanonicalIntegrator::new(-18.0, 0.5, 1.0).unwrap().with_seed(1);
+        let state: State<PottsSpin<3>> = integrator.state(9);
+        let state = integrator.iterate(&exchange, &state, 500, 20).unwrap();
+        let observables = produce(&mut integrator, &exchange, state, 20_000);
+        assert!(integrator.flatness() > 0.5, "flatness {}", integrator.flatness());
+        let averages = integrator.averages(&observables).unwrap();
+        for &temp in [0.3, 0.6, 1.0, 3.0].iter() {
+            let (sampled, expected) = (averages.energy(temp), exact.energy(temp));
+            assert!((sampled - expected).abs() < 0.02 * expected.abs() + 0.05,
+                    "energy at T = {}: {} against {}", temp, sampled, expected);
+            let (sampled, expected) = (averages.specific_heat(temp), exact.specific_heat(temp));
+            assert!((sampled - expected).abs() < 0.05 * expected.abs() + 0.01,
+                    "specific heat at T = {}: {} against {}", temp, sampled, expected);
+        }
+    }
+
+    #[test]
+    fn ten_state_potts_tunnels_through_the_transition() {
+        let size = 8;
+        let nsites = size * size;
+        let exchange = square(size);
+        // Most of the way from the ground state to the disordered phase.
+        let (emin, emax) = (-2.0 * nsites as f64, -0.6 * nsites as f64);
+        let critical = 1.0 / (1.0 + 10f64.sqrt()).ln();
+        let mut integrator = MulticanonicalIntegrator::new(emin, emax, 2.0).unwrap()
+            .with_temp(critical).unwrap()
+            .with_seed(7);
+        let state = State::<PottsSpin<10>>::up_with_size(nsites);
+        let state = integrator.iterate(&exchange, &state, 200, 30).unwrap();
+        let observables = produce(&mut integrator, &exchange, state, 10_000);
+        // Round trips between the ordered and disordered phases.
+        let (ordered, disordered) = (-1.6 * nsites as f64, -1.1 * nsites as f64);
+        let mut trips = 0;
+        let mut last = None;
+        for &e in observables.energies() {
+            let phase = if e < ordered { Some(0) } else if e > disordered { Some(1) } else { None };
+            if phase.is_some() && phase != last {
+                trips += last.is_some() as usize;
+                last = phase;
+            }
+        }
+        assert!(trips >= 10, "only {} tunneling events", trips);
+        let averages = integrator.averages(&observables).unwrap();
+        let temp = averages.equal_height_temperature(0.9 * critical, 1.1 * critical);
+        // The transition of a finite system is shifted by about
+        // `ln q / (L^2 latent heat)` in inverse temperature, the latent heat
+        // per site being 0.696 for ten states.
+        let shifted = 1.0 / (1.0 / critical - 10f64.ln() / (nsites as f64 * 0.696));
+        assert!((temp - shifted).abs() < 0.03 * shifted, "transition at {}", temp);
+        assert!(averages.interface_free_energy(temp) > 0.0);
+        assert!(averages.specific_heat(temp) > averages.specific_heat(0.9 * critical));
+    }
+}
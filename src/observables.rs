@@ -0,0 +1,627 @@
+//! Accumulators for the thermodynamic observables of a simulation.
+
+use serde::{Deserialize, Serialize};
+
+use energy::EnergyComponent;
+use integrator::Totals;
+use error::{self, VegasError};
+use lattice::Geometry;
+use state::{Spin, State};
+use statistics::Accumulator;
+use topology::Triangulation;
+
+
+fn norm(v: [f64; 3]) -> f64 {
+    (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt()
+}
+
+
+/// A generalized magnetization, `|sum_i w_i S_i|`, with a complex weight
+/// `w_i` for every site. Signs give staggered magnetizations, zeros
+/// restrict the sum to a sublattice and phases describe spiral or multi
+/// sublattice orders.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OrderParameter {
+    name: String,
+    re: Vec<f64>,
+    im: Vec<f64>,
+}
+
+
+impl OrderParameter {
+    /// New up an order parameter with a real weight for every site.
+    pub fn with_signs(name: &str, signs: Vec<f64>) -> Self {
+        let im = vec![0.0; signs.len()];
+        Self {
+            name: name.to_string(),
+            re: signs,
+            im,
+        }
+    }
+
+    /// New up an order parameter with a phase for every site, the weights
+    /// are `exp(i phase)`.
+    pub fn with_phases(name: &str, phases: Vec<f64>) -> Self {
+        Self {
+            name: name.to_string(),
+            re: phases.iter().map(|p| p.cos()).collect(),
+            im: phases.iter().map(|p| p.sin()).collect(),
+        }
+    }
+
+    /// Weight every site according to its kind.
+    pub fn from_kinds<F>(name: &str, geometry: &Geometry, phase: F) -> Self
+        where F: Fn(&str) -> f64
+    {
+        Self::with_phases(name, geometry.kinds().iter().map(|k| phase(k)).collect())
+    }
+
+    /// The magnetization modulated by a wave vector, weights are
+    /// `exp(i q . r_i)`. The staggered magnetization of a square lattice
+    /// for instance has `q = (pi, pi, 0)`.
+    pub fn with_wave_vector(name: &str, geometry: &Geometry, q: [f64; 3]) -> Self {
+        Self::with_phases(name, geometry.positions()
+            .iter()
+            .map(|r| q[0] * r[0] + q[1] * r[1] + q[2] * r[2])
+            .collect())
+    }
+
+    /// The magnetization of the sites of a given kind.
+    pub fn sublattice(geometry: &Geometry, kind: &str) -> Self {
+        Self::with_signs(kind, geometry.kinds()
+            .iter()
+            .map(|k| if k == kind { 1.0 } else { 0.0 })
+            .collect())
+    }
+
+    /// One sublattice magnetization for every site kind of a geometry.
+    pub fn sublattices(geometry: &Geometry) -> Vec<Self> {
+        let mut kinds: Vec<&String> = geometry.kinds().iter().collect();
+        kinds.sort();
+        kinds.dedup();
+        kinds.into_iter().map(|kind| Self::sublattice(geometry, kind)).collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Evaluate the order parameter on a state.
+    pub fn value<S: Spin>(&self, state: &State<S>) -> f64 {
+        let mut re = [0.0; 3];
+        let mut im = [0.0; 3];
+        for ((spin, wr), wi) in state.spins().iter().zip(self.re.iter()).zip(self.im.iter()) {
+            let m = spin.magnetization();
+            for c in 0..3 {
+                re[c] += wr * m[c];
+                im[c] += wi * m[c];
+            }
+        }
+        (0..3).map(|c| re[c] * re[c] + im[c] * im[c]).sum::<f64>().sqrt()
+    }
+}
+
+
+/// The samples themselves, one series per quantity.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Series {
+    energies: Vec<f64>,
+    magnetizations: Vec<f64>,
+    vectors: Vec<[f64; 3]>,
+    orders: Vec<Vec<f64>>,
+    charges: Vec<f64>,
+    terms: Vec<Vec<f64>>,
+}
+
+
+/// Accumulator for the energy and magnetization of a system at a fixed
+/// temperature.
+///
+/// Energies and magnetizations are pushed as totals for the whole system,
+/// the means are reported as totals as well while the specific heat and the
+/// susceptibility are reported per site. Samples are accumulated on the fly
+/// so that errors account for autocorrelations: means get their errors from
+/// a binning analysis and derived quantities from a jackknife. The samples
+/// themselves are only kept on request, see `Observables::with_series`.
+///
+/// Order parameters other than the magnetization and the topological charge
+/// can be tracked as well, they are only measured when adding samples from
+/// a state, and so is every term of the energy.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Observables {
+    nsites: usize,
+    energy: Accumulator,
+    magnetization: Accumulator,
+    /// Squared magnetizations, whose moments make the Binder cumulant.
+    squared_magnetization: Accumulator,
+    /// Sum of the magnetization vectors.
+    vector: [f64; 3],
+    order_parameters: Vec<OrderParameter>,
+    orders: Vec<Accumulator>,
+    triangulation: Option<Triangulation>,
+    charge: Accumulator,
+    labels: Vec<String>,
+    terms: Vec<Accumulator>,
+    series: Option<Series>,
+}
+
+
+impl Observables {
+    pub fn new(nsites: usize) -> Self {
+        Self {
+            nsites,
+            energy: Accumulator::new().with_autocorrelation(),
+            magnetization: Accumulator::new().with_autocorrelation(),
+            squared_magnetization: Accumulator::new(),
+            vector: [0.0; 3],
+            order_parameters: Vec::new(),
+            orders: Vec::new(),
+            triangulation: None,
+            charge: Accumulator::new(),
+            labels: Vec::new(),
+            terms: Vec::new(),
+            series: None,
+        }
+    }
+
+    /// Track the topological charge of the states, computed on the given
+    /// triangulation.
+    pub fn with_topological_charge(mut self, triangulation: Triangulation) -> Self {
+        self.triangulation = Some(triangulation);
+        self
+    }
+
+    /// Track the energy terms called `labels`, in the order of the
+    /// breakdown of the Hamiltonian. Without them, the terms are those of
+    /// the first sample.
+    pub fn with_terms(mut self, labels: Vec<String>) -> Self {
+        self.terms = vec![Accumulator::new(); labels.len()];
+        self.labels = labels;
+        self
+    }
+
+    /// Track some order parameters along with the magnetization.
+    pub fn with_order_parameters(mut self, order_parameters: Vec<OrderParameter>) -> Self {
+        self.orders = vec![Accumulator::new(); order_parameters.len()];
+        self.order_parameters = order_parameters;
+        self
+    }
+
+    /// Keep the samples from now on as well, reweighting and dropping
+    /// transients need them. Memory then grows with the number of samples.
+    pub fn with_series(mut self) -> Self {
+        self.series.get_or_insert_with(Series::default);
+        self
+    }
+
+    /// Whether the samples are kept, see `Observables::with_series`.
+    pub fn keeps_series(&self) -> bool {
+        self.series.is_some()
+    }
+
+    /// Stop keeping the samples and forget those kept so far, the averages
+    /// and their errors stay.
+    pub fn drop_series(&mut self) {
+        self.series = None;
+    }
+
+    /// Add a sample given the total energy and magnetization of the system.
+    pub fn push(&mut self, energy: f64, magnetization: [f64; 3]) {
+        let m = norm(magnetization);
+        self.energy.push(energy);
+        self.magnetization.push(m);
+        self.squared_magnetization.push(m * m);
+        for (sum, v) in self.vector.iter_mut().zip(magnetization.iter()) {
+            *sum += v;
+        }
+        if let Some(ref mut series) = self.series {
+            series.energies.push(energy);
+            series.magnetizations.push(m);
+            series.vectors.push(magnetization);
+        }
+    }
+
+    /// Add a sample measured on a state.
+    pub fn measure<S, T>(&mut self, hamiltonian: &T, state: &State<S>)
+        where S: Spin,
+              T: EnergyComponent<S>
+    {
+        self.measure_with(hamiltonian, state, &Totals::of(hamiltonian, state));
+    }
+
+    /// Add a sample measured on a state whose totals are already known,
+    /// say tracked by the integrator. A hamiltonian of a single term is
+    /// then not evaluated at all.
+    pub fn measure_with<S, T>(&mut self, hamiltonian: &T, state: &State<S>, totals: &Totals)
+        where S: Spin,
+              T: EnergyComponent<S>
+    {
+        self.push(totals.energy(), totals.magnetization());
+        let orders: Vec<f64> = self.order_parameters.iter().map(|o| o.value(state)).collect();
+        let charge = self.triangulation.as_ref().map(|t| t.charge(state));
+        let breakdown = if hamiltonian.nterms() == 1 {
+            vec![(hamiltonian.label(), totals.energy())]
+        } else {
+            hamiltonian.breakdown(state)
+        };
+        if self.labels.is_empty() {
+            self.labels = breakdown.iter().map(|(label, _)| label.clone()).collect();
+            self.terms = vec![Accumulator::new(); breakdown.len()];
+        }
+        let terms: Vec<f64> = breakdown.into_iter().map(|(_, term)| term).collect();
+        self.push_measurements(&orders, charge, &terms);
+    }
+
+    /// Add the quantities only measured on states to the last sample.
+    fn push_measurements(&mut self, orders: &[f64], charge: Option<f64>, terms: &[f64]) {
+        for (accumulator, &x) in self.orders.iter_mut().zip(orders.iter()) {
+            accumulator.push(x);
+        }
+        if let Some(q) = charge {
+            self.charge.push(q);
+        }
+        for (accumulator, &x) in self.terms.iter_mut().zip(terms.iter()) {
+            accumulator.push(x);
+        }
+        if let Some(ref mut series) = self.series {
+            series.orders.resize(orders.len(), Vec::new());
+            for (s, &x) in series.orders.iter_mut().zip(orders.iter()) {
+                s.push(x);
+            }
+            series.charges.extend(charge);
+            series.terms.resize(terms.len(), Vec::new());
+            for (s, &x) in series.terms.iter_mut().zip(terms.iter()) {
+                s.push(x);
+            }
+        }
+    }
+
+    /// Drop the first `n` samples, the transient before equilibrium. The
+    /// averages are accumulated again out of the samples left, so this
+    /// fails unless the samples are kept.
+    pub fn discard(&mut self, n: usize) -> Result<(), VegasError> {
+        let series = match self.series.take() {
+            Some(series) => series,
+            None => {
+                return error::invalid(
+                    "dropping samples needs them kept, see Observables::with_series".to_string());
+            },
+        };
+        let mut left = Self::new(self.nsites)
+            .with_order_parameters(self.order_parameters.clone())
+            .with_terms(self.labels.clone())
+            .with_series();
+        left.triangulation = self.triangulation.take();
+        for i in n.min(series.energies.len())..series.energies.len() {
+            left.push(series.energies[i], series.vectors[i]);
+            let orders: Vec<f64> = series.orders.iter().filter_map(|s| s.get(i).cloned()).collect();
+            let terms: Vec<f64> = series.terms.iter().filter_map(|s| s.get(i).cloned()).collect();
+            left.push_measurements(&orders, series.charges.get(i).cloned(), &terms);
+        }
+        *self = left;
+        Ok(())
+    }
+
+    pub fn nsites(&self) -> usize {
+        self.nsites
+    }
+
+    /// Number of samples so far.
+    pub fn count(&self) -> usize {
+        self.energy.count()
+    }
+
+    /// The total energy of every sample, empty unless the samples are kept.
+    pub fn energies(&self) -> &[f64] {
+        self.series.as_ref().map_or(&[], |s| &s.energies)
+    }
+
+    /// The magnetization magnitude of every sample, empty unless the
+    /// samples are kept.
+    pub fn magnetizations(&self) -> &[f64] {
+        self.series.as_ref().map_or(&[], |s| &s.magnetizations)
+    }
+
+    /// Mean total energy, `<E>`.
+    pub fn energy(&self) -> f64 {
+        self.energy.mean()
+    }
+
+    pub fn energy_error(&self) -> f64 {
+        self.energy.binning_error()
+    }
+
+    pub fn energy_autocorrelation_time(&self) -> f64 {
+        self.energy.autocorrelation_time()
+    }
+
+    /// Mean magnetization magnitude, `<|M|>`.
+    pub fn magnetization(&self) -> f64 {
+        self.magnetization.mean()
+    }
+
+    pub fn magnetization_error(&self) -> f64 {
+        self.magnetization.binning_error()
+    }
+
+    pub fn magnetization_autocorrelation_time(&self) -> f64 {
+        self.magnetization.autocorrelation_time()
+    }
+
+    /// Mean magnetization vector, `<M>`.
+    pub fn magnetization_vector(&self) -> [f64; 3] {
+        let count = self.count() as f64;
+        let mut mean = [0.0; 3];
+        for (m, v) in mean.iter_mut().zip(self.vector.iter()) {
+            *m = v / count;
+        }
+        mean
+    }
+
+    /// Magnitude of the mean magnetization vector, `|<M>|`, it averages out
+    /// in finite systems without a symmetry breaking field.
+    pub fn net_magnetization(&self) -> f64 {
+        norm(self.magnetization_vector())
+    }
+
+    /// Specific heat per site, `(<E^2> - <E>^2) / (N T^2)`.
+    pub fn specific_heat(&self, temp: f64) -> f64 {
+        self.specific_heat_with_error(temp).0
+    }
+
+    pub fn specific_heat_error(&self, temp: f64) -> f64 {
+        self.specific_heat_with_error(temp).1
+    }
+
+    /// Magnetic susceptibility per site, `(<M^2> - <|M|>^2) / (N T)`.
+    pub fn susceptibility(&self, temp: f64) -> f64 {
+        self.susceptibility_with_error(temp).0
+    }
+
+    pub fn susceptibility_error(&self, temp: f64) -> f64 {
+        self.susceptibility_with_error(temp).1
+    }
+
+    /// Binder cumulant, `1 - <M^4> / (3 <M^2>^2)`.
+    pub fn binder_cumulant(&self) -> f64 {
+        self.binder_cumulant_with_error().0
+    }
+
+    pub fn binder_cumulant_error(&self) -> f64 {
+        self.binder_cumulant_with_error().1
+    }
+
+    pub fn order_parameters(&self) -> &[OrderParameter] {
+        &self.order_parameters
+    }
+
+    /// Mean value of the `k`-th order parameter.
+    pub fn order_parameter(&self, k: usize) -> f64 {
+        self.orders[k].mean()
+    }
+
+    pub fn order_parameter_error(&self, k: usize) -> f64 {
+        self.orders[k].binning_error()
+    }
+
+    /// Susceptibility per site of the `k`-th order parameter, defined as
+    /// the magnetic one.
+    pub fn order_parameter_susceptibility(&self, k: usize, temp: f64) -> f64 {
+        self.order_susceptibility_with_error(k, temp).0
+    }
+
+    pub fn order_parameter_susceptibility_error(&self, k: usize, temp: f64) -> f64 {
+        self.order_susceptibility_with_error(k, temp).1
+    }
+
+    /// Labels of the energy terms, see `Observables::with_terms`.
+    pub fn term_labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Mean value of the `k`-th energy term.
+    pub fn term(&self, k: usize) -> f64 {
+        self.terms[k].mean()
+    }
+
+    pub fn term_error(&self, k: usize) -> f64 {
+        self.terms[k].binning_error()
+    }
+
+    /// Whether the topological charge is being tracked.
+    pub fn tracks_topological_charge(&self) -> bool {
+        self.triangulation.is_some()
+    }
+
+    /// Mean topological charge, `<Q>`.
+    pub fn topological_charge(&self) -> f64 {
+        self.charge.mean()
+    }
+
+    pub fn topological_charge_error(&self) -> f64 {
+        self.charge.binning_error()
+    }
+
+    /// Topological susceptibility per site, `(<Q^2> - <Q>^2) / N`.
+    pub fn topological_susceptibility(&self) -> f64 {
+        self.topological_susceptibility_with_error().0
+    }
+
+    pub fn topological_susceptibility_error(&self) -> f64 {
+        self.topological_susceptibility_with_error().1
+    }
+
+    fn topological_susceptibility_with_error(&self) -> (f64, f64) {
+        let norm = self.nsites as f64;
+        self.charge.jackknife(|q, q2| (q2 - q * q) / norm)
+    }
+
+    fn order_susceptibility_with_error(&self, k: usize, temp: f64) -> (f64, f64) {
+        let norm = self.nsites as f64 * temp;
+        self.orders[k].jackknife(|m, m2| (m2 - m * m) / norm)
+    }
+
+    fn specific_heat_with_error(&self, temp: f64) -> (f64, f64) {
+        let norm = self.nsites as f64 * temp * temp;
+        self.energy.jackknife(|m, m2| (m2 - m * m) / norm)
+    }
+
+    fn susceptibility_with_error(&self, temp: f64) -> (f64, f64) {
+        let norm = self.nsites as f64 * temp;
+        self.magnetization.jackknife(|m, m2| (m2 - m * m) / norm)
+    }
+
+    fn binder_cumulant_with_error(&self) -> (f64, f64) {
+        self.squared_magnetization.jackknife(|m2, m4| 1.0 - m4 / (3.0 * m2 * m2))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Observables, OrderParameter};
+    use energy::Gauge;
+    use lattice::{Geometry, LatticeBuilder, UnitCell};
+    use std::f64::consts::PI;
+    use topology::Triangulation;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use state::{Spin, State, IsingSpin, HeisenbergSpin};
+
+    #[test]
+    fn observables_of_a_frozen_state() {
+        let state = State::<HeisenbergSpin>::up_with_size(10);
+        let gauge = Gauge::new(1.0);
+        let mut observables = Observables::new(state.len());
+        for _ in 0..10 {
+            observables.measure(&gauge, &state);
+        }
+        assert_eq!(observables.count(), 10);
+        assert!((observables.energy() - 10.0).abs() < 1e-12);
+        assert!((observables.magnetization() - 10.0).abs() < 1e-12);
+        assert!(observables.specific_heat(1.0).abs() < 1e-12);
+        assert!(observables.susceptibility(1.0).abs() < 1e-12);
+        assert!((observables.binder_cumulant() - 2.0 / 3.0).abs() < 1e-12);
+        assert!(observables.energy_error().abs() < 1e-12);
+        assert!(observables.specific_heat_error(1.0).abs() < 1e-12);
+        assert_eq!(observables.term_labels(), ["gauge"]);
+        assert!((observables.term(0) - 10.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn terms_can_be_named_before_the_first_sample() {
+        let labels = vec!["exchange".to_string(), "zeeman".to_string()];
+        let observables = Observables::new(4).with_terms(labels.clone());
+        assert_eq!(observables.term_labels(), &labels[..]);
+        assert_eq!(observables.count(), 0);
+    }
+
+    #[test]
+    fn observables_of_flipping_ising_states() {
+        let ups = State::<IsingSpin>::up_with_size(4);
+        let downs = State::<IsingSpin>::down_with_size(4);
+        let mut observables = Observables::new(4).with_series();
+        observables.push(-1.0, ups.magnetization());
+        observables.push(1.0, downs.magnetization());
+        assert!(observables.energy().abs() < 1e-12);
+        assert!((observables.specific_heat(2.0) - 1.0 / 16.0).abs() < 1e-12);
+        assert!((observables.magnetization() - 4.0).abs() < 1e-12);
+        assert!(observables.net_magnetization().abs() < 1e-12);
+        assert!(observables.susceptibility(1.0).abs() < 1e-12);
+        observables.discard(1).unwrap();
+        assert_eq!(observables.count(), 1);
+        assert!((observables.energy() - 1.0).abs() < 1e-12);
+        assert!((observables.magnetization_vector()[2] + 4.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn samples_are_only_kept_on_request() {
+        let mut streaming = Observables::new(1);
+        let mut kept = Observables::new(1).with_series();
+        for k in 0..100 {
+            let energy = (k % 7) as f64;
+            streaming.push(energy, [0.0, 0.0, 1.0]);
+            kept.push(energy, [0.0, 0.0, 1.0]);
+        }
+        assert!(streaming.energies().is_empty());
+        assert!(streaming.discard(10).is_err());
+        assert_eq!(kept.energies().len(), 100);
+        assert_eq!(kept.energy(), streaming.energy());
+        assert_eq!(kept.specific_heat(1.0), streaming.specific_heat(1.0));
+        kept.drop_series();
+        assert!(!kept.keeps_series());
+        assert_eq!(kept.count(), 100);
+        assert_eq!(kept.energy_error(), streaming.energy_error());
+    }
+
+    #[test]
+    fn errors_of_fluctuating_observables() {
+        let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+        let mut observables = Observables::new(1);
+        for _ in 0..4_096 {
+            let magnetization = if rng.gen::<f64>() < 0.5 { 1.0 } else { -1.0 };
+            observables.push(rng.gen::<f64>(), [0.0, 0.0, magnetization]);
+        }
+        // Uniform energies in [0, 1) have a variance of 1/12.
+        let error = (1.0 / 12.0 / 4_096.0f64).sqrt();
+        assert!((observables.energy() - 0.5).abs() < 4.0 * error);
+        assert!((observables.energy_error() - error).abs() < 0.3 * error);
+        assert!((observables.specific_heat(1.0) - 1.0 / 12.0).abs() <
+                4.0 * observables.specific_heat_error(1.0));
+        assert!(observables.specific_heat_error(1.0) > 0.0);
+        let tau = observables.energy_autocorrelation_time();
+        assert!((tau - 0.5).abs() < 0.1, "tau = {}", tau);
+    }
+
+    #[test]
+    fn order_parameters_of_a_honeycomb_antiferromagnet() {
+        let geometry = Geometry::from_lattice(
+            &LatticeBuilder::new(UnitCell::honeycomb()).extent(2, 2, 1).build().unwrap());
+        let mut state = State::<HeisenbergSpin>::up_with_size(geometry.len());
+        for (i, kind) in geometry.kinds().iter().enumerate() {
+            if kind == "B" {
+                state.set_at(i, HeisenbergSpin::down());
+            }
+        }
+        let mut orders = OrderParameter::sublattices(&geometry);
+        assert_eq!(orders.iter().map(|o| o.name()).collect::<Vec<_>>(), vec!["A", "B"]);
+        orders.push(OrderParameter::from_kinds(
+                "staggered", &geometry, |kind| if kind == "A" { 0.0 } else { PI }));
+        let mut observables = Observables::new(geometry.len())
+            .with_order_parameters(orders);
+        observables.measure(&Gauge::new(0.0), &state);
+        assert!(observables.magnetization().abs() < 1e-12);
+        assert!((observables.order_parameter(0) - 8.0).abs() < 1e-12);
+        assert!((observables.order_parameter(1) - 8.0).abs() < 1e-12);
+        assert!((observables.order_parameter(2) - 16.0).abs() < 1e-12);
+        assert!(observables.order_parameter_susceptibility(2, 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn staggered_magnetization_from_a_wave_vector() {
+        let geometry = Geometry::from_lattice(
+            &LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap());
+        let staggered = OrderParameter::with_wave_vector("staggered", &geometry, [PI, PI, 0.0]);
+        let mut state = State::<IsingSpin>::up_with_size(16);
+        assert!(staggered.value(&state).abs() < 1e-12);
+        for (i, r) in geometry.positions().iter().enumerate() {
+            if (r[0] + r[1]) as usize % 2 == 1 {
+                state.set_at(i, IsingSpin::down());
+            }
+        }
+        assert!((staggered.value(&state) - 16.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn topological_charge_of_uniform_states() {
+        let geometry = Geometry::from_lattice(
+            &LatticeBuilder::new(UnitCell::triangular()).extent(4, 2, 1).build().unwrap());
+        let mut observables = Observables::new(geometry.len())
+            .with_topological_charge(Triangulation::from_geometry(&geometry));
+        assert!(observables.tracks_topological_charge());
+        for _ in 0..4 {
+            observables.measure(&Gauge::new(0.0), &State::<HeisenbergSpin>::up_with_size(16));
+        }
+        assert!(observables.topological_charge().abs() < 1e-12);
+        assert!(observables.topological_susceptibility().abs() < 1e-12);
+    }
+}
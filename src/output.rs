@@ -0,0 +1,544 @@
+//! Self describing results, one row of observables per temperature.
+//!
+//! Every results file starts with the metadata of the run, the version of
+//! the program, the seed, the Hamiltonian and a hash of the lattice, and
+//! the names of the columns. Four formats are supported:
+//!
+//! * `text`, space separated columns after `#` comment lines.
+//! * `csv`, a header row after `#` comment lines with the metadata.
+//! * `jsonl`, JSON Lines, a `{"metadata": …}` line, a flat object per
+//!   temperature and a closing `{"summary": …}` line.
+//! * `binary`, little endian chunks that `read_binary` reads back.
+//!
+//! Every observable comes with its error in the `<name>_err` column.
+
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Map, Value};
+use vegas_lattice::Lattice;
+
+use error::VegasError;
+use observables::Observables;
+use simulation::Stage;
+
+
+/// Leading bytes of binary results.
+pub const MAGIC: &[u8; 8] = b"VEGASOUT";
+
+
+/// Version of the binary layout.
+pub const BINARY_VERSION: u32 = 1;
+
+
+const COLUMNS: u8 = b'C';
+const ROW: u8 = b'R';
+const SUMMARY: u8 = b'S';
+
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    Csv,
+    #[serde(rename = "jsonl")]
+    JsonLines,
+    Binary,
+}
+
+
+impl Format {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "text" | "txt" => Some(Format::Text),
+            "csv" => Some(Format::Csv),
+            "jsonl" => Some(Format::JsonLines),
+            "binary" | "bin" => Some(Format::Binary),
+            _ => None,
+        }
+    }
+
+    /// Pick the format after the extension of a path, text if there is no
+    /// telling.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        path.as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_name)
+            .unwrap_or_default()
+    }
+}
+
+
+/// FNV-1a hash of the lattice as JSON, stable across runs and platforms.
+pub fn lattice_hash(lattice: &Lattice) -> String {
+    let data = serde_json::to_string(lattice).expect("lattices serialize");
+    let hash = data.bytes().fold(0xcbf29ce484222325u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x100000001b3)
+    });
+    format!("{:016x}", hash)
+}
+
+
+/// What was simulated, to tell results apart.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    version: String,
+    seed: Option<u64>,
+    hamiltonian: Value,
+    lattice: Option<String>,
+    sites: usize,
+}
+
+
+impl Metadata {
+    /// New up the metadata of a run with `sites` spins, the Hamiltonian is
+    /// described the way config files do.
+    pub fn new(hamiltonian: Value, sites: usize) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            seed: None,
+            hamiltonian,
+            lattice: None,
+            sites,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_lattice(mut self, lattice: &Lattice) -> Self {
+        self.lattice = Some(lattice_hash(lattice));
+        self
+    }
+
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.seed
+    }
+
+    pub fn hamiltonian(&self) -> &Value {
+        &self.hamiltonian
+    }
+
+    /// Hash of the lattice, see `lattice_hash`.
+    pub fn lattice(&self) -> Option<&str> {
+        self.lattice.as_deref()
+    }
+
+    pub fn sites(&self) -> usize {
+        self.sites
+    }
+}
+
+
+/// Names of the columns for stages measured with copies of `observables`,
+/// and a Hamiltonian whose terms are called `labels`.
+pub fn columns(labels: &[String], observables: &Observables) -> Vec<String> {
+    let mut columns: Vec<String> = ["temp", "discarded", "energy", "energy_err", "tau_energy"]
+        .iter()
+        .map(|c| c.to_string())
+        .collect();
+    for label in labels {
+        columns.push(label.clone());
+        columns.push(format!("{}_err", label));
+    }
+    for name in ["specific_heat", "magnetization", "susceptibility", "binder"].iter() {
+        columns.push(name.to_string());
+        columns.push(format!("{}_err", name));
+    }
+    for order in observables.order_parameters() {
+        for name in [format!("m_{}", order.name()), format!("chi_{}", order.name())].iter() {
+            columns.push(name.clone());
+            columns.push(format!("{}_err", name));
+        }
+    }
+    if observables.tracks_topological_charge() {
+        for name in ["q", "chi_q"].iter() {
+            columns.push(name.to_string());
+            columns.push(format!("{}_err", name));
+        }
+    }
+    columns
+}
+
+
+/// The values of a stage, in the order of `columns`.
+pub fn values(stage: &Stage) -> Vec<f64> {
+    let temp = stage.temp();
+    let o = stage.observables();
+    let mut values = vec![temp, stage.discarded() as f64, o.energy(), o.energy_error(),
+                          o.energy_autocorrelation_time()];
+    for k in 0..o.term_labels().len() {
+        values.push(o.term(k));
+        values.push(o.term_error(k));
+    }
+    values.extend_from_slice(&[
+        o.specific_heat(temp), o.specific_heat_error(temp),
+        o.magnetization(), o.magnetization_error(),
+        o.susceptibility(temp), o.susceptibility_error(temp),
+        o.binder_cumulant(), o.binder_cumulant_error(),
+    ]);
+    for k in 0..o.order_parameters().len() {
+        values.extend_from_slice(&[
+            o.order_parameter(k), o.order_parameter_error(k),
+            o.order_parameter_susceptibility(k, temp),
+            o.order_parameter_susceptibility_error(k, temp),
+        ]);
+    }
+    if o.tracks_topological_charge() {
+        values.extend_from_slice(&[
+            o.topological_charge(), o.topological_charge_error(),
+            o.topological_susceptibility(), o.topological_susceptibility_error(),
+        ]);
+    }
+    values
+}
+
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+
+fn write_str<W: Write>(writer: &mut W, value: &str) -> io::Result<()> {
+    write_u32(writer, value.len() as u32)?;
+    writer.write_all(value.as_bytes())
+}
+
+
+/// Writes rows of results in one of the formats.
+pub struct ResultsWriter<W: Write> {
+    writer: W,
+    format: Format,
+    columns: Vec<String>,
+}
+
+
+impl<W: Write> ResultsWriter<W> {
+    pub fn new(writer: W, format: Format, columns: Vec<String>) -> Self {
+        Self { writer, format, columns }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Write the metadata and the names of the columns, skip it when
+    /// appending to results that already have them.
+    pub fn header(&mut self, metadata: &Metadata) -> io::Result<()> {
+        match self.format {
+            Format::Text | Format::Csv => {
+                let value = serde_json::to_value(metadata)?;
+                for (key, value) in value.as_object().expect("metadata is an object") {
+                    writeln!(self.writer, "# {}: {}", key, value)?;
+                }
+                let separator = if self.format == Format::Csv { "," } else { " " };
+                let prefix = if self.format == Format::Csv { "" } else { "# " };
+                writeln!(self.writer, "{}{}", prefix, self.columns.join(separator))?;
+            },
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &json!({
+                    "metadata": metadata,
+                    "columns": self.columns,
+                }))?;
+                writeln!(self.writer)?;
+            },
+            Format::Binary => {
+                self.writer.write_all(MAGIC)?;
+                write_u32(&mut self.writer, BINARY_VERSION)?;
+                write_str(&mut self.writer, &serde_json::to_string(metadata)?)?;
+                self.writer.write_all(&[COLUMNS])?;
+                write_u32(&mut self.writer, self.columns.len() as u32)?;
+                for column in self.columns.iter() {
+                    write_str(&mut self.writer, column)?;
+                }
+            },
+        }
+        self.writer.flush()
+    }
+
+    /// Write a row, with one value per column.
+    pub fn row(&mut self, values: &[f64]) -> Result<(), VegasError> {
+        if values.len() != self.columns.len() {
+            return Err(VegasError::ColumnMismatch(self.columns.len(), values.len()));
+        }
+        match self.format {
+            Format::Text | Format::Csv => {
+                let separator = if self.format == Format::Csv { "," } else { " " };
+                let values: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+                writeln!(self.writer, "{}", values.join(separator))?;
+            },
+            Format::JsonLines => {
+                let row: Map<String, Value> = self.columns
+                    .iter()
+                    .cloned()
+                    .zip(values.iter().map(|v| json!(v)))
+                    .collect();
+                serde_json::to_writer(&mut self.writer, &row).map_err(io::Error::from)?;
+                writeln!(self.writer)?;
+            },
+            Format::Binary => {
+                self.writer.write_all(&[ROW])?;
+                for value in values {
+                    self.writer.write_all(&value.to_le_bytes())?;
+                }
+            },
+        }
+        Ok(self.writer.flush()?)
+    }
+
+    /// Write a few named numbers that sum up the whole run, like where the
+    /// specific heat peaks.
+    pub fn summary(&mut self, entries: &[(&str, f64)]) -> io::Result<()> {
+        match self.format {
+            Format::Text | Format::Csv => for &(name, value) in entries {
+                writeln!(self.writer, "# {}: {}", name, value)?;
+            },
+            Format::JsonLines => {
+                let summary: Map<String, Value> = entries.iter()
+                    .map(|&(name, value)| (name.to_string(), json!(value)))
+                    .collect();
+                serde_json::to_writer(&mut self.writer, &json!({ "summary": summary }))?;
+                writeln!(self.writer)?;
+            },
+            Format::Binary => {
+                self.writer.write_all(&[SUMMARY])?;
+                write_u32(&mut self.writer, entries.len() as u32)?;
+                for &(name, value) in entries {
+                    write_str(&mut self.writer, name)?;
+                    self.writer.write_all(&value.to_le_bytes())?;
+                }
+            },
+        }
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+
+/// Results read back from the binary format.
+#[derive(Clone, Debug)]
+pub struct Results {
+    metadata: Metadata,
+    columns: Vec<String>,
+    rows: Vec<Vec<f64>>,
+    summary: Vec<(String, f64)>,
+}
+
+
+impl Results {
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    pub fn rows(&self) -> &[Vec<f64>] {
+        &self.rows
+    }
+
+    /// The values of the column called `name`, one per row.
+    pub fn column(&self, name: &str) -> Option<Vec<f64>> {
+        let k = self.columns.iter().position(|c| c == name)?;
+        Some(self.rows.iter().map(|row| row[k]).collect())
+    }
+
+    pub fn summary(&self) -> &[(String, f64)] {
+        &self.summary
+    }
+}
+
+
+fn invalid_data<T>(msg: &str) -> io::Result<T> {
+    Err(io::Error::new(io::ErrorKind::InvalidData, msg.to_string()))
+}
+
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+
+fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(f64::from_le_bytes(bytes))
+}
+
+
+/// Lengths come from the file, so the bytes are read as they come rather
+/// than allocated upfront.
+fn read_str<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = u64::from(read_u32(reader)?);
+    let mut bytes = Vec::new();
+    if reader.take(len).read_to_end(&mut bytes)? as u64 != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).or_else(|_| invalid_data("strings should be UTF-8"))
+}
+
+
+/// Read results written in the binary format.
+pub fn read_binary<R: Read>(mut reader: R) -> io::Result<Results> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return invalid_data("these are not binary vegas results");
+    }
+    if read_u32(&mut reader)? != BINARY_VERSION {
+        return invalid_data("unsupported version of the binary results");
+    }
+    let metadata = serde_json::from_str(&read_str(&mut reader)?)?;
+    let mut results = Results {
+        metadata,
+        columns: Vec::new(),
+        rows: Vec::new(),
+        summary: Vec::new(),
+    };
+    let mut tag = [0];
+    while reader.read(&mut tag)? == 1 {
+        match tag[0] {
+            COLUMNS => {
+                results.columns.clear();
+                for _ in 0..read_u32(&mut reader)? {
+                    results.columns.push(read_str(&mut reader)?);
+                }
+            },
+            ROW => {
+                let row = (0..results.columns.len())
+                    .map(|_| read_f64(&mut reader))
+                    .collect::<Result<_, _>>()?;
+                results.rows.push(row);
+            },
+            SUMMARY => for _ in 0..read_u32(&mut reader)? {
+                let name = read_str(&mut reader)?;
+                results.summary.push((name, read_f64(&mut reader)?));
+            },
+            _ => return invalid_data("unknown chunk in the binary results"),
+        }
+    }
+    Ok(results)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{columns, read_binary, values, Format, Metadata, ResultsWriter};
+    use energy::ExchangeEnergy;
+    use error::VegasError;
+    use integrator::MetropolisIntegrator;
+    use lattice::{Geometry, LatticeBuilder, UnitCell};
+    use observables::{Observables, OrderParameter};
+    use schedule::Schedule;
+    use serde_json::{self, Value};
+    use simulation::{Simulation, Stage};
+    use state::{IsingSpin, State};
+    use std::io;
+
+    fn stages() -> (Metadata, Vec<String>, Vec<Stage>) {
+        let lattice = LatticeBuilder::new(UnitCell::honeycomb()).extent(3, 3, 1).build().unwrap();
+        let geometry = Geometry::from_lattice(&lattice);
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let observables = Observables::new(36)
+            .with_order_parameters(OrderParameter::sublattices(&geometry));
+        let schedule = Schedule::explicit(vec![2.0, 1.0]).unwrap().with_sweeps(0, 20).unwrap();
+        let state = State::<IsingSpin>::up_with_size(36);
+        let integrator = MetropolisIntegrator::new(2.0).unwrap();
+        let mut simulation = Simulation::new(integrator, state, schedule)
+            .with_observables(observables.clone());
+        let mut stages = Vec::new();
+        simulation.run(&exchange, |stage| stages.push(stage)).unwrap();
+        let metadata = Metadata::new(json!([{"term": "exchange", "coupling": 1.0}]), 36)
+            .with_seed(5)
+            .with_lattice(&lattice);
+        (metadata, columns(&["exchange".to_string()], &observables), stages)
+    }
+
+    fn write(format: Format) -> Vec<u8> {
+        let (metadata, columns, stages) = stages();
+        let mut writer = ResultsWriter::new(Vec::new(), format, columns);
+        writer.header(&metadata).unwrap();
+        for stage in stages.iter() {
+            writer.row(&values(stage)).unwrap();
+        }
+        writer.summary(&[("specific_heat_peak", 1.5)]).unwrap();
+        writer.into_inner()
+    }
+
+    #[test]
+    fn csv_results_have_metadata_and_a_header() {
+        let (_, columns, stages) = stages();
+        assert_eq!(columns.len(), values(&stages[0]).len());
+        let text = String::from_utf8(write(Format::Csv)).unwrap();
+        let lines: Vec<&str> = text.lines().filter(|l| !l.starts_with('#')).collect();
+        assert!(text.contains("# seed: 5"));
+        assert!(text.contains("\"coupling\":1.0"));
+        assert_eq!(lines[0].split(',').collect::<Vec<_>>(), columns);
+        assert!(lines[0].contains("m_A_err"));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1].split(',').count(), columns.len());
+        assert!(lines[2].starts_with("1,0,"));
+    }
+
+    #[test]
+    fn json_lines_are_self_describing() {
+        let text = String::from_utf8(write(Format::JsonLines)).unwrap();
+        let lines: Vec<Value> = text.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0]["metadata"]["sites"], 36);
+        assert_eq!(lines[0]["metadata"]["lattice"].as_str().unwrap().len(), 16);
+        assert_eq!(lines[1]["temp"], 2.0);
+        assert!(lines[2]["energy_err"].is_number());
+        assert_eq!(lines[3]["summary"]["specific_heat_peak"], 1.5);
+    }
+
+    #[test]
+    fn rows_need_a_value_per_column() {
+        let mut writer = ResultsWriter::new(Vec::new(), Format::Csv, vec!["temp".to_string()]);
+        match writer.row(&[1.0, 2.0]) {
+            Err(VegasError::ColumnMismatch(1, 2)) => (),
+            _ => panic!("two values do not fit a single column"),
+        }
+        assert!(writer.into_inner().is_empty());
+    }
+
+    #[test]
+    fn truncated_binary_results_are_rejected() {
+        let binary = write(Format::Binary);
+        // The metadata claims to be 4 GiB long.
+        let mut huge = binary[..12].to_vec();
+        huge.extend_from_slice(&[0xff; 4]);
+        huge.extend_from_slice(b"{}");
+        let error = read_binary(&huge[..]).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert!(read_binary(&binary[..binary.len() - 3]).is_err());
+    }
+
+    #[test]
+    fn binary_results_round_trip() {
+        let (metadata, columns, stages) = stages();
+        let results = read_binary(&write(Format::Binary)[..]).unwrap();
+        assert_eq!(results.metadata(), &metadata);
+        assert_eq!(results.columns(), &columns[..]);
+        assert_eq!(results.rows()[1], values(&stages[1]));
+        assert_eq!(results.column("temp").unwrap(), vec![2.0, 1.0]);
+        assert_eq!(results.summary(), &[("specific_heat_peak".to_string(), 1.5)]);
+        assert!(read_binary(&b"not vegas results"[..]).is_err());
+        assert_eq!(Format::from_path("out.jsonl"), Format::JsonLines);
+    }
+}
@@ -0,0 +1,403 @@
+//! Population annealing, after Hukushima and Iba, and Machta.
+//!
+//! A population of replicas is cooled along a schedule. At every step the
+//! replicas are reweighted with `exp(-(1 / T' - 1 / T) E)` to the new
+//! temperature and resampled in proportion to their weights, then swept
+//! independently, in parallel, for a few sweeps. The mean of the weights
+//! is the ratio of the partition functions at both temperatures, so the
+//! free energy comes out along the way.
+//!
+//! Resampling only copies states between the slots of the population,
+//! every slot keeps its integrator and with it its stream of random
+//! numbers, so runs do not depend on the number of threads.
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use energy::EnergyComponent;
+use error::{self, VegasError};
+use integrator::{Integrator, Thermostat};
+use observables::Observables;
+use rng::XorShift128;
+use schedule::Schedule;
+use state::{Spin, State};
+use statistics::log_sum_exp;
+
+
+/// Sweeps every replica does at every temperature by default.
+pub const SWEEPS: usize = 10;
+
+
+/// What the population looked like at one temperature of the schedule.
+#[derive(Clone, Debug)]
+pub struct PopulationStage {
+    temp: f64,
+    observables: Observables,
+    log_partition_function: f64,
+    effective_size: f64,
+    families: usize,
+    family_entropy: f64,
+}
+
+
+impl PopulationStage {
+    pub fn temp(&self) -> f64 {
+        self.temp
+    }
+
+    /// One sample per replica, so averages are population averages.
+    pub fn observables(&self) -> &Observables {
+        &self.observables
+    }
+
+    /// Estimate of `ln Z`, relative to the one given for the first
+    /// temperature.
+    pub fn log_partition_function(&self) -> f64 {
+        self.log_partition_function
+    }
+
+    /// Estimate of the free energy, `F = - T ln Z`.
+    pub fn free_energy(&self) -> f64 {
+        - self.temp * self.log_partition_function
+    }
+
+    /// `1 / sum_i p_i^2` over the normalized weights of the resampling
+    /// into this temperature, as a fraction of the population. Small values
+    /// mean few replicas carried the step, which was then too large.
+    pub fn effective_size(&self) -> f64 {
+        self.effective_size
+    }
+
+    /// Number of replicas of the first temperature that still have
+    /// descendants.
+    pub fn families(&self) -> usize {
+        self.families
+    }
+
+    /// `- sum_f v_f ln v_f`, with `v_f` the fraction of the population
+    /// descending from replica `f` of the first temperature. Its
+    /// exponential is an effective number of independent families, the
+    /// averages are only to be trusted when it is well above one.
+    pub fn family_entropy(&self) -> f64 {
+        self.family_entropy
+    }
+}
+
+
+/// A population of replicas annealed along a schedule.
+pub struct PopulationAnnealing<I, S: Spin> {
+    integrators: Vec<I>,
+    states: Vec<State<S>>,
+    /// The replica of the first temperature every state descends from.
+    families: Vec<usize>,
+    /// Total energy of every state at the current temperature.
+    energies: Vec<f64>,
+    schedule: Schedule,
+    sweeps: usize,
+    rng: XorShift128,
+    log_partition_function: f64,
+    observables: Observables,
+}
+
+
+impl<I, S> PopulationAnnealing<I, S>
+    where I: Thermostat + Send,
+          S: Spin + Clone + Send + Sync
+{
+    /// New up a population, one replica for every integrator and state.
+    /// The integrators should be seeded differently, replicas sharing a
+    /// stream of random numbers are not independent.
+    pub fn new(integrators: Vec<I>, states: Vec<State<S>>, schedule: Schedule)
+        -> Result<Self, VegasError>
+    {
+        if states.is_empty() {
+            return error::invalid("the population needs at least one replica".to_string());
+        }
+        if integrators.len() != states.len() {
+            return Err(VegasError::SizeMismatch(states.len(), integrators.len()));
+        }
+        let observables = Observables::new(states[0].len());
+        Ok(Self {
+            families: (0..states.len()).collect(),
+            energies: Vec::new(),
+            integrators,
+            states,
+            schedule,
+            sweeps: SWEEPS,
+            rng: XorShift128::new_unseeded(),
+            log_partition_function: 0.0,
+            observables,
+        })
+    }
+
+    /// Sweep every replica `sweeps` times at every temperature.
+    pub fn with_sweeps(mut self, sweeps: usize) -> Self {
+        self.sweeps = sweeps;
+        self
+    }
+
+    /// Seed the resampling, runs with the same seeds are identical.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = XorShift128::seed_from_u64(seed);
+        self
+    }
+
+    /// `ln Z` at the first temperature, known for instance at high enough
+    /// temperatures, which makes the free energies absolute. Zero by
+    /// default.
+    pub fn with_log_partition_function(mut self, log_partition_function: f64) -> Self {
+        self.log_partition_function = log_partition_function;
+        self
+    }
+
+    /// Measure with a copy of these, still empty, observables at every
+    /// temperature.
+    pub fn with_observables(mut self, observables: Observables) -> Self {
+        self.observables = observables;
+        self
+    }
+
+    /// Number of replicas.
+    pub fn size(&self) -> usize {
+        self.states.len()
+    }
+
+    pub fn states(&self) -> &[State<S>] {
+        &self.states
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Reweight and resample the population to the next temperature of the
+    /// schedule, then sweep every replica there. Returns `None` once the
+    /// schedule is over, fails if the Hamiltonian does not apply to the
+    /// replicas or keeps state between moves, like `CachedExchange`, since
+    /// the replicas share it.
+    pub fn advance<T>(&mut self, hamiltonian: &T) -> Result<Option<PopulationStage>, VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S> + Sync
+    {
+        if hamiltonian.stateful() {
+            return error::invalid(
+                "the replicas cannot share a hamiltonian that keeps state between moves"
+                    .to_string());
+        }
+        for state in self.states.iter() {
+            hamiltonian.check(state)?;
+        }
+        let previous = self.schedule.current();
+        let temp = match self.schedule.next() {
+            Some(temp) => temp,
+            None => return Ok(None),
+        };
+        let effective_size = match previous {
+            Some(previous) => self.resample(1.0 / temp - 1.0 / previous),
+            None => 1.0,
+        };
+        let sweeps = self.sweeps;
+        self.integrators.par_iter_mut()
+            .zip(self.states.par_iter_mut())
+            .try_for_each(|(integrator, state)| {
+                integrator.set_temp(temp)?;
+                for _ in 0..sweeps {
+                    *state = integrator.step(hamiltonian, state)?;
+                }
+                Ok::<(), VegasError>(())
+            })?;
+        self.energies = self.states.par_iter()
+            .map(|state| hamiltonian.total_energy(state))
+            .collect();
+        let mut observables = self.observables.clone();
+        for state in self.states.iter() {
+            observables.measure(hamiltonian, state);
+        }
+        self.schedule.feedback(observables.specific_heat(temp));
+        let (families, family_entropy) = self.family_statistics();
+        Ok(Some(PopulationStage {
+            temp,
+            observables,
+            log_partition_function: self.log_partition_function,
+            effective_size,
+            families,
+            family_entropy,
+        }))
+    }
+
+    /// Resample the population with weights `exp(- dbeta E)` by systematic
+    /// resampling, which keeps its size. Returns the effective size of the
+    /// weights as a fraction of the population.
+    fn resample(&mut self, dbeta: f64) -> f64 {
+        let size = self.size();
+        let logs: Vec<f64> = self.energies.iter().map(|e| - dbeta * e).collect();
+        let log_sum = log_sum_exp(logs.iter().cloned());
+        self.log_partition_function += log_sum - (size as f64).ln();
+        let probabilities: Vec<f64> = logs.iter().map(|l| (l - log_sum).exp()).collect();
+        let effective_size = 1.0 / probabilities.iter().map(|p| p * p).sum::<f64>();
+        let offset = self.rng.gen::<f64>();
+        let mut picks = Vec::with_capacity(size);
+        let mut cumulative = 0.0;
+        for (replica, p) in probabilities.iter().enumerate() {
+            cumulative += p * size as f64;
+            while picks.len() < size && (picks.len() as f64 + offset) < cumulative {
+                picks.push(replica);
+            }
+        }
+        // Rounding may leave the last pick out.
+        while picks.len() < size {
+            picks.push(size - 1);
+        }
+        self.states = picks.iter().map(|&k| self.states[k].clone()).collect();
+        self.families = picks.iter().map(|&k| self.families[k]).collect();
+        self.energies = picks.iter().map(|&k| self.energies[k]).collect();
+        effective_size / size as f64
+    }
+
+    fn family_statistics(&self) -> (usize, f64) {
+        let mut counts = vec![0usize; self.size()];
+        for &family in self.families.iter() {
+            counts[family] += 1;
+        }
+        let size = self.size() as f64;
+        let entropy = counts.iter()
+            .filter(|&&n| n > 0)
+            .map(|&n| n as f64 / size)
+            .map(|v| - v * v.ln())
+            .sum();
+        (counts.iter().filter(|&&n| n > 0).count(), entropy)
+    }
+
+    /// Go through the whole schedule, `report` gets the population at every
+    /// temperature.
+    pub fn run<T, F>(&mut self, hamiltonian: &T, mut report: F) -> Result<(), VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S> + Sync,
+              F: FnMut(PopulationStage)
+    {
+        while let Some(stage) = self.advance(hamiltonian)? {
+            report(stage);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::PopulationAnnealing;
+    use energy::{CompoundEnergy, ExchangeEnergy, ZeemanEnergy};
+    use error::VegasError;
+    use exact::Enumeration;
+    use field::CachedExchange;
+    use integrator::{MetropolisIntegrator, StateGenerator};
+    use lattice::{LatticeBuilder, UnitCell};
+    use rayon::ThreadPoolBuilder;
+    use schedule::Schedule;
+    use state::{HeisenbergSpin, IsingSpin, Spin, State};
+
+    fn square(size: usize) -> ExchangeEnergy {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(size, size, 1).build()
+            .unwrap();
+        ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+    }
+
+    fn population(size: usize, nsites: usize, schedule: Schedule)
+        -> PopulationAnnealing<MetropolisIntegrator, IsingSpin>
+    {
+        let mut integrators: Vec<MetropolisIntegrator> = (0..size)
+            .map(|k| MetropolisIntegrator::new(1.0).unwrap().with_seed(k as u64 + 1))
+            .collect();
+        let states = integrators.iter_mut().map(|i| i.state(nsites)).collect();
+        PopulationAnnealing::new(integrators, states, schedule).unwrap().with_seed(3)
+    }
+
+    #[test]
+    fn populations_are_checked() {
+        let schedule = Schedule::linear(3.0, 1.0, 0.5).unwrap();
+        let integrators = vec![MetropolisIntegrator::new(1.0).unwrap(); 3];
+        let states = vec![State::<IsingSpin>::up_with_size(4); 2];
+        match PopulationAnnealing::new(integrators, states, schedule.clone()) {
+            Err(VegasError::SizeMismatch(2, 3)) => (),
+            _ => panic!("there should be a state for every integrator"),
+        }
+        let states: Vec<State<IsingSpin>> = Vec::new();
+        let integrators: Vec<MetropolisIntegrator> = Vec::new();
+        assert!(PopulationAnnealing::new(integrators, states, schedule).is_err());
+    }
+
+    #[test]
+    fn cached_fields_cannot_be_shared() {
+        let schedule = Schedule::linear(3.0, 1.0, 0.5).unwrap();
+        let integrators = vec![MetropolisIntegrator::new(1.0).unwrap(); 2];
+        let states = vec![State::<HeisenbergSpin>::up_with_size(16); 2];
+        let mut annealing = PopulationAnnealing::new(integrators, states, schedule).unwrap();
+        let hamiltonian = CompoundEnergy::new(ZeemanEnergy::new(HeisenbergSpin::up(), 1.0),
+                                              CachedExchange::new(square(4)));
+        match annealing.advance(&hamiltonian) {
+            Err(VegasError::InvalidParameter(_)) => (),
+            _ => panic!("the replicas would corrupt the cached fields"),
+        }
+        assert_eq!(annealing.schedule().current(), None);
+    }
+
+    #[test]
+    fn free_energies_match_enumeration() {
+        let exchange = square(4);
+        let exact = Enumeration::new::<IsingSpin, _>(&exchange, 16).unwrap();
+        let schedule = Schedule::geometric(10.0, 1.5, 0.85).unwrap();
+        let mut annealing = population(500, 16, schedule)
+            .with_log_partition_function(exact.log_partition_function(10.0));
+        let mut stages = 0;
+        annealing.run(&exchange, |stage| {
+            let temp = stage.temp();
+            let (sampled, expected) = (stage.free_energy(), exact.free_energy(temp));
+            assert!((sampled - expected).abs() < 0.01 * expected.abs(),
+                    "free energy at T = {}: {} against {}", temp, sampled, expected);
+            let (sampled, expected) = (stage.observables().energy(), exact.energy(temp));
+            assert!((sampled - expected).abs() < 0.05 * expected.abs() + 0.5,
+                    "energy at T = {}: {} against {}", temp, sampled, expected);
+            assert_eq!(stage.observables().count(), 500);
+            stages += 1;
+        }).unwrap();
+        assert_eq!(stages, Schedule::geometric(10.0, 1.5, 0.85).unwrap().count());
+    }
+
+    #[test]
+    fn families_die_out_as_the_population_cools() {
+        let exchange = square(8);
+        let schedule = Schedule::linear(4.0, 1.0, 0.25).unwrap();
+        let mut annealing = population(100, 64, schedule).with_sweeps(5);
+        let mut last = (100, 0.0);
+        let mut first = true;
+        annealing.run(&exchange, |stage| {
+            assert!(stage.effective_size() > 0.0 && stage.effective_size() <= 1.0 + 1e-12);
+            if first {
+                // Nothing resampled at the first temperature.
+                assert_eq!(stage.families(), 100);
+                assert!((stage.effective_size() - 1.0).abs() < 1e-12);
+                first = false;
+            }
+            assert!(stage.families() <= last.0);
+            last = (stage.families(), stage.family_entropy());
+        }).unwrap();
+        assert!(last.0 < 100);
+        assert!(last.1 > 0.0 && last.1 < (100f64).ln());
+    }
+
+    #[test]
+    fn runs_do_not_depend_on_the_number_of_threads() {
+        let exchange = square(6);
+        let run = |threads: usize| {
+            let pool = ThreadPoolBuilder::new().num_threads(threads).build().unwrap();
+            pool.install(|| {
+                let schedule = Schedule::linear(3.0, 1.5, 0.5).unwrap();
+                let mut annealing = population(40, 36, schedule);
+                let mut free_energies = Vec::new();
+                annealing.run(&exchange, |stage| free_energies.push(stage.free_energy())).unwrap();
+                free_energies
+            })
+        };
+        assert_eq!(run(1), run(4));
+    }
+}
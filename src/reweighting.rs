@@ -0,0 +1,359 @@
+//! Histogram reweighting of the time series recorded at one or several
+//! temperatures, after Ferrenberg and Swendsen.
+//!
+//! Reweighting works on the raw time series rather than on binned
+//! histograms, so there is no bin width to tune, but the observables of the
+//! runs have to keep their samples. With a single run the
+//! estimates are only reliable close to the temperature it was recorded at,
+//! with several runs (WHAM) they cover the whole range as long as the energy
+//! distributions of neighboring runs overlap.
+
+use error::{self, VegasError};
+use observables::Observables;
+use statistics::log_sum_exp;
+
+
+/// Stop iterating the free energies once they change less than this.
+const TOLERANCE: f64 = 1e-10;
+
+/// Give up iterating the free energies after this many iterations.
+const MAX_ITERATIONS: usize = 10_000;
+
+/// Number of temperatures scanned when looking for a peak.
+const SCAN_POINTS: usize = 200;
+
+
+/// Reweighted estimates built out of the time series of one or more runs.
+#[derive(Clone, Debug)]
+pub struct Reweighting {
+    nsites: usize,
+    betas: Vec<f64>,
+    energies: Vec<f64>,
+    magnetizations: Vec<f64>,
+    /// Correlated samples count for less, every sample of run `j` weighs
+    /// `1 / g_j` with `g_j = 2 tau_j` its statistical inefficiency. Kept as
+    /// logs.
+    sample_weights: Vec<f64>,
+    /// The distinct energies among the samples, their weighted number of
+    /// samples, as a log, and which one every sample has. Discrete models
+    /// only visit a few energies, so this saves a lot of work.
+    levels: Vec<f64>,
+    multiplicities: Vec<f64>,
+    level_of: Vec<usize>,
+    /// `ln sum_j (N_j / g_j) exp(-beta_j E - ln Z_j)` for every energy level.
+    denominators: Vec<f64>,
+    free_energies: Vec<f64>,
+}
+
+
+impl Reweighting {
+    /// Single histogram reweighting of one run.
+    pub fn single(temp: f64, observables: &Observables) -> Result<Self, VegasError> {
+        Self::multiple(&[(temp, observables)])
+    }
+
+    /// Multiple histogram reweighting of several runs, the free energies of
+    /// the runs are solved for self consistently. Every sample is weighted
+    /// by the inverse statistical inefficiency of its run, so that every run
+    /// counts as its number of independent samples.
+    ///
+    /// Fails if there are no runs, a temperature is not positive, a run
+    /// did not keep its samples or a sample is not finite.
+    pub fn multiple(runs: &[(f64, &Observables)]) -> Result<Self, VegasError> {
+        if runs.is_empty() {
+            return error::invalid("reweighting needs at least one run".to_string());
+        }
+        for &(temp, observables) in runs.iter() {
+            error::check_temp(temp)?;
+            if !observables.keeps_series() {
+                return error::invalid(format!("the run at {} did not keep its samples", temp));
+            }
+            let finite = observables.energies().iter()
+                .chain(observables.magnetizations().iter())
+                .all(|x| x.is_finite());
+            if !finite {
+                return error::invalid(format!("the run at {} has non finite samples", temp));
+            }
+        }
+        let nsites = runs.first().map_or(0, |&(_, o)| o.nsites());
+        let betas: Vec<f64> = runs.iter().map(|&(temp, _)| 1.0 / temp).collect();
+        let inefficiencies: Vec<f64> = runs.iter()
+            .map(|&(_, o)| (2.0 * o.energy_autocorrelation_time()).ln())
+            .collect();
+        let counts: Vec<f64> = runs.iter()
+            .zip(inefficiencies.iter())
+            .map(|(&(_, o), g)| (o.count() as f64).ln() - g)
+            .collect();
+        let sample_weights: Vec<f64> = runs.iter()
+            .zip(inefficiencies.iter())
+            .flat_map(|(&(_, o), &g)| o.energies().iter().map(move |_| -g))
+            .collect();
+        // Adding zero turns -0 into 0, so that both land on the same level.
+        let energies: Vec<f64> = runs.iter()
+            .flat_map(|&(_, o)| o.energies().iter().map(|e| e + 0.0))
+            .collect();
+        let magnetizations: Vec<f64> = runs.iter()
+            .flat_map(|&(_, o)| o.magnetizations().to_vec())
+            .collect();
+        let mut levels = energies.clone();
+        levels.sort_by(f64::total_cmp);
+        levels.dedup();
+        let level_of: Vec<usize> = energies.iter()
+            .map(|e| levels.partition_point(|l| l < e))
+            .collect();
+        let mut multiplicities = vec![0.0f64; levels.len()];
+        for (&level, w) in level_of.iter().zip(sample_weights.iter()) {
+            multiplicities[level] += w.exp();
+        }
+        let mut reweighting = Self {
+            nsites,
+            betas,
+            energies,
+            magnetizations,
+            sample_weights,
+            levels,
+            multiplicities: multiplicities.iter().map(|m| m.ln()).collect(),
+            level_of,
+            denominators: Vec::new(),
+            free_energies: vec![0.0; runs.len()],
+        };
+        for _ in 0..MAX_ITERATIONS {
+            reweighting.denominators = reweighting.levels.iter()
+                .map(|e| log_sum_exp(reweighting.betas.iter()
+                    .zip(counts.iter())
+                    .zip(reweighting.free_energies.iter())
+                    .map(|((beta, n), f)| n - beta * e - f)))
+                .collect();
+            let mut updated: Vec<f64> = reweighting.betas.iter()
+                .map(|&beta| reweighting.log_partition_function(beta))
+                .collect();
+            let reference = updated[0];
+            for f in updated.iter_mut() {
+                *f -= reference;
+            }
+            let change = updated.iter()
+                .zip(reweighting.free_energies.iter())
+                .map(|(a, b)| (a - b).abs())
+                .fold(0.0, f64::max);
+            reweighting.free_energies = updated;
+            if change < TOLERANCE {
+                break;
+            }
+        }
+        Ok(reweighting)
+    }
+
+    /// The log of the partition function at inverse temperature `beta`, up
+    /// to a constant.
+    fn log_partition_function(&self, beta: f64) -> f64 {
+        log_sum_exp(self.levels.iter()
+            .zip(self.multiplicities.iter())
+            .zip(self.denominators.iter())
+            .map(|((e, m), d)| m - beta * e - d))
+    }
+
+    /// The dimensionless free energy `beta F = -ln Z` of every run,
+    /// relative to the first one.
+    pub fn free_energies(&self) -> Vec<f64> {
+        self.free_energies.iter().map(|f| -f).collect()
+    }
+
+    /// The range of temperatures covered by the runs.
+    pub fn range(&self) -> (f64, f64) {
+        let temps = self.betas.iter().map(|b| 1.0 / b);
+        (temps.clone().fold(f64::MAX, f64::min), temps.fold(f64::MIN, f64::max))
+    }
+
+    /// Normalized weights of every sample at a temperature.
+    fn weights(&self, temp: f64) -> Vec<f64> {
+        let beta = 1.0 / temp;
+        let log_z = self.log_partition_function(beta);
+        self.energies.iter()
+            .zip(self.level_of.iter())
+            .zip(self.sample_weights.iter())
+            .map(|((e, &level), w)| (w - beta * e - self.denominators[level] - log_z).exp())
+            .collect()
+    }
+
+    /// The first two moments of a series at a temperature.
+    fn moments(&self, series: &[f64], temp: f64) -> (f64, f64) {
+        self.weights(temp)
+            .iter()
+            .zip(series.iter())
+            .fold((0.0, 0.0), |(m, m2), (w, x)| (m + w * x, m2 + w * x * x))
+    }
+
+    /// The first two moments of the energy at a temperature, they only
+    /// need the energy levels.
+    fn energy_moments(&self, temp: f64) -> (f64, f64) {
+        let beta = 1.0 / temp;
+        let log_z = self.log_partition_function(beta);
+        self.levels.iter()
+            .zip(self.multiplicities.iter())
+            .zip(self.denominators.iter())
+            .map(|((e, m), d)| (e, (m - beta * e - d - log_z).exp()))
+            .fold((0.0, 0.0), |(m, m2), (e, w)| (m + w * e, m2 + w * e * e))
+    }
+
+    /// Mean total energy at a temperature.
+    pub fn energy(&self, temp: f64) -> f64 {
+        self.energy_moments(temp).0
+    }
+
+    /// Specific heat per site at a temperature.
+    pub fn specific_heat(&self, temp: f64) -> f64 {
+        let (e, e2) = self.energy_moments(temp);
+        (e2 - e * e) / (self.nsites as f64 * temp * temp)
+    }
+
+    /// Mean magnetization magnitude at a temperature.
+    pub fn magnetization(&self, temp: f64) -> f64 {
+        self.moments(&self.magnetizations, temp).0
+    }
+
+    /// Magnetic susceptibility per site at a temperature.
+    pub fn susceptibility(&self, temp: f64) -> f64 {
+        let (m, m2) = self.moments(&self.magnetizations, temp);
+        (m2 - m * m) / (self.nsites as f64 * temp)
+    }
+
+    /// Locate the maximum of a reweighted quantity within the range of the
+    /// runs, a coarse scan followed by a golden section search. Returns the
+    /// temperature of the peak and the value there.
+    pub fn peak<F>(&self, f: F) -> (f64, f64)
+        where F: Fn(&Self, f64) -> f64
+    {
+        let (lo, hi) = self.range();
+        if hi <= lo {
+            return (lo, f(self, lo));
+        }
+        let step = (hi - lo) / SCAN_POINTS as f64;
+        let best = (0..SCAN_POINTS + 1)
+            .map(|i| lo + i as f64 * step)
+            .map(|t| (t, f(self, t)))
+            .fold((lo, f64::MIN), |a, b| if b.1 > a.1 { b } else { a });
+        let ratio = (5f64.sqrt() - 1.0) / 2.0;
+        let (mut a, mut b) = ((best.0 - step).max(lo), (best.0 + step).min(hi));
+        while b - a > 1e-8 * (1.0 + best.0.abs()) {
+            let (c, d) = (b - ratio * (b - a), a + ratio * (b - a));
+            if f(self, c) > f(self, d) {
+                b = d;
+            } else {
+                a = c;
+            }
+        }
+        let temp = (a + b) / 2.0;
+        (temp, f(self, temp))
+    }
+
+    /// Temperature and height of the specific heat peak.
+    pub fn specific_heat_peak(&self) -> (f64, f64) {
+        self.peak(|r, t| r.specific_heat(t))
+    }
+
+    /// Temperature and height of the susceptibility peak.
+    pub fn susceptibility_peak(&self) -> (f64, f64) {
+        self.peak(|r, t| r.susceptibility(t))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Reweighting;
+    use observables::Observables;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    const NSITES: usize = 16;
+
+    /// Independent Ising spins in a unit field sampled exactly, the energy
+    /// is `-N tanh(1 / T)` and the specific heat per site
+    /// `sech(1 / T)^2 / T^2`.
+    fn paramagnet(temp: f64, samples: usize, rng: &mut XorShiftRng) -> Observables {
+        correlated_paramagnet(temp, samples, 1, rng)
+    }
+
+    /// The same paramagnet, but every sample is recorded `repeat` times in a
+    /// row, as a slow integrator would.
+    fn correlated_paramagnet(temp: f64, samples: usize, repeat: usize, rng: &mut XorShiftRng)
+        -> Observables
+    {
+        let up = 1.0 / (1.0 + (-2.0 / temp).exp());
+        let mut observables = Observables::new(NSITES).with_series();
+        for _ in 0..samples {
+            let m = (0..NSITES)
+                .map(|_| if rng.gen::<f64>() < up { 1.0 } else { -1.0 })
+                .sum::<f64>();
+            for _ in 0..repeat {
+                observables.push(-m, [0.0, 0.0, m]);
+            }
+        }
+        observables
+    }
+
+    fn exact_energy(temp: f64) -> f64 {
+        - (NSITES as f64) * (1.0 / temp).tanh()
+    }
+
+    #[test]
+    fn single_histogram_close_to_the_run() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let run = paramagnet(1.5, 20_000, &mut rng);
+        let reweighting = Reweighting::single(1.5, &run).unwrap();
+        assert!((reweighting.energy(1.5) - run.energy()).abs() < 1e-9);
+        assert!((reweighting.specific_heat(1.5) - run.specific_heat(1.5)).abs() < 1e-9);
+        assert!((reweighting.energy(1.45) - exact_energy(1.45)).abs() < 0.1);
+    }
+
+    #[test]
+    fn multiple_histograms_interpolate_and_find_peaks() {
+        let mut rng = XorShiftRng::from_seed([4, 3, 2, 1]);
+        let temps = [0.6, 0.7, 0.8, 0.9, 1.0, 1.1];
+        let runs: Vec<Observables> = temps.iter()
+            .map(|&t| paramagnet(t, 20_000, &mut rng))
+            .collect();
+        let pairs: Vec<(f64, &Observables)> = temps.iter().cloned().zip(runs.iter()).collect();
+        let reweighting = Reweighting::multiple(&pairs).unwrap();
+        assert_eq!(reweighting.range(), (0.6, 1.1));
+        for &temp in [0.65, 0.85, 1.05].iter() {
+            assert!((reweighting.energy(temp) - exact_energy(temp)).abs() < 0.05);
+        }
+        // The Schottky peak sits where tanh(x) = 1 / x with x = 1 / T.
+        let (temp, height) = reweighting.specific_heat_peak();
+        assert!((temp - 0.8336).abs() < 0.02, "peak at {}", temp);
+        assert!((height - 0.4392).abs() < 0.02, "peak of {}", height);
+    }
+
+    #[test]
+    fn multiple_histograms_weigh_correlated_runs_down() {
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let temps = [0.8, 1.2];
+        let fast = paramagnet(temps[0], 20_000, &mut rng);
+        let slow = correlated_paramagnet(temps[1], 1_000, 50, &mut rng);
+        assert!(slow.energy_autocorrelation_time() > 10.0 * fast.energy_autocorrelation_time());
+        let reweighting = Reweighting::multiple(&[(temps[0], &fast), (temps[1], &slow)]).unwrap();
+        for &temp in [0.8, 0.9, 1.0, 1.1, 1.2].iter() {
+            let energy = reweighting.energy(temp);
+            assert!((energy - exact_energy(temp)).abs() < 0.1, "{} at {}", energy, temp);
+        }
+        // beta F = -N ln(2 cosh(beta)) for independent spins.
+        let exact = |temp: f64| - (NSITES as f64) * (2.0 * (1.0 / temp).cosh()).ln();
+        let free_energy = reweighting.free_energies()[1];
+        let expected = exact(temps[1]) - exact(temps[0]);
+        assert!((free_energy - expected).abs() < 0.05, "{} vs {}", free_energy, expected);
+    }
+
+    #[test]
+    fn reweighting_rejects_bad_runs() {
+        let mut run = Observables::new(NSITES);
+        run.push(-1.0, [0.0, 0.0, 1.0]);
+        assert!(Reweighting::single(1.0, &run).is_err());
+        let mut run = Observables::new(NSITES).with_series();
+        run.push(-1.0, [0.0, 0.0, 1.0]);
+        assert!(Reweighting::single(1.0, &run).is_ok());
+        assert!(Reweighting::single(0.0, &run).is_err());
+        assert!(Reweighting::multiple(&[]).is_err());
+        run.push(f64::NAN, [0.0, 0.0, 1.0]);
+        assert!(Reweighting::single(1.0, &run).is_err());
+    }
+}
@@ -0,0 +1,113 @@
+//! Random number generators whose state can be saved and restored.
+
+use std::num::Wrapping as w;
+
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+
+/// The xorshift generator behind `rand::XorShiftRng`, it yields the very
+/// same stream for the same seed, but its state can go into a checkpoint.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct XorShift128 {
+    x: u32,
+    y: u32,
+    z: u32,
+    w: u32,
+}
+
+
+impl XorShift128 {
+    /// New up a generator with the fixed seed of
+    /// `rand::XorShiftRng::new_unseeded`.
+    pub fn new_unseeded() -> Self {
+        Self::from_seed([0x193a6754, 0xa8a7d469, 0x97830e05, 0x113ba7bb])
+    }
+
+    /// New up a generator out of a single number, spread over the whole
+    /// state with splitmix64 so that close seeds give unrelated streams.
+    pub fn seed_from_u64(seed: u64) -> Self {
+        let mut z = seed;
+        let mut next = || {
+            z = z.wrapping_add(0x9e3779b97f4a7c15);
+            let mut x = z;
+            x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+            x ^ (x >> 31)
+        };
+        let (a, b) = (next(), next());
+        Self::from_seed([a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32 | 1])
+    }
+}
+
+
+impl Rng for XorShift128 {
+    fn next_u32(&mut self) -> u32 {
+        let x = w(self.x);
+        let t = x ^ (x << 11);
+        self.x = self.y;
+        self.y = self.z;
+        self.z = self.w;
+        let w_ = w(self.w);
+        self.w = (w_ ^ (w_ >> 19) ^ (t ^ (t >> 8))).0;
+        self.w
+    }
+}
+
+
+impl SeedableRng<[u32; 4]> for XorShift128 {
+    /// Panics if `seed` is entirely 0.
+    fn reseed(&mut self, seed: [u32; 4]) {
+        *self = Self::from_seed(seed);
+    }
+
+    /// Panics if `seed` is entirely 0.
+    fn from_seed(seed: [u32; 4]) -> Self {
+        assert!(seed.iter().any(|&x| x != 0), "xorshift seeds cannot be all zeros");
+        Self {
+            x: seed[0],
+            y: seed[1],
+            z: seed[2],
+            w: seed[3],
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::XorShift128;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn same_stream_as_rand() {
+        let mut ours = XorShift128::new_unseeded();
+        let mut theirs = XorShiftRng::new_unseeded();
+        for _ in 0..100 {
+            assert_eq!(ours.next_u32(), theirs.next_u32());
+        }
+        let mut ours = XorShift128::from_seed([1, 2, 3, 4]);
+        let mut theirs = XorShiftRng::from_seed([1, 2, 3, 4]);
+        for _ in 0..100 {
+            assert_eq!(ours.gen::<f64>(), theirs.gen::<f64>());
+        }
+    }
+
+    #[test]
+    fn close_seeds_give_different_streams() {
+        let mut a = XorShift128::seed_from_u64(1);
+        let mut b = XorShift128::seed_from_u64(2);
+        assert_eq!(XorShift128::seed_from_u64(1), a.clone());
+        assert!((0..10).any(|_| a.next_u32() != b.next_u32()));
+        XorShift128::seed_from_u64(0).next_u32();
+    }
+
+    #[test]
+    fn streams_resume_after_a_round_trip() {
+        let mut rng = XorShift128::from_seed([4, 3, 2, 1]);
+        rng.gen::<u64>();
+        let saved = ::serde_json::to_string(&rng).unwrap();
+        let mut restored: XorShift128 = ::serde_json::from_str(&saved).unwrap();
+        assert_eq!(rng.gen::<u64>(), restored.gen::<u64>());
+    }
+}
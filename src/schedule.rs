@@ -0,0 +1,318 @@
+//! Temperature schedules for annealing simulations.
+//!
+//! A schedule hands out temperatures one at a time, it heats or cools
+//! depending on whether it stops above or below where it starts. Along with
+//! the temperatures, it tells how many sweeps to spend thermalizing and
+//! measuring at each one, and whether to look for the end of the transient
+//! on top of the fixed thermalization.
+
+
+use serde::{Deserialize, Serialize};
+
+use error::{check_temp, invalid, VegasError};
+
+
+/// Slack allowed when comparing temperatures against the end of a ramp.
+const TOLERANCE: f64 = 1e-9;
+
+
+/// How a schedule steps from one temperature to the next.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Ramp {
+    /// Evenly spaced temperatures, `step` apart.
+    Linear { start: f64, stop: f64, step: f64 },
+    /// Temperatures in a geometric progression, each `ratio` times the
+    /// previous one when cooling, or `1 / ratio` times when heating.
+    Geometric { start: f64, stop: f64, ratio: f64 },
+    /// An explicit list of temperatures.
+    Explicit(Vec<f64>),
+    /// Steps shrink as the specific heat grows, so that the entropy changes
+    /// by about the same amount from one temperature to the next.
+    Adaptive { start: f64, stop: f64, min_step: f64, max_step: f64 },
+}
+
+
+/// How to tell that the system has reached equilibrium after changing
+/// the temperature.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Equilibration {
+    /// Trust the thermalization sweeps.
+    Fixed,
+    /// Look for the end of the transient in the energy series with the
+    /// marginal standard error rule, see `statistics::mser`.
+    Mser,
+}
+
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Schedule {
+    ramp: Ramp,
+    thermalization: usize,
+    measurement: usize,
+    equilibration: Equilibration,
+    /// Number of temperatures handed out so far.
+    index: usize,
+    current: Option<f64>,
+    /// Specific heat reported for the current temperature, and the entropy
+    /// step adaptive ramps aim for.
+    specific_heat: Option<f64>,
+    entropy: Option<f64>,
+}
+
+
+impl Schedule {
+    /// New up a schedule along a ramp, which must only go through positive
+    /// temperatures and make some progress at every step.
+    pub fn new(ramp: Ramp) -> Result<Self, VegasError> {
+        match ramp {
+            Ramp::Linear { start, stop, step } => {
+                check_temp(start)?;
+                check_temp(stop)?;
+                if step <= 0.0 || step.is_nan() {
+                    return invalid(format!("the step must be positive, got {}", step));
+                }
+            },
+            Ramp::Geometric { start, stop, ratio } => {
+                check_temp(start)?;
+                check_temp(stop)?;
+                if ratio <= 0.0 || ratio >= 1.0 || ratio.is_nan() {
+                    return invalid(format!("the ratio must be positive and not 1, got {}",
+                                           ratio));
+                }
+            },
+            Ramp::Explicit(ref temps) => {
+                for &temp in temps.iter() {
+                    check_temp(temp)?;
+                }
+            },
+            Ramp::Adaptive { start, stop, min_step, max_step } => {
+                check_temp(start)?;
+                check_temp(stop)?;
+                if min_step <= 0.0 || min_step.is_nan() || max_step < min_step {
+                    return invalid(format!(
+                        "steps must be positive with `min_step` up to `max_step`, got {} and {}",
+                        min_step, max_step));
+                }
+            },
+        }
+        Ok(Self {
+            ramp,
+            thermalization: 0,
+            measurement: 1000,
+            equilibration: Equilibration::Fixed,
+            index: 0,
+            current: None,
+            specific_heat: None,
+            entropy: None,
+        })
+    }
+
+    pub fn linear(start: f64, stop: f64, step: f64) -> Result<Self, VegasError> {
+        Self::new(Ramp::Linear { start, stop, step: step.abs() })
+    }
+
+    pub fn geometric(start: f64, stop: f64, ratio: f64) -> Result<Self, VegasError> {
+        let ratio = if ratio > 1.0 { 1.0 / ratio } else { ratio };
+        Self::new(Ramp::Geometric { start, stop, ratio })
+    }
+
+    pub fn explicit(temps: Vec<f64>) -> Result<Self, VegasError> {
+        Self::new(Ramp::Explicit(temps))
+    }
+
+    /// Steps go from `max_step` away from the transition down to `min_step`
+    /// close to it, the first step sets the entropy change to aim for.
+    pub fn adaptive(start: f64, stop: f64, min_step: f64, max_step: f64)
+        -> Result<Self, VegasError>
+    {
+        Self::new(Ramp::Adaptive {
+            start,
+            stop,
+            min_step: min_step.abs(),
+            max_step: max_step.abs(),
+        })
+    }
+
+    /// Set how many sweeps to spend thermalizing and measuring at every
+    /// temperature. Fails without measurement sweeps.
+    pub fn with_sweeps(mut self, thermalization: usize, measurement: usize)
+        -> Result<Self, VegasError>
+    {
+        if measurement == 0 {
+            return invalid("there must be at least one measurement sweep".to_string());
+        }
+        self.thermalization = thermalization;
+        self.measurement = measurement;
+        Ok(self)
+    }
+
+    pub fn with_equilibration(mut self, equilibration: Equilibration) -> Self {
+        self.equilibration = equilibration;
+        self
+    }
+
+    pub fn ramp(&self) -> &Ramp {
+        &self.ramp
+    }
+
+    pub fn thermalization(&self) -> usize {
+        self.thermalization
+    }
+
+    pub fn measurement(&self) -> usize {
+        self.measurement
+    }
+
+    pub fn equilibration(&self) -> Equilibration {
+        self.equilibration
+    }
+
+    /// The last temperature handed out.
+    pub fn current(&self) -> Option<f64> {
+        self.current
+    }
+
+    /// Number of temperatures handed out so far.
+    pub fn position(&self) -> usize {
+        self.index
+    }
+
+    pub fn is_heating(&self) -> bool {
+        match self.ramp {
+            Ramp::Linear { start, stop, .. } |
+            Ramp::Geometric { start, stop, .. } |
+            Ramp::Adaptive { start, stop, .. } => stop > start,
+            Ramp::Explicit(ref temps) => temps.len() > 1 && temps[temps.len() - 1] > temps[0],
+        }
+    }
+
+    /// Report the specific heat per site measured at the current
+    /// temperature, adaptive schedules use it to pick the next step.
+    pub fn feedback(&mut self, specific_heat: f64) {
+        self.specific_heat = Some(specific_heat);
+    }
+
+    fn step(&mut self) -> f64 {
+        match self.ramp {
+            Ramp::Linear { step, .. } => step,
+            Ramp::Adaptive { min_step, max_step, .. } => {
+                let temp = self.current.unwrap_or(0.0);
+                match self.specific_heat {
+                    Some(cv) if cv > 0.0 => {
+                        // dS = C dT / T, calibrated on the first step.
+                        let entropy = *self.entropy.get_or_insert(cv * max_step / temp);
+                        (temp * entropy / cv).max(min_step).min(max_step)
+                    },
+                    _ => max_step,
+                }
+            },
+            _ => 0.0,
+        }
+    }
+
+    fn advance(&mut self) -> Option<f64> {
+        let heating = self.is_heating();
+        let past = |temp: f64, stop: f64| {
+            if heating { temp > stop + TOLERANCE } else { temp < stop - TOLERANCE }
+        };
+        let next = match self.current {
+            None => match self.ramp {
+                Ramp::Linear { start, .. } |
+                Ramp::Geometric { start, .. } |
+                Ramp::Adaptive { start, .. } => Some(start),
+                Ramp::Explicit(ref temps) => temps.first().cloned(),
+            },
+            Some(temp) => match self.ramp.clone() {
+                Ramp::Linear { stop, .. } | Ramp::Adaptive { stop, .. } => {
+                    let step = self.step();
+                    let next = if heating { temp + step } else { temp - step };
+                    if past(next, stop) { None } else { Some(next) }
+                },
+                Ramp::Geometric { stop, ratio, .. } => {
+                    let next = if heating { temp / ratio } else { temp * ratio };
+                    if past(next, stop) { None } else { Some(next) }
+                },
+                Ramp::Explicit(ref temps) => temps.get(self.index).cloned(),
+            },
+        };
+        if next.is_some() {
+            self.index += 1;
+            self.current = next;
+            self.specific_heat = None;
+        }
+        next
+    }
+}
+
+
+impl Iterator for Schedule {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        self.advance()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::Schedule;
+
+    fn close(a: &[f64], b: &[f64]) -> bool {
+        a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| (x - y).abs() < 1e-9)
+    }
+
+    #[test]
+    fn linear_schedules_cool_and_heat() {
+        let cooling: Vec<f64> = Schedule::linear(1.0, 0.5, 0.1).unwrap().collect();
+        assert!(close(&cooling, &[1.0, 0.9, 0.8, 0.7, 0.6, 0.5]));
+        let heating = Schedule::linear(0.5, 1.0, 0.25).unwrap();
+        assert!(heating.is_heating());
+        assert!(close(&heating.collect::<Vec<_>>(), &[0.5, 0.75, 1.0]));
+    }
+
+    #[test]
+    fn geometric_and_explicit_schedules() {
+        let geometric: Vec<f64> = Schedule::geometric(8.0, 1.0, 0.5).unwrap().collect();
+        assert!(close(&geometric, &[8.0, 4.0, 2.0, 1.0]));
+        let heating: Vec<f64> = Schedule::geometric(1.0, 8.0, 2.0).unwrap().collect();
+        assert!(close(&heating, &[1.0, 2.0, 4.0, 8.0]));
+        let explicit: Vec<f64> = Schedule::explicit(vec![3.0, 2.5, 2.2]).unwrap().collect();
+        assert!(close(&explicit, &[3.0, 2.5, 2.2]));
+    }
+
+    #[test]
+    fn adaptive_schedules_refine_near_peaks() {
+        let mut schedule = Schedule::adaptive(3.0, 1.0, 0.01, 0.5).unwrap()
+            .with_sweeps(10, 100).unwrap();
+        assert_eq!((schedule.thermalization(), schedule.measurement()), (10, 100));
+        let mut temps = Vec::new();
+        while let Some(temp) = schedule.next() {
+            temps.push(temp);
+            // A specific heat peak at T = 2.
+            schedule.feedback(1.0 / (0.01 + (temp - 2.0).powi(2)));
+        }
+        let steps: Vec<f64> = temps.windows(2).map(|w| w[0] - w[1]).collect();
+        assert!((steps[0] - 0.5).abs() < 1e-9);
+        let smallest = steps.iter().cloned().fold(f64::MAX, f64::min);
+        assert!(smallest < 0.05);
+        assert!(*temps.last().unwrap() >= 1.0 - 1e-9);
+        assert_eq!(schedule.position(), temps.len());
+    }
+
+    #[test]
+    fn schedules_need_positive_temperatures_and_steps() {
+        assert!(Schedule::linear(-1.0, 0.5, 0.1).is_err());
+        assert!(Schedule::linear(1.0, 0.5, 0.0).is_err());
+        assert!(Schedule::geometric(8.0, 1.0, 1.0).is_err());
+        assert!(Schedule::explicit(vec![1.0, f64::NAN]).is_err());
+        assert!(Schedule::adaptive(3.0, 1.0, 0.5, 0.1).is_err());
+    }
+
+    #[test]
+    fn schedules_need_measurement_sweeps() {
+        let schedule = Schedule::linear(1.0, 0.5, 0.1).unwrap();
+        assert!(schedule.clone().with_sweeps(10, 0).is_err());
+        assert!(schedule.with_sweeps(0, 1).is_ok());
+    }
+}
@@ -0,0 +1,486 @@
+//! Annealing simulations driven by a temperature schedule.
+//!
+//! At every temperature of the schedule the integrator is first run for
+//! the thermalization sweeps, then for the measurement sweeps while the
+//! observables are sampled after every one of them. When the schedule asks
+//! for it, the transient left in the measurements is detected and thrown
+//! away, and the sweeps measured again.
+//!
+//! The energy and magnetization are not recomputed after every sweep: the
+//! integrator keeps running totals from the changes of the moves it
+//! accepts, checked against a full computation every so often.
+//!
+//! A simulation can also be advanced a number of sweeps at a time, stopping
+//! halfway through a temperature if need be, so that long temperatures can
+//! be checkpointed.
+
+use serde::{Deserialize, Serialize};
+
+use energy::EnergyComponent;
+use error::VegasError;
+use integrator::{Integrator, Thermostat, Totals};
+use observables::Observables;
+use schedule::{Equilibration, Schedule};
+use state::{Spin, State};
+use statistics;
+
+
+/// Give up looking for equilibrium after measuring this many times over.
+const MAX_ROUNDS: usize = 10;
+
+
+/// Recompute the running totals from scratch after this many sweeps by
+/// default.
+pub const RESYNC: usize = 100;
+
+
+fn default_resync() -> usize {
+    RESYNC
+}
+
+
+/// What was measured at one temperature of the schedule.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Stage {
+    temp: f64,
+    discarded: usize,
+    observables: Observables,
+}
+
+
+/// Where a simulation is within the temperature it is at.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Progress {
+    temp: f64,
+    /// Thermalization sweeps done.
+    thermalized: usize,
+    /// Measurements thrown away as transients.
+    transients: usize,
+    observables: Observables,
+    /// Sweeps left to measure in this round.
+    pending: usize,
+    /// Rounds of transient detection done, and whether the last one found
+    /// the transient.
+    rounds: usize,
+    settled: bool,
+}
+
+
+/// How far `Simulation::advance_for` got.
+#[derive(Clone, Debug)]
+pub enum Advance {
+    /// Ran out of sweeps halfway through a temperature.
+    Paused,
+    /// Done with a temperature.
+    Stage(Box<Stage>),
+    /// The schedule is over.
+    Finished,
+}
+
+
+impl Stage {
+    pub fn temp(&self) -> f64 {
+        self.temp
+    }
+
+    /// Sweeps spent reaching equilibrium, thermalization included.
+    pub fn discarded(&self) -> usize {
+        self.discarded
+    }
+
+    pub fn observables(&self) -> &Observables {
+        &self.observables
+    }
+
+    pub fn into_observables(self) -> Observables {
+        self.observables
+    }
+
+    /// Forget the samples measured at this temperature, the averages stay.
+    pub fn drop_series(&mut self) {
+        self.observables.drop_series();
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Simulation<I, S: Spin> {
+    integrator: I,
+    state: State<S>,
+    schedule: Schedule,
+    observables: Observables,
+    /// Sweeps done so far, over all temperatures.
+    #[serde(default)]
+    sweeps: usize,
+    /// Running totals of the state, when already computed.
+    #[serde(default)]
+    totals: Option<Totals>,
+    #[serde(default = "default_resync")]
+    resync: usize,
+    /// Largest gap found between the running totals and a full
+    /// computation.
+    #[serde(default)]
+    drift: f64,
+    /// Progress within the current temperature, `None` between two.
+    #[serde(default)]
+    progress: Option<Progress>,
+}
+
+
+impl<I, S> Simulation<I, S>
+    where I: Thermostat,
+          S: Spin
+{
+    /// New up a simulation that will anneal `state` along a schedule.
+    pub fn new(integrator: I, state: State<S>, schedule: Schedule) -> Self {
+        let observables = Observables::new(state.len());
+        Self {
+            integrator,
+            state,
+            schedule,
+            observables,
+            sweeps: 0,
+            totals: None,
+            resync: RESYNC,
+            drift: 0.0,
+            progress: None,
+        }
+    }
+
+    /// Recompute the running totals from scratch every `sweeps` sweeps,
+    /// at least one.
+    pub fn with_resync(mut self, sweeps: usize) -> Self {
+        self.resync = sweeps.max(1);
+        self
+    }
+
+    /// Measure with a copy of these, still empty, observables at every
+    /// temperature. Use it to track order parameters or the topological
+    /// charge, or to keep the samples.
+    pub fn with_observables(mut self, observables: Observables) -> Self {
+        self.observables = observables;
+        self
+    }
+
+    pub fn integrator(&self) -> &I {
+        &self.integrator
+    }
+
+    pub fn state(&self) -> &State<S> {
+        &self.state
+    }
+
+    pub fn schedule(&self) -> &Schedule {
+        &self.schedule
+    }
+
+    /// Number of sweeps done so far, over all temperatures.
+    pub fn sweeps(&self) -> usize {
+        self.sweeps
+    }
+
+    /// Largest difference found so far between the running totals and
+    /// the totals computed from scratch.
+    pub fn drift(&self) -> f64 {
+        self.drift
+    }
+
+    /// Energy and magnetization of the state as tracked during the sweeps,
+    /// `None` before the first one.
+    pub fn totals(&self) -> Option<&Totals> {
+        self.totals.as_ref()
+    }
+
+    /// The observables every temperature starts from.
+    pub fn observables(&self) -> &Observables {
+        &self.observables
+    }
+
+    /// Move on to the next temperature of the schedule, thermalize and
+    /// measure there. Returns `None` once the schedule is over, fails if the
+    /// Hamiltonian does not apply to the state.
+    pub fn advance<T>(&mut self, hamiltonian: &T) -> Result<Option<Stage>, VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S>
+    {
+        self.advance_with(hamiltonian, |_, _| ())
+    }
+
+    /// Like `advance`, but `observer` gets to look at the state after every
+    /// sweep, along with the number of sweeps done so far. Use it to take
+    /// snapshots.
+    pub fn advance_with<T, F>(&mut self, hamiltonian: &T, observer: F)
+        -> Result<Option<Stage>, VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S>,
+              F: FnMut(usize, &State<S>)
+    {
+        match self.advance_for(hamiltonian, usize::MAX, observer)? {
+            Advance::Stage(stage) => Ok(Some(*stage)),
+            Advance::Finished => Ok(None),
+            Advance::Paused => unreachable!("paused without running out of sweeps"),
+        }
+    }
+
+    /// Like `advance_with`, but stop after `sweeps` sweeps even if the
+    /// temperature is not done yet. The next call carries on from there,
+    /// also after saving and loading the simulation.
+    pub fn advance_for<T, F>(&mut self, hamiltonian: &T, sweeps: usize, mut observer: F)
+        -> Result<Advance, VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S>,
+              F: FnMut(usize, &State<S>)
+    {
+        hamiltonian.check(&self.state)?;
+        if self.observables.term_labels().is_empty() {
+            let labels = hamiltonian.breakdown(&self.state).into_iter().map(|(l, _)| l).collect();
+            self.observables = self.observables.clone().with_terms(labels);
+        }
+        let mser = self.schedule.equilibration() == Equilibration::Mser;
+        let mut progress = match self.progress.take() {
+            Some(progress) => progress,
+            None => match self.schedule.next() {
+                // Finding the transient takes the samples, for this stage only
+                // unless the observables keep them anyway.
+                Some(temp) => Progress {
+                    temp,
+                    thermalized: 0,
+                    transients: 0,
+                    observables: if mser {
+                        self.observables.clone().with_series()
+                    } else {
+                        self.observables.clone()
+                    },
+                    pending: self.schedule.measurement(),
+                    rounds: 0,
+                    settled: false,
+                },
+                None => return Ok(Advance::Finished),
+            },
+        };
+        if let Err(e) = self.integrator.set_temp(progress.temp) {
+            self.progress = Some(progress);
+            return Err(e);
+        }
+        let mut left = sweeps;
+        loop {
+            let measuring = if progress.thermalized < self.schedule.thermalization() {
+                false
+            } else if progress.pending > 0 {
+                true
+            } else if mser && !progress.settled && progress.rounds < MAX_ROUNDS {
+                // Still drifting when there is no cut, so start over.
+                let cut = statistics::mser(progress.observables.energies());
+                let transient = cut.unwrap_or_else(|| progress.observables.count());
+                if let Err(e) = progress.observables.discard(transient) {
+                    self.progress = Some(progress);
+                    return Err(e);
+                }
+                progress.transients += transient;
+                progress.pending = transient;
+                progress.rounds += 1;
+                progress.settled = cut.is_some();
+                continue;
+            } else {
+                break;
+            };
+            if left == 0 {
+                self.progress = Some(progress);
+                return Ok(Advance::Paused);
+            }
+            left -= 1;
+            if let Err(e) = self.sweep(hamiltonian, &mut observer) {
+                self.progress = Some(progress);
+                return Err(e);
+            }
+            if measuring {
+                let totals = self.totals.expect("totals tracked by the sweep");
+                progress.observables.measure_with(hamiltonian, &self.state, &totals);
+                progress.pending -= 1;
+            } else {
+                progress.thermalized += 1;
+            }
+        }
+        let Progress { temp, thermalized, transients, mut observables, .. } = progress;
+        if !self.observables.keeps_series() {
+            observables.drop_series();
+        }
+        self.schedule.feedback(observables.specific_heat(temp));
+        let stage = Stage { temp, discarded: thermalized + transients, observables };
+        Ok(Advance::Stage(Box::new(stage)))
+    }
+
+    fn sweep<T, F>(&mut self, hamiltonian: &T, observer: &mut F) -> Result<(), VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S>,
+              F: FnMut(usize, &State<S>)
+    {
+        let mut totals = match self.totals {
+            Some(totals) => totals,
+            None => Totals::of(hamiltonian, &self.state),
+        };
+        self.state = self.integrator.step_tracking(hamiltonian, &self.state, &mut totals)?;
+        self.sweeps += 1;
+        if self.sweeps.is_multiple_of(self.resync) {
+            let exact = Totals::of(hamiltonian, &self.state);
+            self.drift = self.drift.max(totals.distance(&exact));
+            totals = exact;
+        }
+        self.totals = Some(totals);
+        observer(self.sweeps, &self.state);
+        Ok(())
+    }
+
+    /// Go through the whole schedule, `report` gets what was measured at
+    /// every temperature.
+    pub fn run<T, F>(&mut self, hamiltonian: &T, mut report: F) -> Result<(), VegasError>
+        where I: Integrator<S, T>,
+              T: EnergyComponent<S>,
+              F: FnMut(Stage)
+    {
+        while let Some(stage) = self.advance(hamiltonian)? {
+            report(stage);
+        }
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Advance, Simulation};
+    use energy::{EnergyComponent, ExchangeEnergy};
+    use integrator::{MetropolisIntegrator, StateGenerator, Thermostat};
+    use lattice::{LatticeBuilder, UnitCell};
+    use observables::Observables;
+    use schedule::{Equilibration, Schedule};
+    use state::{State, IsingSpin};
+
+    #[test]
+    fn annealing_an_ising_ferromagnet_orders_it() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(8, 8, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let schedule = Schedule::linear(4.0, 1.0, 0.5).unwrap().with_sweeps(100, 200).unwrap();
+        let state = State::<IsingSpin>::down_with_size(64);
+        let integrator = MetropolisIntegrator::new(10.0).unwrap();
+        let mut simulation = Simulation::new(integrator, state, schedule);
+        let mut temps = Vec::new();
+        let mut last = None;
+        simulation.run(&exchange, |stage| {
+            assert_eq!(stage.observables().count(), 200);
+            assert_eq!(stage.discarded(), 100);
+            temps.push(stage.temp());
+            last = Some(stage.into_observables());
+        }).unwrap();
+        assert_eq!(temps.len(), 7);
+        assert!((simulation.integrator().temp() - 1.0).abs() < 1e-9);
+        assert_eq!(simulation.schedule().position(), 7);
+        assert_eq!(simulation.sweeps(), 7 * 300);
+        // Nearly all spins aligned at T = 1, with 128 bonds.
+        let last = last.unwrap();
+        assert!(last.magnetization() > 60.0);
+        assert!((last.energy() + 128.0).abs() < 8.0);
+        assert_eq!(last.term_labels(), ["exchange"]);
+        // Named from the Hamiltonian, so stages have the terms before any sample.
+        assert_eq!(simulation.observables().term_labels(), ["exchange"]);
+        assert_eq!(simulation.observables().count(), 0);
+        let energy = exchange.total_energy(simulation.state());
+        assert!(energy < -100.0);
+    }
+
+    #[test]
+    fn transients_are_detected_and_discarded() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(16, 16, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        // A quench from a random state coarsens slowly at T = 1.5.
+        let schedule = Schedule::explicit(vec![1.5]).unwrap()
+            .with_sweeps(0, 400).unwrap()
+            .with_equilibration(Equilibration::Mser);
+        let mut integrator = MetropolisIntegrator::new(1.5).unwrap().with_seed(1);
+        let state: State<IsingSpin> = integrator.state(256);
+        let mut simulation = Simulation::new(integrator, state, schedule)
+            .with_observables(Observables::new(256).with_series());
+        let stage = simulation.advance(&exchange).unwrap().unwrap();
+        assert!(stage.discarded() > 0);
+        assert_eq!(stage.observables().count(), 400);
+        // The first sample left is already close to the ordered energy.
+        let first = stage.observables().energies()[0];
+        assert!(first < -0.75 * 512.0, "first energy {}", first);
+        assert!(simulation.advance(&exchange).unwrap().is_none());
+    }
+
+    #[test]
+    fn samples_are_dropped_after_finding_transients() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let schedule = Schedule::explicit(vec![2.0]).unwrap()
+            .with_sweeps(10, 100).unwrap()
+            .with_equilibration(Equilibration::Mser);
+        let mut integrator = MetropolisIntegrator::new(2.0).unwrap().with_seed(2);
+        let state: State<IsingSpin> = integrator.state(16);
+        let mut simulation = Simulation::new(integrator, state, schedule);
+        let stage = simulation.advance(&exchange).unwrap().unwrap();
+        assert_eq!(stage.observables().count(), 100);
+        assert!(!stage.observables().keeps_series());
+        assert!(!simulation.observables().keeps_series());
+    }
+
+    #[test]
+    fn running_totals_are_resynchronized() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(8, 8, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let schedule = Schedule::linear(3.0, 1.0, 1.0).unwrap().with_sweeps(50, 50).unwrap();
+        let state = State::<IsingSpin>::down_with_size(64);
+        let integrator = MetropolisIntegrator::new(3.0).unwrap();
+        let mut simulation = Simulation::new(integrator, state, schedule)
+            .with_resync(7)
+            .with_observables(Observables::new(64).with_series());
+        assert!(simulation.totals().is_none());
+        simulation.run(&exchange, |stage| {
+            let last = *stage.observables().energies().last().unwrap();
+            assert!(last.is_finite());
+        }).unwrap();
+        let totals = simulation.totals().unwrap();
+        assert!((totals.energy() - exchange.total_energy(simulation.state())).abs() < 1e-9);
+        assert!(simulation.drift() < 1e-9);
+    }
+
+    #[test]
+    fn paused_runs_match_uninterrupted_ones() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(4, 4, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let simulation = || {
+            let schedule = Schedule::linear(3.0, 1.0, 1.0).unwrap()
+                .with_sweeps(15, 60).unwrap()
+                .with_equilibration(Equilibration::Mser);
+            let mut integrator = MetropolisIntegrator::new(3.0).unwrap().with_seed(3);
+            let state: State<IsingSpin> = integrator.state(16);
+            Simulation::new(integrator, state, schedule)
+                .with_observables(Observables::new(16).with_series())
+        };
+        let mut whole = Vec::new();
+        simulation().run(&exchange, |stage| whole.push(stage)).unwrap();
+
+        let mut paused = simulation();
+        let (mut stages, mut pauses) = (Vec::new(), 0);
+        loop {
+            match paused.advance_for(&exchange, 7, |_, _| ()).unwrap() {
+                Advance::Paused => pauses += 1,
+                Advance::Stage(stage) => stages.push(*stage),
+                Advance::Finished => break,
+            }
+            // Carry on from a copy, as a checkpoint would.
+            let saved = serde_json::to_string(&paused).unwrap();
+            paused = serde_json::from_str(&saved).unwrap();
+        }
+        assert!(pauses > 3 * whole.len());
+        let sweeps: usize = whole.iter().map(|s| s.discarded() + s.observables().count()).sum();
+        assert_eq!(paused.sweeps(), sweeps);
+        assert_eq!(stages.len(), whole.len());
+        for (a, b) in stages.iter().zip(whole.iter()) {
+            assert_eq!(a.temp(), b.temp());
+            assert_eq!(a.discarded(), b.discarded());
+            assert_eq!(a.observables().energies(), b.observables().energies());
+        }
+    }
+}
@@ -0,0 +1,554 @@
+//! Snapshots of spin configurations, to look at them in ParaView or OVITO
+//! and to start new runs from them.
+//!
+//! A snapshot puts together the positions of the sites and the spin of
+//! every site. Four text formats are supported, picked by file extension:
+//!
+//! * `.xyz`, extended XYZ with `species`, `pos` and `spin` properties.
+//! * `.vtk`, legacy VTK polydata with the spins as point vectors.
+//! * `.vtp`, XML VTK polydata with the spins as point vectors.
+//! * `.csv`, one row per site with a `kind,x,y,z,sx,sy,sz` header.
+//!
+//! Readers get the spins back as Heisenberg spins, VTK files have to be
+//! written in ASCII for them.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use lattice::Geometry;
+use state::{HeisenbergSpin, Spin, State};
+
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum Format {
+    Xyz,
+    Vtk,
+    Vtp,
+    Csv,
+}
+
+
+impl Format {
+    pub fn from_extension(extension: &str) -> Option<Self> {
+        match extension.to_lowercase().as_str() {
+            "xyz" => Some(Format::Xyz),
+            "vtk" => Some(Format::Vtk),
+            "vtp" => Some(Format::Vtp),
+            "csv" => Some(Format::Csv),
+            _ => None,
+        }
+    }
+
+    /// Pick the format after the extension of a path.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self, SnapshotError> {
+        let path = path.as_ref();
+        path.extension()
+            .and_then(|e| e.to_str())
+            .and_then(Self::from_extension)
+            .ok_or_else(|| SnapshotError::UnknownFormat(path.display().to_string()))
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Xyz => "xyz",
+            Format::Vtk => "vtk",
+            Format::Vtp => "vtp",
+            Format::Csv => "csv",
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    Io(io::Error),
+    Parse(String),
+    UnknownFormat(String),
+    /// A state that does not fit the geometry, the number of sites first.
+    SizeMismatch(usize, usize),
+}
+
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref e) => write!(f, "could not access the snapshot: {}", e),
+            SnapshotError::Parse(ref msg) => write!(f, "malformed snapshot: {}", msg),
+            SnapshotError::UnknownFormat(ref path) => write!(
+                f, "unknown snapshot format for {}, use xyz, vtk, vtp or csv", path),
+            SnapshotError::SizeMismatch(sites, spins) => write!(
+                f, "{} spins do not fit a geometry of {} sites", spins, sites),
+        }
+    }
+}
+
+
+impl StdError for SnapshotError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            SnapshotError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for SnapshotError {
+    fn from(e: io::Error) -> Self {
+        SnapshotError::Io(e)
+    }
+}
+
+
+fn parse_error<T>(msg: String) -> Result<T, SnapshotError> {
+    Err(SnapshotError::Parse(msg))
+}
+
+
+fn parse_number(token: &str) -> Result<f64, SnapshotError> {
+    token.parse().or_else(|_| parse_error(format!("expected a number, found `{}`", token)))
+}
+
+
+/// Site positions and spins read from a snapshot.
+#[derive(Clone)]
+pub struct Snapshot {
+    positions: Vec<[f64; 3]>,
+    state: State<HeisenbergSpin>,
+}
+
+
+impl Snapshot {
+    fn new(positions: Vec<[f64; 3]>, spins: Vec<[f64; 3]>) -> Result<Self, SnapshotError> {
+        if positions.len() != spins.len() {
+            return parse_error(format!(
+                "{} positions but {} spins", positions.len(), spins.len()));
+        }
+        let mut state = State::up_with_size(spins.len());
+        for (i, spin) in spins.into_iter().enumerate() {
+            if spin.iter().all(|s| *s == 0.0) {
+                return parse_error(format!("the spin of site {} is zero", i));
+            }
+            state.set_at(i, HeisenbergSpin::new(spin));
+        }
+        Ok(Self { positions, state })
+    }
+
+    pub fn positions(&self) -> &[[f64; 3]] {
+        &self.positions
+    }
+
+    pub fn state(&self) -> &State<HeisenbergSpin> {
+        &self.state
+    }
+
+    pub fn into_state(self) -> State<HeisenbergSpin> {
+        self.state
+    }
+}
+
+
+fn check_size<S: Spin>(geometry: &Geometry, state: &State<S>) -> Result<(), SnapshotError> {
+    if geometry.len() != state.len() {
+        return Err(SnapshotError::SizeMismatch(geometry.len(), state.len()));
+    }
+    Ok(())
+}
+
+
+/// Write the spins of a state at the sites of a geometry.
+pub fn write<W, S>(writer: &mut W, format: Format, geometry: &Geometry, state: &State<S>)
+    -> Result<(), SnapshotError>
+    where W: Write,
+          S: Spin
+{
+    check_size(geometry, state)?;
+    let n = state.len();
+    let spins: Vec<[f64; 3]> = state.spins().iter().map(|s| s.magnetization()).collect();
+    let triplets = |writer: &mut W, values: &[[f64; 3]]| -> io::Result<()> {
+        for v in values.iter() {
+            writeln!(writer, "{} {} {}", v[0], v[1], v[2])?;
+        }
+        Ok(())
+    };
+    match format {
+        Format::Xyz => {
+            let size = geometry.size();
+            let pbc: Vec<&str> = geometry.periodic()
+                .iter()
+                .map(|&p| if p { "T" } else { "F" })
+                .collect();
+            writeln!(writer, "{}", n)?;
+            writeln!(writer, "Lattice=\"{} 0 0 0 {} 0 0 0 {}\" \
+                              Properties=species:S:1:pos:R:3:spin:R:3 pbc=\"{}\"",
+                     size[0], size[1], size[2], pbc.join(" "))?;
+            for ((kind, r), s) in geometry.kinds().iter().zip(geometry.positions()).zip(&spins) {
+                writeln!(writer, "{} {} {} {} {} {} {}", kind, r[0], r[1], r[2], s[0], s[1], s[2])?;
+            }
+        },
+        Format::Vtk => {
+            writeln!(writer, "# vtk DataFile Version 3.0")?;
+            writeln!(writer, "vegas snapshot")?;
+            writeln!(writer, "ASCII")?;
+            writeln!(writer, "DATASET POLYDATA")?;
+            writeln!(writer, "POINTS {} double", n)?;
+            triplets(writer, geometry.positions())?;
+            writeln!(writer, "VERTICES {} {}", n, 2 * n)?;
+            for i in 0..n {
+                writeln!(writer, "1 {}", i)?;
+            }
+            writeln!(writer, "POINT_DATA {}", n)?;
+            writeln!(writer, "VECTORS spin double")?;
+            triplets(writer, &spins)?;
+        },
+        Format::Vtp => {
+            writeln!(writer, "<?xml version=\"1.0\"?>")?;
+            writeln!(writer, "<VTKFile type=\"PolyData\" version=\"0.1\" \
+                              byte_order=\"LittleEndian\">")?;
+            writeln!(writer, "<PolyData>")?;
+            writeln!(writer, "<Piece NumberOfPoints=\"{}\" NumberOfVerts=\"{}\" \
+                              NumberOfLines=\"0\" NumberOfStrips=\"0\" NumberOfPolys=\"0\">",
+                     n, n)?;
+            writeln!(writer, "<PointData Vectors=\"spin\">")?;
+            writeln!(writer, "<DataArray type=\"Float64\" Name=\"spin\" \
+                              NumberOfComponents=\"3\" format=\"ascii\">")?;
+            triplets(writer, &spins)?;
+            writeln!(writer, "</DataArray>")?;
+            writeln!(writer, "</PointData>")?;
+            writeln!(writer, "<Points>")?;
+            writeln!(writer, "<DataArray type=\"Float64\" NumberOfComponents=\"3\" \
+                              format=\"ascii\">")?;
+            triplets(writer, geometry.positions())?;
+            writeln!(writer, "</DataArray>")?;
+            writeln!(writer, "</Points>")?;
+            writeln!(writer, "<Verts>")?;
+            writeln!(writer, "<DataArray type=\"Int64\" Name=\"connectivity\" format=\"ascii\">")?;
+            for i in 0..n {
+                writeln!(writer, "{}", i)?;
+            }
+            writeln!(writer, "</DataArray>")?;
+            writeln!(writer, "<DataArray type=\"Int64\" Name=\"offsets\" format=\"ascii\">")?;
+            for i in 0..n {
+                writeln!(writer, "{}", i + 1)?;
+            }
+            writeln!(writer, "</DataArray>")?;
+            writeln!(writer, "</Verts>")?;
+            writeln!(writer, "</Piece>")?;
+            writeln!(writer, "</PolyData>")?;
+            writeln!(writer, "</VTKFile>")?;
+        },
+        Format::Csv => {
+            writeln!(writer, "kind,x,y,z,sx,sy,sz")?;
+            for ((kind, r), s) in geometry.kinds().iter().zip(geometry.positions()).zip(&spins) {
+                writeln!(writer, "{},{},{},{},{},{},{}", kind, r[0], r[1], r[2], s[0], s[1], s[2])?;
+            }
+        },
+    }
+    Ok(())
+}
+
+
+/// Read the positions and spins of a snapshot.
+pub fn read<R: BufRead>(reader: R, format: Format) -> Result<Snapshot, SnapshotError> {
+    match format {
+        Format::Xyz => read_xyz(reader),
+        Format::Vtk => read_vtk(reader),
+        Format::Vtp => read_vtp(reader),
+        Format::Csv => read_csv(reader),
+    }
+}
+
+
+/// Save a snapshot, the format follows the extension of the path.
+pub fn save<P, S>(path: P, geometry: &Geometry, state: &State<S>) -> Result<(), SnapshotError>
+    where P: AsRef<Path>,
+          S: Spin
+{
+    let format = Format::from_path(&path)?;
+    check_size(geometry, state)?;
+    let mut writer = BufWriter::new(File::create(path)?);
+    write(&mut writer, format, geometry, state)?;
+    writer.flush()?;
+    Ok(())
+}
+
+
+/// Load a snapshot, the format follows the extension of the path.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Snapshot, SnapshotError> {
+    let format = Format::from_path(&path)?;
+    read(BufReader::new(File::open(path)?), format)
+}
+
+
+fn triplet(values: &[f64]) -> [f64; 3] {
+    [values[0], values[1], values[2]]
+}
+
+
+fn read_xyz<R: BufRead>(reader: R) -> Result<Snapshot, SnapshotError> {
+    let mut lines = reader.lines();
+    let mut next = || lines.next().unwrap_or_else(|| Ok(String::new()));
+    let count = next()?;
+    let n: usize = count.trim()
+        .parse()
+        .or_else(|_| parse_error(format!("expected the number of sites, found `{}`", count)))?;
+    // Find the columns of the positions and spins among the properties,
+    // every property takes as many columns as its count.
+    let comment = next()?;
+    let mut columns = (1, 4);
+    if let Some(start) = comment.find("Properties=") {
+        let properties = comment[start + 11..].split_whitespace().next().unwrap_or("");
+        let fields: Vec<&str> = properties.split(':').collect();
+        let (mut pos, mut spin, mut column) = (None, None, 0);
+        for field in fields.chunks(3).filter(|f| f.len() == 3) {
+            match field[0] {
+                "pos" => pos = Some(column),
+                "spin" | "magnetic_moment" => spin = Some(column),
+                _ => (),
+            }
+            column += field[2].parse::<usize>().unwrap_or(1);
+        }
+        match (pos, spin) {
+            (Some(pos), Some(spin)) => columns = (pos, spin),
+            _ => return parse_error("the properties lack `pos` or `spin`".to_string()),
+        }
+    }
+    let mut positions = Vec::with_capacity(n);
+    let mut spins = Vec::with_capacity(n);
+    for i in 0..n {
+        let line = next()?;
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        if tokens.len() < columns.0.max(columns.1) + 3 {
+            return parse_error(format!("line {} of the sites is too short", i + 1));
+        }
+        let number = |k: usize| parse_number(tokens[k]);
+        positions.push([number(columns.0)?, number(columns.0 + 1)?, number(columns.0 + 2)?]);
+        spins.push([number(columns.1)?, number(columns.1 + 1)?, number(columns.1 + 2)?]);
+    }
+    Snapshot::new(positions, spins)
+}
+
+
+/// Read `count` triplets of numbers off a stream of tokens.
+fn read_triplets<'a, I>(tokens: &mut I, count: usize) -> Result<Vec<[f64; 3]>, SnapshotError>
+    where I: Iterator<Item = &'a str>
+{
+    let values = tokens.take(3 * count).map(parse_number).collect::<Result<Vec<f64>, _>>()?;
+    if values.len() != 3 * count {
+        return parse_error(format!("expected {} values, found {}", 3 * count, values.len()));
+    }
+    Ok(values.chunks(3).map(triplet).collect())
+}
+
+
+fn read_vtk<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    if text.lines().nth(2).map(|l| l.trim()) != Some("ASCII") {
+        return parse_error("only ASCII legacy VTK files can be read".to_string());
+    }
+    let mut tokens = text.lines().skip(3).flat_map(|l| l.split_whitespace());
+    let mut positions = None;
+    let mut spins = None;
+    while let Some(token) = tokens.next() {
+        match token {
+            "POINTS" => {
+                let n = tokens.next().map(parse_number).unwrap_or(Ok(0.0))? as usize;
+                tokens.next();
+                positions = Some(read_triplets(&mut tokens, n)?);
+            },
+            "VECTORS" => {
+                tokens.next();
+                tokens.next();
+                let n = positions.as_ref().map_or(0, |p: &Vec<[f64; 3]>| p.len());
+                spins = Some(read_triplets(&mut tokens, n)?);
+                break;
+            },
+            _ => (),
+        }
+    }
+    match (positions, spins) {
+        (Some(positions), Some(spins)) => Snapshot::new(positions, spins),
+        _ => parse_error("expected POINTS and spin VECTORS".to_string()),
+    }
+}
+
+
+/// The content of the first `DataArray` after `marker` in an XML file.
+fn data_array<'a>(text: &'a str, marker: &str) -> Option<&'a str> {
+    let start = text.find(marker)?;
+    let rest = &text[start..];
+    let open = rest.find("<DataArray")?;
+    let rest = &rest[open..];
+    let body = rest.find('>')? + 1;
+    let close = rest.find("</DataArray>")?;
+    Some(&rest[body..close])
+}
+
+
+fn read_vtp<R: Read>(mut reader: R) -> Result<Snapshot, SnapshotError> {
+    let mut text = String::new();
+    reader.read_to_string(&mut text)?;
+    let triplets = |marker: &str| -> Result<Vec<[f64; 3]>, SnapshotError> {
+        let body = match data_array(&text, marker) {
+            Some(body) => body,
+            None => return parse_error(format!("no data array after `{}`", marker)),
+        };
+        let values = body.split_whitespace().map(parse_number).collect::<Result<Vec<_>, _>>()?;
+        if values.len() % 3 != 0 {
+            return parse_error(format!("the data array after `{}` is not of vectors", marker));
+        }
+        Ok(values.chunks(3).map(triplet).collect())
+    };
+    let positions = triplets("<Points>")?;
+    let spins = triplets("<PointData")?;
+    Snapshot::new(positions, spins)
+}
+
+
+fn read_csv<R: BufRead>(reader: R) -> Result<Snapshot, SnapshotError> {
+    let mut lines = reader.lines();
+    let header = lines.next().unwrap_or_else(|| Ok(String::new()))?;
+    let names: Vec<&str> = header.split(',').map(|n| n.trim()).collect();
+    let column = |name: &str| names.iter()
+        .position(|n| *n == name)
+        .ok_or_else(|| SnapshotError::Parse(format!("no `{}` column", name)));
+    let columns = [column("x")?, column("y")?, column("z")?,
+                   column("sx")?, column("sy")?, column("sz")?];
+    let mut positions = Vec::new();
+    let mut spins = Vec::new();
+    for line in lines {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let values = columns.iter()
+            .map(|&c| fields.get(c).map_or_else(
+                || parse_error(format!("row `{}` is too short", line)),
+                |f| parse_number(f)))
+            .collect::<Result<Vec<f64>, _>>()?;
+        positions.push(triplet(&values[..3]));
+        spins.push(triplet(&values[3..]));
+    }
+    Snapshot::new(positions, spins)
+}
+
+
+/// Snapshots taken at a fixed interval of sweeps along a run, saved as
+/// `<prefix>-<sweep>.<extension>`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Snapshots {
+    prefix: String,
+    format: Format,
+    interval: usize,
+}
+
+
+impl Snapshots {
+    pub fn new(prefix: &str, format: Format, interval: usize) -> Self {
+        Self {
+            prefix: prefix.to_string(),
+            format,
+            interval,
+        }
+    }
+
+    pub fn interval(&self) -> usize {
+        self.interval
+    }
+
+    pub fn path(&self, sweep: usize) -> PathBuf {
+        PathBuf::from(format!("{}-{:08}.{}", self.prefix, sweep, self.format.extension()))
+    }
+
+    /// Save a snapshot if the sweep falls on the interval.
+    pub fn observe<S: Spin>(&self, sweep: usize, geometry: &Geometry, state: &State<S>)
+        -> Result<(), SnapshotError>
+    {
+        if self.interval == 0 || !sweep.is_multiple_of(self.interval) {
+            return Ok(());
+        }
+        save(self.path(sweep), geometry, state)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lattice::{LatticeBuilder, UnitCell};
+    use rand::{SeedableRng, XorShiftRng};
+    use std::env;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn textured() -> (Geometry, State<HeisenbergSpin>) {
+        let geometry = Geometry::from_lattice(
+            &LatticeBuilder::new(UnitCell::kagome()).extent(2, 2, 1).build().unwrap());
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        (geometry.clone(), State::rand_with_size(geometry.len(), &mut rng))
+    }
+
+    #[test]
+    fn every_format_round_trips() {
+        let (geometry, state) = textured();
+        for &format in [Format::Xyz, Format::Vtk, Format::Vtp, Format::Csv].iter() {
+            let mut buffer = Vec::new();
+            write(&mut buffer, format, &geometry, &state).unwrap();
+            let snapshot = read(Cursor::new(buffer), format).unwrap();
+            assert_eq!(snapshot.positions(), geometry.positions());
+            for (a, b) in snapshot.state().spins().iter().zip(state.spins()) {
+                let (a, b) = (a.magnetization(), b.magnetization());
+                assert!((0..3).all(|k| (a[k] - b[k]).abs() < 1e-12), "{:?}", format);
+            }
+        }
+    }
+
+    #[test]
+    fn states_must_fit_the_geometry() {
+        let (geometry, _) = textured();
+        let state: State<HeisenbergSpin> = State::up_with_size(geometry.len() + 1);
+        match write(&mut Vec::new(), Format::Xyz, &geometry, &state) {
+            Err(SnapshotError::SizeMismatch(sites, spins)) => {
+                assert_eq!((sites, spins), (geometry.len(), geometry.len() + 1));
+            },
+            _ => panic!("expected a size mismatch"),
+        }
+    }
+
+    #[test]
+    fn xyz_columns_follow_the_properties() {
+        let text = "2\n\
+                    Properties=spin:R:3:species:S:1:pos:R:3 Time=1.0\n\
+                    0 0 1 A 0.0 0.0 0.0\n\
+                    1 0 0 B 1.0 0.0 0.0\n";
+        let snapshot = read(Cursor::new(text), Format::Xyz).unwrap();
+        assert_eq!(snapshot.positions(), &[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0]]);
+        assert_eq!(snapshot.state().at(1).magnetization(), [1.0, 0.0, 0.0]);
+        let bad = "1\n\n0 0 0 A 0 0 0\n";
+        assert!(read(Cursor::new(bad), Format::Xyz).is_err());
+    }
+
+    #[test]
+    fn snapshots_are_taken_at_intervals() {
+        let (geometry, state) = textured();
+        let prefix = env::temp_dir().join(format!("vegas-snapshot-{}", ::std::process::id()));
+        let snapshots = Snapshots::new(prefix.to_str().unwrap(), Format::Vtp, 10);
+        for sweep in 1..21 {
+            snapshots.observe(sweep, &geometry, &state).unwrap();
+        }
+        assert!(!snapshots.path(5).exists());
+        let loaded = load(snapshots.path(20)).unwrap();
+        assert_eq!(loaded.state().len(), state.len());
+        for sweep in [10, 20].iter() {
+            fs::remove_file(snapshots.path(*sweep)).unwrap();
+        }
+        assert!(Format::from_path("spins.png").is_err());
+    }
+}
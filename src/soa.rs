@@ -0,0 +1,422 @@
+//! Heisenberg states laid out as a structure of arrays, with energy kernels
+//! that stream through the components.
+//!
+//! A `State<HeisenbergSpin>` keeps the three components of every spin
+//! together, while a `SoaState` keeps all the x components, then all the y
+//! and all the z ones, in single or double precision. Reductions run over
+//! `LANES` independent accumulators, which the compiler can map to SIMD
+//! registers when the sites are visited in order, as in the single site
+//! terms. The exchange kernel visits the sites in order as well, through a
+//! `PaddedExchange` that gives every site the same number of neighbors, so
+//! the neighbors of `LANES` consecutive sites are gathered at once, with
+//! vector gathers on targets that have them.
+
+use std::ops::{Add, AddAssign, Mul, Sub};
+
+use energy::{CompoundEnergy, EnergyComponent, ExchangeEnergy, Gauge, UniaxialAnisotropy,
+             ZeemanEnergy};
+use error::VegasError;
+use state::{HeisenbergSpin, Spin, State};
+
+
+/// Width of the accumulators of the kernels.
+pub const LANES: usize = 8;
+
+
+/// The floating point types a `SoaState` can hold.
+pub trait Real: Copy + Default + Send + Sync + PartialOrd + Add<Output = Self> +
+                Sub<Output = Self> + Mul<Output = Self> + AddAssign
+{
+    fn from_f64(value: f64) -> Self;
+    fn to_f64(self) -> f64;
+}
+
+
+impl Real for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        f64::from(self)
+    }
+}
+
+
+impl Real for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+}
+
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SoaState<F: Real> {
+    xs: Vec<F>,
+    ys: Vec<F>,
+    zs: Vec<F>,
+}
+
+
+impl<F: Real> SoaState<F> {
+    pub fn from_state(state: &State<HeisenbergSpin>) -> Self {
+        let mut soa = Self {
+            xs: Vec::with_capacity(state.len()),
+            ys: Vec::with_capacity(state.len()),
+            zs: Vec::with_capacity(state.len()),
+        };
+        for spin in state.spins() {
+            soa.push(spin.magnetization());
+        }
+        soa
+    }
+
+    fn push(&mut self, spin: [f64; 3]) {
+        self.xs.push(F::from_f64(spin[0]));
+        self.ys.push(F::from_f64(spin[1]));
+        self.zs.push(F::from_f64(spin[2]));
+    }
+
+    /// Back to an array of spins, normalized again when in single
+    /// precision.
+    pub fn to_state(&self) -> State<HeisenbergSpin> {
+        let mut state = State::up_with_size(self.len());
+        for i in 0..self.len() {
+            state.set_at(i, HeisenbergSpin::new(self.at(i)));
+        }
+        state
+    }
+
+    pub fn len(&self) -> usize {
+        self.xs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.xs.is_empty()
+    }
+
+    pub fn at(&self, index: usize) -> [f64; 3] {
+        [self.xs[index].to_f64(), self.ys[index].to_f64(), self.zs[index].to_f64()]
+    }
+
+    pub fn set_at(&mut self, index: usize, spin: &HeisenbergSpin) {
+        let spin = spin.magnetization();
+        self.xs[index] = F::from_f64(spin[0]);
+        self.ys[index] = F::from_f64(spin[1]);
+        self.zs[index] = F::from_f64(spin[2]);
+    }
+
+    pub fn xs(&self) -> &[F] {
+        &self.xs
+    }
+
+    pub fn ys(&self) -> &[F] {
+        &self.ys
+    }
+
+    pub fn zs(&self) -> &[F] {
+        &self.zs
+    }
+
+    pub fn magnetization(&self) -> [f64; 3] {
+        [sum(&self.xs), sum(&self.ys), sum(&self.zs)]
+    }
+}
+
+
+fn lanes<F: Real>(acc: [F; LANES]) -> f64 {
+    acc.iter().map(|a| a.to_f64()).sum()
+}
+
+
+/// Sum of a slice over `LANES` accumulators.
+pub fn sum<F: Real>(xs: &[F]) -> f64 {
+    let mut acc = [F::default(); LANES];
+    let chunks = xs.chunks_exact(LANES);
+    let rest: f64 = chunks.remainder().iter().map(|x| x.to_f64()).sum();
+    for chunk in chunks {
+        for (a, &x) in acc.iter_mut().zip(chunk) {
+            *a += x;
+        }
+    }
+    lanes(acc) + rest
+}
+
+
+/// Sum of `f(n · s)` over the spins `s` of a state.
+pub fn sum_projected<F, G>(state: &SoaState<F>, direction: [f64; 3], f: G) -> f64
+    where F: Real,
+          G: Fn(F) -> F
+{
+    let n = [F::from_f64(direction[0]), F::from_f64(direction[1]), F::from_f64(direction[2])];
+    let project = |x: F, y: F, z: F| f(n[0] * x + n[1] * y + n[2] * z);
+    let mut acc = [F::default(); LANES];
+    let xs = state.xs.chunks_exact(LANES);
+    let ys = state.ys.chunks_exact(LANES);
+    let zs = state.zs.chunks_exact(LANES);
+    let rest: f64 = xs.remainder().iter()
+        .zip(ys.remainder())
+        .zip(zs.remainder())
+        .map(|((&x, &y), &z)| project(x, y, z).to_f64())
+        .sum();
+    for ((x, y), z) in xs.zip(ys).zip(zs) {
+        for (l, a) in acc.iter_mut().enumerate() {
+            *a += project(x[l], y[l], z[l]);
+        }
+    }
+    lanes(acc) + rest
+}
+
+
+/// Sum of the dot products of the spins of two states, site by site.
+pub fn dot<F: Real>(a: &SoaState<F>, b: &SoaState<F>) -> f64 {
+    assert_eq!(a.len(), b.len(), "states of different sizes");
+    let mut acc = [F::default(); LANES];
+    let chunks = |s| <[F]>::chunks_exact(s, LANES);
+    let (axs, ays, azs) = (chunks(&a.xs), chunks(&a.ys), chunks(&a.zs));
+    let (bxs, bys, bzs) = (chunks(&b.xs), chunks(&b.ys), chunks(&b.zs));
+    let rest: f64 = (a.len() - axs.remainder().len()..a.len())
+        .map(|i| (a.xs[i] * b.xs[i] + a.ys[i] * b.ys[i] + a.zs[i] * b.zs[i]).to_f64())
+        .sum();
+    let lhs = axs.zip(ays).zip(azs);
+    let rhs = bxs.zip(bys).zip(bzs);
+    for (((ax, ay), az), ((bx, by), bz)) in lhs.zip(rhs) {
+        for (l, acc) in acc.iter_mut().enumerate() {
+            *acc += ax[l] * bx[l] + ay[l] * by[l] + az[l] * bz[l];
+        }
+    }
+    lanes(acc) + rest
+}
+
+
+/// Total energies of states in the structure of arrays layout.
+pub trait SoaEnergy {
+    /// Fails if the energy does not apply to a state of this size.
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError>;
+}
+
+
+impl SoaEnergy for Gauge {
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        Ok(self.value() * state.len() as f64)
+    }
+}
+
+
+impl SoaEnergy for ZeemanEnergy<HeisenbergSpin> {
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        Ok(- self.strength() * sum_projected(state, self.reference().magnetization(), |p| p))
+    }
+}
+
+
+impl SoaEnergy for UniaxialAnisotropy<HeisenbergSpin> {
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        Ok(self.strength() * sum_projected(state, self.reference().magnetization(), |p| p * p))
+    }
+}
+
+
+/// An exchange matrix with the same number of neighbors for every site,
+/// in the ELLPACK layout.
+///
+/// Neighbor `k` of every site is stored next to neighbor `k` of the
+/// following sites, along with its coupling. Sites with fewer neighbors
+/// than the widest row are padded with couplings of zero to themselves,
+/// which regular lattices do not need.
+#[derive(Clone, Debug)]
+pub struct PaddedExchange {
+    sites: usize,
+    width: usize,
+    neighbors: Vec<u32>,
+    couplings: Vec<f64>,
+}
+
+
+impl PaddedExchange {
+    pub fn new(exchange: &ExchangeEnergy) -> Self {
+        let matrix = exchange.matrix();
+        let (indptr, indices, data) = (matrix.indptr(), matrix.indices(), matrix.data());
+        let sites = matrix.rows();
+        let width = indptr.windows(2).map(|bounds| bounds[1] - bounds[0]).max().unwrap_or(0);
+        let mut neighbors: Vec<u32> = (0..width).flat_map(|_| 0..sites as u32).collect();
+        let mut couplings = vec![0.0; width * sites];
+        for (i, bounds) in indptr.windows(2).enumerate() {
+            let row = indices[bounds[0]..bounds[1]].iter().zip(&data[bounds[0]..bounds[1]]);
+            for (k, (&j, &coupling)) in row.enumerate() {
+                neighbors[k * sites + i] = j as u32;
+                couplings[k * sites + i] = coupling;
+            }
+        }
+        Self { sites, width, neighbors, couplings }
+    }
+
+    /// Number of neighbors of every site, padding included.
+    pub fn width(&self) -> usize {
+        self.width
+    }
+}
+
+
+impl SoaEnergy for PaddedExchange {
+    /// The exchange fields of `LANES` consecutive sites are gathered
+    /// together, one neighbor at a time, then projected on their spins.
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        let n = self.sites;
+        if n != state.len() {
+            return Err(VegasError::SizeMismatch(n, state.len()));
+        }
+        let (xs, ys, zs) = (&state.xs[..n], &state.ys[..n], &state.zs[..n]);
+        let mut acc = [F::default(); LANES];
+        let blocks = n - n % LANES;
+        for start in (0..blocks).step_by(LANES) {
+            let mut field = [[F::default(); LANES]; 3];
+            for k in 0..self.width {
+                let slot = k * n + start..k * n + start + LANES;
+                let neighbors = &self.neighbors[slot.clone()];
+                let couplings = &self.couplings[slot];
+                for l in 0..LANES {
+                    let (j, coupling) = (neighbors[l] as usize, F::from_f64(couplings[l]));
+                    field[0][l] += coupling * xs[j];
+                    field[1][l] += coupling * ys[j];
+                    field[2][l] += coupling * zs[j];
+                }
+            }
+            for (l, acc) in acc.iter_mut().enumerate() {
+                let i = start + l;
+                *acc += xs[i] * field[0][l] + ys[i] * field[1][l] + zs[i] * field[2][l];
+            }
+        }
+        let mut rest = 0.0;
+        for i in blocks..n {
+            let (mut x, mut y, mut z) = (F::default(), F::default(), F::default());
+            for k in 0..self.width {
+                let slot = k * n + i;
+                let j = self.neighbors[slot] as usize;
+                let coupling = F::from_f64(self.couplings[slot]);
+                x += coupling * xs[j];
+                y += coupling * ys[j];
+                z += coupling * zs[j];
+            }
+            rest += (xs[i] * x + ys[i] * y + zs[i] * z).to_f64();
+        }
+        Ok(- (lanes(acc) + rest) / 2.0)
+    }
+}
+
+
+impl SoaEnergy for ExchangeEnergy {
+    /// Pads the exchange on every call, keep a `PaddedExchange` around to
+    /// evaluate many states.
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        PaddedExchange::new(self).soa_total_energy(state)
+    }
+}
+
+
+impl<U, V> SoaEnergy for CompoundEnergy<HeisenbergSpin, U, V>
+    where U: SoaEnergy + EnergyComponent<HeisenbergSpin>,
+          V: SoaEnergy + EnergyComponent<HeisenbergSpin>
+{
+    fn soa_total_energy<F: Real>(&self, state: &SoaState<F>) -> Result<f64, VegasError> {
+        let (a, b) = self.terms();
+        Ok(a.soa_total_energy(state)? + b.soa_total_energy(state)?)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{PaddedExchange, SoaEnergy, SoaState};
+    use energy::{CompoundEnergy, EnergyComponent, ExchangeEnergy, UniaxialAnisotropy,
+                 ZeemanEnergy};
+    use error::VegasError;
+    use lattice::{LatticeBuilder, UnitCell};
+    use rand::{SeedableRng, XorShiftRng};
+    use sprs::TriMat;
+    use state::{HeisenbergSpin, Spin, State};
+
+    #[test]
+    fn soa_states_round_trip() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let state = State::<HeisenbergSpin>::rand_with_size(37, &mut rng);
+        let soa = SoaState::<f64>::from_state(&state);
+        assert_eq!(soa.len(), 37);
+        assert_eq!(soa.at(5), state.at(5).magnetization());
+        let m = soa.magnetization();
+        let expected = state.magnetization();
+        for k in 0..3 {
+            assert!((m[k] - expected[k]).abs() < 1e-12);
+        }
+        let back = SoaState::<f32>::from_state(&state).to_state();
+        assert!((back.at(9).magnetization()[2] - state.at(9).magnetization()[2]).abs() < 1e-6);
+    }
+
+    #[test]
+    fn soa_kernels_match_the_site_energies() {
+        let lattice = LatticeBuilder::new(UnitCell::cubic()).extent(5, 5, 3).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.3).unwrap();
+        let zeeman = ZeemanEnergy::new(HeisenbergSpin::new([1.0, 2.0, 2.0]), 0.7);
+        let anisotropy = UniaxialAnisotropy::new(HeisenbergSpin::new([0.0, 1.0, 1.0]), -0.4);
+        let mut rng = XorShiftRng::from_seed([5, 6, 7, 8]);
+        let state = State::<HeisenbergSpin>::rand_with_size(75, &mut rng);
+        let double = SoaState::<f64>::from_state(&state);
+        let single = SoaState::<f32>::from_state(&state);
+
+        let expected = exchange.total_energy(&state);
+        assert!((exchange.soa_total_energy(&double).unwrap() - expected).abs() < 1e-9);
+        assert!((exchange.soa_total_energy(&single).unwrap() - expected).abs() < 1e-3);
+        let expected = zeeman.total_energy(&state);
+        assert!((zeeman.soa_total_energy(&double).unwrap() - expected).abs() < 1e-9);
+        assert!((zeeman.soa_total_energy(&single).unwrap() - expected).abs() < 1e-3);
+        let expected = anisotropy.total_energy(&state);
+        assert!((anisotropy.soa_total_energy(&double).unwrap() - expected).abs() < 1e-9);
+        assert!((anisotropy.soa_total_energy(&single).unwrap() - expected).abs() < 1e-3);
+
+        let compound = CompoundEnergy::new(zeeman, anisotropy);
+        let expected = compound.total_energy(&state);
+        assert!((compound.soa_total_energy(&double).unwrap() - expected).abs() < 1e-9);
+    }
+
+    #[test]
+    fn padded_exchange_handles_uneven_rows() {
+        // A star, its center couples to every other site, plus a ring.
+        let n = 19;
+        let mut matrix = TriMat::new((n, n));
+        for i in 1..n {
+            let j = i % (n - 1) + 1;
+            for &(a, b, coupling) in [(0, i, 0.5 + i as f64 / 10.0), (i, j, -1.0)].iter() {
+                matrix.add_triplet(a, b, coupling);
+                matrix.add_triplet(b, a, coupling);
+            }
+        }
+        matrix.add_triplet(3, 3, 0.25);
+        let exchange = ExchangeEnergy::new(matrix.to_csr()).unwrap();
+        let padded = PaddedExchange::new(&exchange);
+        assert_eq!(padded.width(), n - 1);
+        let mut rng = XorShiftRng::from_seed([9, 8, 7, 6]);
+        let state = State::<HeisenbergSpin>::rand_with_size(n, &mut rng);
+        let expected = exchange.total_energy(&state);
+        let double = SoaState::<f64>::from_state(&state);
+        assert!((padded.soa_total_energy(&double).unwrap() - expected).abs() < 1e-9);
+        let single = SoaState::<f32>::from_state(&state);
+        assert!((padded.soa_total_energy(&single).unwrap() - expected).abs() < 1e-3);
+    }
+
+    #[test]
+    fn soa_exchange_needs_a_row_per_site() {
+        let lattice = LatticeBuilder::new(UnitCell::square()).extent(3, 3, 1).build().unwrap();
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        for &len in [8, 10].iter() {
+            let state = SoaState::<f64>::from_state(&State::up_with_size(len));
+            match exchange.soa_total_energy(&state) {
+                Err(VegasError::SizeMismatch(9, found)) => assert_eq!(found, len),
+                _ => panic!("a state of {} sites went through", len),
+            }
+        }
+    }
+}
@@ -6,6 +6,7 @@ extern crate rand;
 
 use rand::Rng;
 use rand::distributions::{IndependentSample, Range};
+use serde::{Deserialize, Serialize};
 
 
 /// This trait specifies what a spin is for me.
@@ -22,6 +23,10 @@ pub trait Spin {
 
     /// Interact with another spin
     fn interact(&self, other: &Self) -> f64;
+
+    /// The magnetic moment of the spin as a vector, with `Spin::up()`
+    /// pointing along z.
+    fn magnetization(&self) -> [f64; 3];
 }
 
 
@@ -33,7 +38,23 @@ pub trait PerturbableSpin: Spin {
 }
 
 
-#[derive(Clone)]
+/// This trait represents a spin which can be made to point along a given
+/// direction, as closely as the model allows.
+pub trait OrientableSpin: Spin {
+    /// New up a spin along `direction`, it does not need to be normalized.
+    fn along(direction: [f64; 3]) -> Self;
+}
+
+
+/// This trait represents a spin with finitely many values, so that every
+/// configuration of a small system can be gone through.
+pub trait DiscreteSpin: Spin + Sized {
+    /// Every value of the spin, starting with `Spin::up()`.
+    fn values() -> Vec<Self>;
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
 pub enum IsingSpin {
     Up,
     Down,
@@ -66,6 +87,26 @@ impl Spin for IsingSpin {
             _ => -1f64,
         }
     }
+
+    fn magnetization(&self) -> [f64; 3] {
+        match *self {
+            IsingSpin::Up => [0f64, 0f64, 1f64],
+            IsingSpin::Down => [0f64, 0f64, -1f64],
+        }
+    }
+}
+
+impl OrientableSpin for IsingSpin {
+    /// Up for directions along positive z, down otherwise.
+    fn along(direction: [f64; 3]) -> Self {
+        if direction[2] >= 0.0 { IsingSpin::Up } else { IsingSpin::Down }
+    }
+}
+
+impl DiscreteSpin for IsingSpin {
+    fn values() -> Vec<Self> {
+        vec![IsingSpin::Up, IsingSpin::Down]
+    }
 }
 
 impl PerturbableSpin for IsingSpin {
@@ -79,9 +120,17 @@ impl PerturbableSpin for IsingSpin {
 }
 
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub struct HeisenbergSpin([f64; 3]);
 
+impl HeisenbergSpin {
+    /// New up a spin pointing along a given direction, it gets normalized.
+    pub fn new(direction: [f64; 3]) -> Self {
+        let norm = direction.iter().map(|i| i * i).fold(0f64, |s, i| s + i).sqrt();
+        HeisenbergSpin([direction[0] / norm, direction[1] / norm, direction[2] / norm])
+    }
+}
+
 impl Spin for HeisenbergSpin {
     fn up() -> Self {
         HeisenbergSpin([0f64, 0f64, 1f64])
@@ -96,6 +145,7 @@ impl Spin for HeisenbergSpin {
     fn rand<T: Rng>(rng: &mut T) -> Self {
         loop {
             let (a, b) = rng.gen::<(f64, f64)>();
+            let (a, b) = (2f64 * a - 1f64, 2f64 * b - 1f64);
             let sum = a * a + b * b;
             if sum >= 1f64 {
                 continue;
@@ -113,6 +163,16 @@ impl Spin for HeisenbergSpin {
             .map(|(a, b)| a * b)
             .fold(0f64, |sum, i| sum + i)
     }
+
+    fn magnetization(&self) -> [f64; 3] {
+        self.0
+    }
+}
+
+impl OrientableSpin for HeisenbergSpin {
+    fn along(direction: [f64; 3]) -> Self {
+        HeisenbergSpin::new(direction)
+    }
 }
 
 impl PerturbableSpin for HeisenbergSpin {
@@ -122,7 +182,78 @@ impl PerturbableSpin for HeisenbergSpin {
 }
 
 
-#[derive(Clone)]
+/// A `Q` state Potts spin. Two spins interact only when they are in the
+/// same state, and state `k` has the moment of a clock spin at an angle
+/// `2 pi k / Q` from z, in the xz plane.
+///
+/// There are at least two states, fewer do not compile:
+///
+/// ```compile_fail
+/// use vegas_rs::state::{PottsSpin, Spin};
+/// let spin = PottsSpin::<1>::up();
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PottsSpin<const Q: usize>(usize);
+
+impl<const Q: usize> PottsSpin<Q> {
+    /// Evaluated wherever spins are made, so that `Q < 2` fails to build.
+    const ENOUGH_STATES: () = assert!(Q >= 2, "a Potts spin needs at least two states");
+
+    /// New up a spin in state `k`, modulo `Q`.
+    pub fn new(k: usize) -> Self {
+        let () = Self::ENOUGH_STATES;
+        PottsSpin(k % Q)
+    }
+
+    pub fn value(&self) -> usize {
+        self.0
+    }
+}
+
+impl<const Q: usize> Spin for PottsSpin<Q> {
+    fn up() -> Self {
+        let () = Self::ENOUGH_STATES;
+        PottsSpin(0)
+    }
+
+    /// The state furthest from up, opposite to it for an even `Q`.
+    fn down() -> Self {
+        let () = Self::ENOUGH_STATES;
+        PottsSpin(Q / 2)
+    }
+
+    fn rand<T: Rng>(rng: &mut T) -> Self {
+        let () = Self::ENOUGH_STATES;
+        PottsSpin(rng.gen_range(0, Q))
+    }
+
+    fn interact(&self, other: &Self) -> f64 {
+        if self.0 == other.0 { 1f64 } else { 0f64 }
+    }
+
+    fn magnetization(&self) -> [f64; 3] {
+        let angle = 2f64 * ::std::f64::consts::PI * self.0 as f64 / Q as f64;
+        [angle.sin(), 0f64, angle.cos()]
+    }
+}
+
+impl<const Q: usize> DiscreteSpin for PottsSpin<Q> {
+    fn values() -> Vec<Self> {
+        let () = Self::ENOUGH_STATES;
+        (0..Q).map(PottsSpin).collect()
+    }
+}
+
+impl<const Q: usize> PerturbableSpin for PottsSpin<Q> {
+    /// Any of the other `Q - 1` states.
+    fn perturbation_of<R: Rng>(other: &Self, rng: &mut R) -> Self {
+        let () = Self::ENOUGH_STATES;
+        PottsSpin((other.0 + rng.gen_range(1, Q)) % Q)
+    }
+}
+
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct State<T: Spin>(Vec<T>);
 
 impl<T: Spin> State<T> {
@@ -139,11 +270,10 @@ impl<T: Spin> State<T> {
     }
 
     pub fn spins(&self) -> &Vec<T> {
-        let &State::<T>(ref items) = self;
-        items
+        &self.0
     }
 
-    pub fn at<'a>(&'a self, index: usize) -> &'a T {
+    pub fn at(&self, index: usize) -> &T {
         &self.spins()[index]
     }
 
@@ -154,16 +284,28 @@ impl<T: Spin> State<T> {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The total magnetization vector of the state.
+    pub fn magnetization(&self) -> [f64; 3] {
+        self.0.iter()
+            .map(|s| s.magnetization())
+            .fold([0f64; 3], |m, s| [m[0] + s[0], m[1] + s[1], m[2] + s[2]])
+    }
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{Spin, PerturbableSpin};
+    use super::{DiscreteSpin, Spin, PerturbableSpin};
     use super::IsingSpin;
     use super::HeisenbergSpin;
+    use super::PottsSpin;
     use super::State;
-    use rand::thread_rng;
+    use rand::{thread_rng, SeedableRng, XorShiftRng};
 
     fn real_close(a: f64, b: f64) {
         assert!((a - b).abs() < 1e-15);
@@ -208,6 +350,30 @@ mod tests {
         }
     }
 
+    #[test]
+    fn random_heisenberg_spins_cover_the_sphere() {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        let n = 10_000;
+        let mut mean = [0f64; 3];
+        let mut negative = [false; 3];
+        for _ in 0..n {
+            let HeisenbergSpin(a) = HeisenbergSpin::rand(&mut rng);
+            for ((m, neg), x) in mean.iter_mut().zip(negative.iter_mut()).zip(a.iter()) {
+                *m += x / n as f64;
+                *neg |= *x < 0.0;
+            }
+        }
+        assert!(negative.iter().all(|&neg| neg));
+        assert!(mean.iter().all(|m| m.abs() < 0.05), "mean spin {:?}", mean);
+    }
+
+    #[test]
+    fn heisenberg_spins_are_normalized() {
+        let HeisenbergSpin(a) = HeisenbergSpin::new([3.0, 0.0, 4.0]);
+        real_close(a[0], 0.6);
+        real_close(a[2], 0.8);
+    }
+
     #[test]
     fn perturbation_of_heisenberg_spins() {
         let a = HeisenbergSpin::rand(&mut thread_rng());
@@ -217,6 +383,35 @@ mod tests {
         assert!(aitems != bitems);
     }
 
+    #[test]
+    fn potts_spins_interact_only_in_the_same_state() {
+        let up = PottsSpin::<5>::up();
+        let rand = PottsSpin::<5>::rand(&mut thread_rng());
+        real_close(up.interact(&up), 1.0);
+        real_close(up.interact(&PottsSpin::down()), 0.0);
+        real_close(rand.interact(&rand), 1.0);
+        assert_eq!(PottsSpin::<5>::values().len(), 5);
+        assert_eq!(PottsSpin::<5>::new(7), PottsSpin::new(2));
+        for _ in 0..20 {
+            let other = PottsSpin::perturbation_of(&rand, &mut thread_rng());
+            assert!(other != rand);
+        }
+        // The moments of all the states cancel out.
+        let total = PottsSpin::<5>::values().iter()
+            .map(|s| s.magnetization())
+            .fold([0.0; 3], |m, s| [m[0] + s[0], m[1] + s[1], m[2] + s[2]]);
+        assert!(total.iter().all(|m| m.abs() < 1e-12));
+        assert_eq!(PottsSpin::<4>::down().magnetization()[2], -1.0);
+    }
+
+    #[test]
+    fn magnetization_of_states() {
+        let ups = State::<IsingSpin>::up_with_size(10);
+        let downs = State::<HeisenbergSpin>::down_with_size(10);
+        assert_eq!(ups.magnetization(), [0.0, 0.0, 10.0]);
+        assert_eq!(downs.magnetization(), [0.0, 0.0, -10.0]);
+    }
+
     #[test]
     fn lengths_of_states() {
         let State(items) = State::<HeisenbergSpin>::up_with_size(10);
@@ -0,0 +1,539 @@
+//! Error analysis for correlated Monte Carlo time series.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+
+/// Binning levels stop once there are fewer bins than this.
+const MIN_BINS: usize = 32;
+
+/// Window factor for the automatic windowing of the autocorrelation time.
+const WINDOW_FACTOR: f64 = 6.0;
+
+/// Samples are averaged in batches of this size before looking for the end
+/// of a transient, as in MSER-5.
+const MSER_BATCH: usize = 5;
+
+/// Streaming autocorrelation functions go up to this lag.
+const MAX_LAG: usize = 128;
+
+/// Streaming jackknife estimates use between this many blocks and twice as
+/// many.
+pub const JACKKNIFE_BLOCKS: usize = 32;
+
+
+pub fn mean(series: &[f64]) -> f64 {
+    series.iter().sum::<f64>() / series.len() as f64
+}
+
+
+/// `ln sum_i exp(x_i)` without overflowing, minus infinity for no terms.
+pub fn log_sum_exp<I: Iterator<Item = f64>>(values: I) -> f64 {
+    let values: Vec<f64> = values.collect();
+    let max = values.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+    if max == f64::NEG_INFINITY {
+        return max;
+    }
+    max + values.iter().map(|v| (v - max).exp()).sum::<f64>().ln()
+}
+
+
+/// Unbiased sample variance.
+pub fn variance(series: &[f64]) -> f64 {
+    if series.len() < 2 {
+        return 0.0;
+    }
+    let m = mean(series);
+    series.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / (series.len() - 1) as f64
+}
+
+
+/// Average consecutive blocks of `size` samples, samples that do not fill a
+/// whole block at the end are dropped.
+pub fn bin(series: &[f64], size: usize) -> Vec<f64> {
+    series.chunks(size)
+        .filter(|chunk| chunk.len() == size)
+        .map(mean)
+        .collect()
+}
+
+
+/// Naive standard error of the mean at every binning level, the bin size
+/// doubles from one level to the next.
+pub fn binning_errors(series: &[f64]) -> Vec<f64> {
+    let mut errors = Vec::new();
+    let mut bins = series.to_vec();
+    while bins.len() >= MIN_BINS {
+        errors.push((variance(&bins) / bins.len() as f64).sqrt());
+        bins = bin(&bins, 2);
+    }
+    errors
+}
+
+
+/// Standard error of the mean from a binning analysis. The error grows with
+/// the bin size until bins are uncorrelated, so this takes the largest error
+/// among the levels with enough bins.
+pub fn binning_error(series: &[f64]) -> f64 {
+    binning_errors(series)
+        .into_iter()
+        .fold((variance(series) / series.len() as f64).sqrt(), f64::max)
+}
+
+
+/// Normalized autocorrelation function at a given lag.
+pub fn autocorrelation(series: &[f64], lag: usize) -> f64 {
+    let n = series.len();
+    if lag >= n {
+        return 0.0;
+    }
+    let m = mean(series);
+    let c0 = series.iter().map(|x| (x - m) * (x - m)).sum::<f64>() / n as f64;
+    if c0 == 0.0 {
+        return 0.0;
+    }
+    let ct = series.iter()
+        .zip(series[lag..].iter())
+        .map(|(a, b)| (a - m) * (b - m))
+        .sum::<f64>() / (n - lag) as f64;
+    ct / c0
+}
+
+
+/// Integrated autocorrelation time, `1/2 + sum_t rho(t)`, with the sum cut
+/// at the first window `W >= c tau(W)` (Sokal's automatic windowing).
+pub fn integrated_autocorrelation_time(series: &[f64]) -> f64 {
+    let mut tau = 0.5;
+    for lag in 1..series.len() {
+        tau += autocorrelation(series, lag);
+        if lag as f64 >= WINDOW_FACTOR * tau {
+            break;
+        }
+    }
+    tau.max(0.5)
+}
+
+
+/// Number of leading samples to drop so that the rest looks stationary,
+/// following the marginal standard error rule (MSER-5). The cut minimizes
+/// the squared standard error of the mean of the samples left. Returns
+/// `None` when the transient looks longer than half the series, the series
+/// is too short to tell then.
+pub fn mser(series: &[f64]) -> Option<usize> {
+    let batches = bin(series, MSER_BATCH);
+    let n = batches.len();
+    if n < 2 {
+        return Some(0);
+    }
+    // Suffix sums of the batches and of their squares.
+    let mut sums = vec![0.0; n + 1];
+    let mut squares = vec![0.0; n + 1];
+    for (i, x) in batches.iter().enumerate().rev() {
+        sums[i] = sums[i + 1] + x;
+        squares[i] = squares[i + 1] + x * x;
+    }
+    let statistic = |d: usize| {
+        let len = (n - d) as f64;
+        (squares[d] - sums[d] * sums[d] / len) / (len * len)
+    };
+    let cut = (0..n - 1)
+        .filter(|&d| statistic(d).is_finite())
+        .min_by(|&a, &b| statistic(a).total_cmp(&statistic(b)))
+        .unwrap_or(0);
+    if cut > n / 2 { None } else { Some(cut * MSER_BATCH) }
+}
+
+
+/// Jackknife estimate of a quantity derived from the means of several
+/// series of equal length, returns the value and its error.
+///
+/// The series are cut in `nbins` blocks, `f` gets the means of every
+/// series with one block left out at a time.
+pub fn jackknife<F>(columns: &[&[f64]], nbins: usize, f: F) -> (f64, f64)
+    where F: Fn(&[f64]) -> f64
+{
+    let len = columns.iter().map(|c| c.len()).min().unwrap_or(0);
+    let nbins = nbins.min(len);
+    let value = f(&columns.iter().map(|c| mean(&c[..len])).collect::<Vec<_>>());
+    if nbins < 2 {
+        return (value, 0.0);
+    }
+    let size = len / nbins;
+    let used = size * nbins;
+    let sums: Vec<f64> = columns.iter().map(|c| c[..used].iter().sum()).collect();
+    let estimates: Vec<f64> = (0..nbins)
+        .map(|k| {
+            let means: Vec<f64> = columns.iter()
+                .zip(sums.iter())
+                .map(|(c, sum)| {
+                    let block: f64 = c[k * size..(k + 1) * size].iter().sum();
+                    (sum - block) / (used - size) as f64
+                })
+                .collect();
+            f(&means)
+        })
+        .collect();
+    let m = mean(&estimates);
+    let spread = estimates.iter().map(|e| (e - m) * (e - m)).sum::<f64>();
+    (value, (spread * (nbins - 1) as f64 / nbins as f64).sqrt())
+}
+
+
+/// Count, sum and sum of squares of some samples.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+struct Moments {
+    count: usize,
+    sum: f64,
+    squares: f64,
+}
+
+
+impl Moments {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        self.sum += x;
+        self.squares += x * x;
+    }
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            count: self.count + other.count,
+            sum: self.sum + other.sum,
+            squares: self.squares + other.squares,
+        }
+    }
+
+    /// Naive standard error of the mean, from the unbiased variance.
+    fn error(&self) -> f64 {
+        let n = self.count as f64;
+        let variance = if self.count < 2 {
+            0.0
+        } else {
+            ((self.squares - self.sum * self.sum / n) / (n - 1.0)).max(0.0)
+        };
+        (variance / n).sqrt()
+    }
+}
+
+
+/// The autocorrelation function of a series on the fly, up to `MAX_LAG`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Correlator {
+    /// The first and the last `MAX_LAG` samples.
+    first: Vec<f64>,
+    last: VecDeque<f64>,
+    /// `sum_t x_t x_(t + l)` for every lag `l` from 1.
+    products: Vec<f64>,
+}
+
+
+impl Correlator {
+    fn push(&mut self, x: f64) {
+        if self.products.len() < self.last.len() {
+            self.products.push(0.0);
+        }
+        for (product, y) in self.products.iter_mut().zip(self.last.iter().rev()) {
+            *product += x * y;
+        }
+        if self.first.len() < MAX_LAG {
+            self.first.push(x);
+        }
+        self.last.push_back(x);
+        if self.last.len() > MAX_LAG {
+            self.last.pop_front();
+        }
+    }
+
+    /// Normalized autocorrelation at a lag up to `MAX_LAG`, as
+    /// `autocorrelation` computes it on the whole series.
+    fn at(&self, samples: &Moments, lag: usize) -> f64 {
+        let n = samples.count as f64;
+        let m = samples.sum / n;
+        let c0 = samples.squares / n - m * m;
+        if c0 <= 1e-12 * samples.squares / n {
+            return 0.0;
+        }
+        let head = samples.sum - self.last.iter().rev().take(lag).sum::<f64>();
+        let tail = samples.sum - self.first[..lag].iter().sum::<f64>();
+        let len = (samples.count - lag) as f64;
+        let ct = (self.products[lag - 1] - m * (head + tail) + len * m * m) / len;
+        ct / c0
+    }
+
+    /// Integrated autocorrelation time as `integrated_autocorrelation_time`
+    /// computes it, `None` when the window goes past `MAX_LAG`.
+    fn time(&self, samples: &Moments) -> Option<f64> {
+        let mut tau = 0.5;
+        for lag in 1..samples.count.min(MAX_LAG + 1) {
+            tau += self.at(samples, lag);
+            if lag as f64 >= WINDOW_FACTOR * tau {
+                return Some(tau.max(0.5));
+            }
+        }
+        if samples.count > MAX_LAG + 1 { None } else { Some(tau.max(0.5)) }
+    }
+}
+
+
+/// Error analysis of a series on the fly, without keeping it.
+///
+/// Samples go through a logarithmic binning analysis, one level per bin
+/// size, and into a fixed number of blocks for jackknife estimates: once
+/// there are twice `JACKKNIFE_BLOCKS` full blocks, neighbors are merged and
+/// the block size doubles. Memory grows with the log of the number of
+/// samples, and the estimates agree with the functions on whole series.
+///
+/// The autocorrelation function can be followed as well, up to a fixed lag,
+/// for short autocorrelation times the binning analysis overestimates.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Accumulator {
+    /// Moments of the bin means at every level, level 0 holds the samples
+    /// themselves, along with the bin waiting for its pair to make a bin
+    /// of the next level.
+    levels: Vec<(Moments, Option<f64>)>,
+    /// Moments of consecutive blocks of `block` samples, the last block
+    /// may still be filling up.
+    blocks: Vec<Moments>,
+    block: usize,
+    correlator: Option<Correlator>,
+}
+
+
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+impl Accumulator {
+    pub fn new() -> Self {
+        Self {
+            levels: Vec::new(),
+            blocks: Vec::new(),
+            block: 1,
+            correlator: None,
+        }
+    }
+
+    /// Follow the autocorrelation function too.
+    pub fn with_autocorrelation(mut self) -> Self {
+        self.correlator = Some(Correlator::default());
+        self
+    }
+
+    pub fn push(&mut self, x: f64) {
+        let mut value = x;
+        let mut level = 0;
+        loop {
+            if level == self.levels.len() {
+                self.levels.push((Moments::default(), None));
+            }
+            let (ref mut moments, ref mut pending) = self.levels[level];
+            moments.add(value);
+            match pending.take() {
+                Some(first) => value = (first + value) / 2.0,
+                None => {
+                    *pending = Some(value);
+                    break;
+                },
+            }
+            level += 1;
+        }
+        if self.blocks.last().is_none_or(|b| b.count == self.block) {
+            if self.blocks.len() == 2 * JACKKNIFE_BLOCKS {
+                self.blocks = self.blocks.chunks(2).map(|pair| pair[0].merge(pair[1])).collect();
+                self.block *= 2;
+            }
+            self.blocks.push(Moments::default());
+        }
+        self.blocks.last_mut().expect("a block to fill").add(x);
+        if let Some(ref mut correlator) = self.correlator {
+            correlator.push(x);
+        }
+    }
+
+    fn samples(&self) -> Moments {
+        self.levels.first().map_or(Moments::default(), |&(moments, _)| moments)
+    }
+
+    pub fn count(&self) -> usize {
+        self.samples().count
+    }
+
+    pub fn mean(&self) -> f64 {
+        let samples = self.samples();
+        samples.sum / samples.count as f64
+    }
+
+    /// Standard error of the mean, the largest among the binning levels
+    /// with enough bins as for `binning_error`.
+    pub fn binning_error(&self) -> f64 {
+        self.levels.iter()
+            .map(|&(moments, _)| moments)
+            .filter(|moments| moments.count >= MIN_BINS)
+            .fold(self.samples().error(), |error, moments| error.max(moments.error()))
+    }
+
+    /// Integrated autocorrelation time, with Sokal's windowing when the
+    /// autocorrelation function is followed far enough. Otherwise out of the
+    /// binning analysis, the squared error of the mean is `2 tau` times the
+    /// naive one.
+    pub fn autocorrelation_time(&self) -> f64 {
+        let samples = self.samples();
+        let windowed = self.correlator.as_ref().and_then(|c| c.time(&samples));
+        if let Some(tau) = windowed {
+            return tau;
+        }
+        let naive = samples.error();
+        if naive > 0.0 {
+            0.5 * (self.binning_error() / naive).powi(2)
+        } else {
+            0.5
+        }
+    }
+
+    /// Jackknife estimate of a quantity derived from the means of the
+    /// samples and of their squares, returns the value and its error. The
+    /// samples of a block still filling up are left out of the error.
+    pub fn jackknife<F>(&self, f: F) -> (f64, f64)
+        where F: Fn(f64, f64) -> f64
+    {
+        let samples = self.samples();
+        let n = samples.count as f64;
+        let value = f(samples.sum / n, samples.squares / n);
+        let full: Vec<Moments> = self.blocks.iter()
+            .filter(|b| b.count == self.block)
+            .cloned()
+            .collect();
+        let nbins = full.len();
+        if nbins < 2 {
+            return (value, 0.0);
+        }
+        let used = full.iter().fold(Moments::default(), |a, &b| a.merge(b));
+        let estimates: Vec<f64> = full.iter()
+            .map(|b| {
+                let n = (used.count - b.count) as f64;
+                f((used.sum - b.sum) / n, (used.squares - b.squares) / n)
+            })
+            .collect();
+        let m = mean(&estimates);
+        let spread = estimates.iter().map(|e| (e - m) * (e - m)).sum::<f64>();
+        (value, (spread * (nbins - 1) as f64 / nbins as f64).sqrt())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    fn noise(n: usize) -> Vec<f64> {
+        let mut rng = XorShiftRng::from_seed([1, 2, 3, 4]);
+        (0..n).map(|_| rng.gen::<f64>() - 0.5).collect()
+    }
+
+    /// An AR(1) process with autocorrelation time `(1 + a) / (2 (1 - a))`.
+    fn correlated(n: usize, a: f64) -> Vec<f64> {
+        let mut x = 0.0;
+        noise(n).into_iter().map(|e| { x = a * x + e; x }).collect()
+    }
+
+    #[test]
+    fn binning_of_series() {
+        let series = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(bin(&series, 2), vec![1.5, 3.5]);
+        assert!((mean(&series) - 3.0).abs() < 1e-12);
+        assert!((variance(&series) - 2.5).abs() < 1e-12);
+    }
+
+    #[test]
+    fn uncorrelated_noise_has_no_autocorrelation() {
+        let series = noise(20_000);
+        let tau = integrated_autocorrelation_time(&series);
+        assert!((tau - 0.5).abs() < 0.1, "tau = {}", tau);
+        let naive = (variance(&series) / series.len() as f64).sqrt();
+        assert!(binning_error(&series) < 1.5 * naive);
+    }
+
+    #[test]
+    fn correlated_series_have_larger_errors() {
+        let series = correlated(100_000, 0.9);
+        let tau = integrated_autocorrelation_time(&series);
+        assert!((tau - 9.5).abs() < 1.5, "tau = {}", tau);
+        let naive = (variance(&series) / series.len() as f64).sqrt();
+        let expected = naive * (2.0 * tau).sqrt();
+        let error = binning_error(&series);
+        assert!((error - expected).abs() < 0.25 * expected, "{} vs {}", error, expected);
+    }
+
+    #[test]
+    fn mser_drops_transients() {
+        let relaxing: Vec<f64> = noise(4_000).iter()
+            .enumerate()
+            .map(|(i, e)| 10.0 * (-(i as f64) / 100.0).exp() + e)
+            .collect();
+        let cut = mser(&relaxing).unwrap();
+        assert!(cut > 300 && cut < 1_000, "cut = {}", cut);
+        assert!(mser(&noise(4_000)).unwrap() < 400);
+        let drifting: Vec<f64> = (0..1_000).map(|i| i as f64).collect();
+        assert_eq!(mser(&drifting), None);
+        assert_eq!(mser(&[f64::NAN; 100]), Some(0));
+    }
+
+    #[test]
+    fn jackknife_of_the_mean_is_the_standard_error() {
+        let series = noise(1_024);
+        let (value, error) = jackknife(&[&series], 1_024, |m| m[0]);
+        assert!((value - mean(&series)).abs() < 1e-12);
+        let naive = (variance(&series) / series.len() as f64).sqrt();
+        assert!((error - naive).abs() < 1e-9);
+    }
+
+    #[test]
+    fn accumulators_match_whole_series() {
+        let series = correlated(1_024, 0.9);
+        let mut accumulator = Accumulator::new();
+        for &x in series.iter() {
+            accumulator.push(x);
+        }
+        assert_eq!(accumulator.count(), 1_024);
+        assert!((accumulator.mean() - mean(&series)).abs() < 1e-12);
+        assert!((accumulator.binning_error() - binning_error(&series)).abs() < 1e-12);
+        // 1024 samples end up in 64 blocks of 16.
+        let squares: Vec<f64> = series.iter().map(|x| x * x).collect();
+        let f = |m: f64, m2: f64| m2 - m * m;
+        let (value, error) = accumulator.jackknife(f);
+        let (expected, expected_error) = jackknife(&[&series, &squares], 64, |m| f(m[0], m[1]));
+        assert!((value - expected).abs() < 1e-12);
+        assert!((error - expected_error).abs() < 1e-12);
+    }
+
+    #[test]
+    fn accumulators_measure_autocorrelation_times() {
+        let fast = correlated(10_000, 0.5);
+        let mut accumulator = Accumulator::new().with_autocorrelation();
+        for &x in fast.iter() {
+            accumulator.push(x);
+        }
+        let tau = accumulator.autocorrelation_time();
+        assert!((tau - integrated_autocorrelation_time(&fast)).abs() < 1e-9, "tau = {}", tau);
+        let mut noisy = Accumulator::new().with_autocorrelation();
+        let mut slow = Accumulator::new().with_autocorrelation();
+        let mut binned = Accumulator::new();
+        for (&x, &y) in noise(100_000).iter().zip(correlated(100_000, 0.98).iter()) {
+            noisy.push(x);
+            slow.push(y);
+            binned.push(y);
+        }
+        let tau = noisy.autocorrelation_time();
+        assert!((tau - 0.5).abs() < 0.1, "tau = {}", tau);
+        // Past the lags followed, so out of the binning analysis.
+        let tau = slow.autocorrelation_time();
+        assert_eq!(tau, binned.autocorrelation_time());
+        assert!((tau - 49.5).abs() < 10.0, "tau = {}", tau);
+        assert_eq!(Accumulator::new().autocorrelation_time(), 0.5);
+    }
+}
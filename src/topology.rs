@@ -0,0 +1,321 @@
+//! Topological charge of two dimensional spin textures.
+//!
+//! The charge is computed with the solid angle triangulation of Berg and
+//! Lüscher: the sites are triangulated in the xy plane and the solid angles
+//! subtended by the spins at the corners of every triangle are added up, so
+//! that the charge of a smooth texture, like a skyrmion, is an integer.
+
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+use lattice::Geometry;
+use state::{Spin, State};
+
+
+/// Relative size of the jitter used to break the ties of cocircular sites.
+const JITTER: f64 = 1e-7;
+
+/// Triangles smaller than this, relative to the mean area per site, are
+/// dropped, they come from collinear sites at open boundaries.
+const MIN_AREA: f64 = 1e-3;
+
+
+fn dot(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+
+fn cross(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+
+/// Twice the signed area of a triangle, positive if counter clockwise.
+fn orientation(a: [f64; 2], b: [f64; 2], c: [f64; 2]) -> f64 {
+    (b[0] - a[0]) * (c[1] - a[1]) - (b[1] - a[1]) * (c[0] - a[0])
+}
+
+
+/// The solid angle subtended by three unit vectors, signed by their order.
+pub fn solid_angle(a: [f64; 3], b: [f64; 3], c: [f64; 3]) -> f64 {
+    let numerator = dot(a, cross(b, c));
+    let denominator = 1.0 + dot(a, b) + dot(b, c) + dot(c, a);
+    2.0 * numerator.atan2(denominator)
+}
+
+
+/// A triangle being built, with its circumcircle.
+struct Triangle {
+    corners: [usize; 3],
+    center: [f64; 2],
+    radius2: f64,
+}
+
+
+impl Triangle {
+    fn new(corners: [usize; 3], points: &[[f64; 2]]) -> Self {
+        let [a, b, c] = [points[corners[0]], points[corners[1]], points[corners[2]]];
+        let d = 2.0 * orientation(a, b, c);
+        let (a2, b2, c2) = (dot2(a, a), dot2(b, b), dot2(c, c));
+        let center = [
+            (a2 * (b[1] - c[1]) + b2 * (c[1] - a[1]) + c2 * (a[1] - b[1])) / d,
+            (a2 * (c[0] - b[0]) + b2 * (a[0] - c[0]) + c2 * (b[0] - a[0])) / d,
+        ];
+        let radius2 = (a[0] - center[0]).powi(2) + (a[1] - center[1]).powi(2);
+        Self {
+            corners,
+            center,
+            radius2,
+        }
+    }
+
+    fn encloses(&self, p: [f64; 2]) -> bool {
+        (p[0] - self.center[0]).powi(2) + (p[1] - self.center[1]).powi(2) < self.radius2
+    }
+}
+
+
+fn dot2(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+
+/// Bowyer-Watson Delaunay triangulation of a set of points.
+fn delaunay(points: &[[f64; 2]]) -> Vec<[usize; 3]> {
+    let n = points.len();
+    let (mut lo, mut hi) = ([f64::MAX; 2], [f64::MIN; 2]);
+    for p in points {
+        for k in 0..2 {
+            lo[k] = lo[k].min(p[k]);
+            hi[k] = hi[k].max(p[k]);
+        }
+    }
+    let span = (hi[0] - lo[0]).max(hi[1] - lo[1]).max(1.0);
+    let mid = [(lo[0] + hi[0]) / 2.0, (lo[1] + hi[1]) / 2.0];
+    let mut all = points.to_vec();
+    all.push([mid[0] - 20.0 * span, mid[1] - 10.0 * span]);
+    all.push([mid[0] + 20.0 * span, mid[1] - 10.0 * span]);
+    all.push([mid[0], mid[1] + 20.0 * span]);
+    let mut triangles = vec![Triangle::new([n, n + 1, n + 2], &all)];
+    for (i, &p) in points.iter().enumerate() {
+        let mut edges: Vec<(usize, usize)> = Vec::new();
+        triangles.retain(|t| {
+            if !t.encloses(p) {
+                return true;
+            }
+            let [a, b, c] = t.corners;
+            edges.extend_from_slice(&[(a, b), (b, c), (c, a)]);
+            false
+        });
+        // Edges shared by two removed triangles are inside the cavity.
+        let boundary: Vec<(usize, usize)> = edges.iter()
+            .filter(|&&(a, b)| !edges.iter().any(|&(c, d)| c == b && d == a))
+            .cloned()
+            .collect();
+        for (a, b) in boundary {
+            triangles.push(Triangle::new([a, b, i], &all));
+        }
+    }
+    triangles.into_iter()
+        .map(|t| t.corners)
+        .filter(|c| c.iter().all(|&k| k < n))
+        .collect()
+}
+
+
+/// A triangulation of the sites of a lattice.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Triangulation {
+    triangles: Vec<[usize; 3]>,
+}
+
+
+impl Triangulation {
+    /// New up a triangulation out of triangles given as site indices, they
+    /// should be counter clockwise in the xy plane.
+    pub fn new(triangles: Vec<[usize; 3]>) -> Self {
+        Self { triangles }
+    }
+
+    /// Delaunay triangulation of the projection of the sites on the xy
+    /// plane, periodic boundaries are honored so every triangle across the
+    /// boundary shows up exactly once.
+    pub fn from_geometry(geometry: &Geometry) -> Self {
+        let n = geometry.len();
+        if n < 3 {
+            return Self::new(Vec::new());
+        }
+        let size = geometry.size();
+        let periodic = geometry.periodic();
+        let (mut lo, mut hi) = ([f64::MAX; 2], [f64::MIN; 2]);
+        for p in geometry.positions() {
+            for k in 0..2 {
+                lo[k] = lo[k].min(p[k]);
+                hi[k] = hi[k].max(p[k]);
+            }
+        }
+        let area = (0..2)
+            .map(|k| if periodic[k] { size[k] } else { (hi[k] - lo[k]).max(1e-12) })
+            .product::<f64>();
+        let spacing = (area / n as f64).sqrt();
+
+        // Periodic images of the sites close to the box, so triangles across
+        // the boundary get built.
+        let margin = 3.0 * spacing;
+        let mut points = Vec::new();
+        let mut sources = Vec::new();
+        let shifts = |k: usize| if periodic[k] { vec![0.0, -size[k], size[k]] } else { vec![0.0] };
+        let mut seed: u64 = 0x2545_f491_4f6c_dd1d;
+        for (i, p) in geometry.positions().iter().enumerate() {
+            // Deterministic jitter to break ties between cocircular sites.
+            let mut jitter = [0.0; 2];
+            for j in jitter.iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 7;
+                seed ^= seed << 17;
+                *j = ((seed >> 11) as f64 / (1u64 << 53) as f64 - 0.5) * JITTER * spacing;
+            }
+            for &dx in shifts(0).iter() {
+                for &dy in shifts(1).iter() {
+                    let q = [p[0] + dx + jitter[0], p[1] + dy + jitter[1]];
+                    let inside = (0..2).all(|k| q[k] > lo[k] - margin && q[k] < hi[k] + margin);
+                    if (dx == 0.0 && dy == 0.0) || inside {
+                        points.push(q);
+                        sources.push(i);
+                    }
+                }
+            }
+        }
+
+        // Keep the one image of every triangle with its centroid in the box.
+        let origin = [lo[0] - spacing / 2.0, lo[1] - spacing / 2.0];
+        let in_box = |c: [f64; 2]| (0..2).all(|k| {
+            !periodic[k] || (c[k] >= origin[k] && c[k] < origin[k] + size[k])
+        });
+        let triangles = delaunay(&points)
+            .into_iter()
+            .filter_map(|[a, b, c]| {
+                let (pa, pb, pc) = (points[a], points[b], points[c]);
+                let centroid = [(pa[0] + pb[0] + pc[0]) / 3.0, (pa[1] + pb[1] + pc[1]) / 3.0];
+                let signed = orientation(pa, pb, pc);
+                if !in_box(centroid) || signed.abs() < 2.0 * MIN_AREA * spacing * spacing {
+                    return None;
+                }
+                if signed > 0.0 {
+                    Some([sources[a], sources[b], sources[c]])
+                } else {
+                    Some([sources[a], sources[c], sources[b]])
+                }
+            })
+            .collect();
+        Self::new(triangles)
+    }
+
+    pub fn triangles(&self) -> &[[usize; 3]] {
+        &self.triangles
+    }
+
+    /// The topological charge of a state, the sum of the solid angles of all
+    /// triangles over `4 pi`.
+    pub fn charge<S: Spin>(&self, state: &State<S>) -> f64 {
+        let unit = |i: usize| {
+            let m = state.at(i).magnetization();
+            let norm = dot(m, m).sqrt();
+            [m[0] / norm, m[1] / norm, m[2] / norm]
+        };
+        self.triangles.iter()
+            .map(|t| solid_angle(unit(t[0]), unit(t[1]), unit(t[2])))
+            .sum::<f64>() / (4.0 * PI)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{Triangulation, solid_angle};
+    use std::f64::consts::PI;
+    use lattice::{Geometry, LatticeBuilder, UnitCell};
+    use state::{State, HeisenbergSpin};
+    use vegas_lattice::Axis;
+
+    fn skyrmion(geometry: &Geometry, radius: f64) -> State<HeisenbergSpin> {
+        let size = geometry.size();
+        let center = [size[0] / 2.0 - 0.25, size[1] / 2.0 - 0.25];
+        let mut state = State::up_with_size(geometry.len());
+        for (i, p) in geometry.positions().iter().enumerate() {
+            let (dx, dy) = (p[0] - center[0], p[1] - center[1]);
+            let r = (dx * dx + dy * dy).sqrt();
+            if r < radius {
+                let theta = PI * (1.0 - r / radius);
+                let phi = dy.atan2(dx);
+                state.set_at(i, HeisenbergSpin::new([
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    theta.cos(),
+                ]));
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn solid_angle_of_an_octant() {
+        let omega = solid_angle([1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]);
+        assert!((omega - PI / 2.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn periodic_triangulations_cover_the_torus() {
+        let cells = [
+            (UnitCell::square(), (6, 5, 1)),
+            (UnitCell::triangular(), (5, 3, 1)),
+            (UnitCell::honeycomb(), (3, 2, 1)),
+            (UnitCell::kagome(), (2, 2, 1)),
+        ];
+        for &(ref cell, (nx, ny, nz)) in cells.iter() {
+            let geometry = Geometry::from_lattice(
+                &LatticeBuilder::new(cell.clone()).extent(nx, ny, nz).build().unwrap());
+            let triangulation = Triangulation::from_geometry(&geometry);
+            // A triangulated torus has twice as many triangles as vertices.
+            assert_eq!(triangulation.triangles().len(), 2 * geometry.len());
+        }
+    }
+
+    #[test]
+    fn charge_of_uniform_states_vanishes() {
+        let geometry = Geometry::from_lattice(
+            &LatticeBuilder::new(UnitCell::square()).extent(5, 5, 1).build().unwrap());
+        let triangulation = Triangulation::from_geometry(&geometry);
+        let state = State::<HeisenbergSpin>::up_with_size(geometry.len());
+        assert!(triangulation.charge(&state).abs() < 1e-12);
+    }
+
+    #[test]
+    fn charge_of_a_skyrmion() {
+        for cell in [UnitCell::square(), UnitCell::triangular()].iter() {
+            let geometry = Geometry::from_lattice(
+                &LatticeBuilder::new(cell.clone()).extent(16, 10, 1).build().unwrap());
+            let triangulation = Triangulation::from_geometry(&geometry);
+            let charge = triangulation.charge(&skyrmion(&geometry, 6.0));
+            assert!((charge.abs() - 1.0).abs() < 1e-9, "charge = {}", charge);
+        }
+    }
+
+    #[test]
+    fn charge_with_open_boundaries() {
+        let geometry = Geometry::from_lattice(&LatticeBuilder::new(UnitCell::square())
+            .extent(16, 16, 1)
+            .open_along(Axis::X)
+            .open_along(Axis::Y)
+            .build().unwrap());
+        let triangulation = Triangulation::from_geometry(&geometry);
+        assert_eq!(triangulation.triangles().len(), 2 * 15 * 15);
+        let charge = triangulation.charge(&skyrmion(&geometry, 6.0));
+        assert!((charge.abs() - 1.0).abs() < 1e-9, "charge = {}", charge);
+    }
+}
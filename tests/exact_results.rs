@@ -0,0 +1,358 @@
+//! Validation of the integrators against exact results: Onsager's solution
+//! of the square lattice Ising model, exact enumeration and exact partition
+//! functions of small clusters, and detailed balance of every integrator.
+//!
+//! Sampled averages must fall within a few binning errors of the exact ones,
+//! on top of a small allowance for finite size effects where they apply.
+
+extern crate rand;
+extern crate sprs;
+#[macro_use] extern crate vegas_rs;
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use sprs::TriMat;
+
+use vegas_rs::energy::{EnergyComponent, ExchangeEnergy, UniaxialAnisotropy, ZeemanEnergy};
+use vegas_rs::exact::Enumeration;
+use vegas_rs::integrator::{CheckerboardIntegrator, Integrator, MetropolisIntegrator};
+use vegas_rs::lattice::{LatticeBuilder, UnitCell};
+use vegas_rs::observables::Observables;
+use vegas_rs::state::{HeisenbergSpin, IsingSpin, Spin, State};
+use vegas_rs::statistics;
+
+
+/// How many standard errors a sampled average may be off.
+const SIGMAS: f64 = 5.0;
+
+
+/// Run `sweeps` sweeps after `thermalization` ones, measuring after every
+/// sweep.
+fn sample<I, S, T>(integrator: &mut I, hamiltonian: &T, mut state: State<S>,
+                   thermalization: usize, sweeps: usize) -> Observables
+    where I: Integrator<S, T>,
+          S: Spin,
+          T: EnergyComponent<S>
+{
+    for _ in 0..thermalization {
+        state = integrator.step(hamiltonian, &state).unwrap();
+    }
+    let mut observables = Observables::new(state.len());
+    for _ in 0..sweeps {
+        state = integrator.step(hamiltonian, &state).unwrap();
+        observables.measure(hamiltonian, &state);
+    }
+    observables
+}
+
+
+fn assert_close(name: &str, sampled: f64, error: f64, exact: f64, slack: f64) {
+    assert!((sampled - exact).abs() < SIGMAS * error + slack,
+            "{}: sampled {} +- {}, exact {}", name, sampled, error, exact);
+}
+
+
+/// Complete elliptic integral of the first kind, by the arithmetic-geometric
+/// mean.
+fn elliptic_k(k: f64) -> f64 {
+    let (mut a, mut b) = (1.0, (1.0 - k * k).sqrt());
+    while (a - b).abs() > 1e-15 {
+        let next = ((a + b) / 2.0, (a * b).sqrt());
+        a = next.0;
+        b = next.1;
+    }
+    PI / (2.0 * a)
+}
+
+
+/// Onsager's energy per site of the square lattice Ising ferromagnet with
+/// unit coupling.
+fn onsager_energy(temp: f64) -> f64 {
+    let beta = 2.0 / temp;
+    let k = 2.0 * beta.sinh() / beta.cosh().powi(2);
+    let factor = 2.0 * beta.tanh().powi(2) - 1.0;
+    - 1.0 / beta.tanh() * (1.0 + 2.0 / PI * factor * elliptic_k(k))
+}
+
+
+/// Yang's spontaneous magnetization per site, zero above the critical
+/// temperature.
+fn onsager_magnetization(temp: f64) -> f64 {
+    let sinh = (2.0 / temp).sinh();
+    (1.0 - sinh.powi(-4)).max(0.0).powf(0.125)
+}
+
+
+fn square_ising(size: usize) -> ExchangeEnergy {
+    let lattice = LatticeBuilder::new(UnitCell::square()).extent(size, size, 1).build().unwrap();
+    ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+}
+
+
+fn check_onsager<I>(mut integrator: I, exchange: &ExchangeEnergy, temp: f64)
+    where I: Integrator<IsingSpin, ExchangeEnergy>
+{
+    let nsites = exchange.matrix().rows() as f64;
+    let state = State::<IsingSpin>::up_with_size(exchange.matrix().rows());
+    let observables = sample(&mut integrator, exchange, state, 1_000, 4_000);
+    let name = format!("energy at T = {}", temp);
+    assert_close(&name, observables.energy() / nsites, observables.energy_error() / nsites,
+                 onsager_energy(temp), 0.005);
+    if temp < 2.0 / (1.0 + 2f64.sqrt()).ln() {
+        let name = format!("magnetization at T = {}", temp);
+        assert_close(&name, observables.magnetization() / nsites,
+                     observables.magnetization_error() / nsites,
+                     onsager_magnetization(temp), 0.005);
+    }
+}
+
+
+#[test]
+fn onsager_values_are_known() {
+    assert!((onsager_energy(2.0) + 1.745564).abs() < 1e-5);
+    assert!((onsager_magnetization(2.0) - 0.911319).abs() < 1e-5);
+    assert_eq!(onsager_magnetization(3.0), 0.0);
+}
+
+
+#[test]
+fn metropolis_reproduces_onsager() {
+    let exchange = square_ising(32);
+    for &temp in [1.8, 2.0, 3.0].iter() {
+        check_onsager(MetropolisIntegrator::new(temp).unwrap().with_seed(17), &exchange, temp);
+    }
+}
+
+
+#[test]
+fn checkerboard_reproduces_onsager() {
+    let exchange = square_ising(32);
+    for &temp in [1.8, 2.0, 3.0].iter() {
+        let integrator = CheckerboardIntegrator::new(temp, exchange.coloring()).unwrap()
+            .with_seed(17);
+        check_onsager(integrator, &exchange, temp);
+    }
+}
+
+
+fn check_cluster<I, T>(name: &str, mut integrator: I, hamiltonian: &T, nsites: usize, temp: f64)
+    where I: Integrator<IsingSpin, T>,
+          T: EnergyComponent<IsingSpin>
+{
+    let exact = Enumeration::new(hamiltonian, nsites).unwrap();
+    let state = State::<IsingSpin>::up_with_size(nsites);
+    let observables = sample(&mut integrator, hamiltonian, state, 1_000, 50_000);
+    assert_close(&format!("{} energy", name), observables.energy(),
+                 observables.energy_error(), exact.energy(temp), 0.0);
+    assert_close(&format!("{} magnetization", name), observables.magnetization(),
+                 observables.magnetization_error(), exact.magnetization(temp), 0.0);
+    assert_close(&format!("{} specific heat", name), observables.specific_heat(temp),
+                 observables.specific_heat_error(temp), exact.specific_heat(temp), 0.0);
+}
+
+
+#[test]
+fn small_ising_clusters_match_exact_enumeration() {
+    let clusters = [
+        ("triangular", LatticeBuilder::new(UnitCell::triangular()).extent(2, 3, 1)),
+        ("honeycomb", LatticeBuilder::new(UnitCell::honeycomb()).extent(2, 2, 1)),
+        ("kagome", LatticeBuilder::new(UnitCell::kagome()).extent(2, 1, 1)),
+    ];
+    for &(name, ref builder) in clusters.iter() {
+        let lattice = builder.build().unwrap();
+        let nsites = lattice.sites().len();
+        assert!(nsites <= 16, "{} has too many sites to enumerate", name);
+        let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+        let hamiltonian = hamiltonian!(
+            ZeemanEnergy::new(IsingSpin::Up, 0.3),
+            ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap()
+        );
+        // Colder than this single spin moves no longer tunnel between the
+        // up and down sectors within the run, which the enumeration counts.
+        for &temp in [2.5, 5.0].iter() {
+            let metropolis = MetropolisIntegrator::new(temp).unwrap().with_seed(5);
+            check_cluster(name, metropolis, &hamiltonian, nsites, temp);
+            let checkerboard = CheckerboardIntegrator::new(temp, exchange.coloring()).unwrap()
+                .with_seed(5);
+            check_cluster(name, checkerboard, &hamiltonian, nsites, temp);
+        }
+    }
+}
+
+
+#[test]
+fn binning_errors_cover_the_exact_averages() {
+    let lattice = LatticeBuilder::new(UnitCell::honeycomb()).extent(2, 2, 1).build().unwrap();
+    let nsites = lattice.sites().len();
+    let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+    let temp = 3.0;
+    let exact = Enumeration::new::<IsingSpin, _>(&exchange, nsites).unwrap();
+    // About 95% of independent runs should land within two errors.
+    let runs = 40;
+    let covered = (0..runs)
+        .filter(|&seed| {
+            let mut integrator = MetropolisIntegrator::new(temp).unwrap().with_seed(seed);
+            let state = State::<IsingSpin>::up_with_size(nsites);
+            let observables = sample(&mut integrator, &exchange, state, 200, 2_000);
+            (observables.energy() - exact.energy(temp)).abs() < 2.0 * observables.energy_error()
+        })
+        .count();
+    assert!(covered >= 32, "only {} of {} runs within two errors", covered, runs);
+}
+
+
+/// `<f(cos θ)>` over a single spin with energy `energy(cos θ)`, by
+/// Simpson's rule.
+fn single_spin_average<E, F>(energy: E, f: F, temp: f64) -> f64
+    where E: Fn(f64) -> f64,
+          F: Fn(f64) -> f64
+{
+    let n = 2_000;
+    let h = 2.0 / n as f64;
+    let (mut z, mut sum) = (0.0, 0.0);
+    for i in 0..(n + 1) {
+        let c = -1.0 + i as f64 * h;
+        let weight = if i == 0 || i == n { 1.0 } else if i % 2 == 1 { 4.0 } else { 2.0 };
+        let boltzmann = weight * (- energy(c) / temp).exp();
+        z += boltzmann;
+        sum += boltzmann * f(c);
+    }
+    sum / z
+}
+
+
+fn heisenberg_dimer(coupling: f64) -> ExchangeEnergy {
+    let mut matrix = TriMat::new((2, 2));
+    matrix.add_triplet(0, 1, coupling);
+    matrix.add_triplet(1, 0, coupling);
+    ExchangeEnergy::new(matrix.to_csr()).unwrap()
+}
+
+
+#[test]
+fn heisenberg_dimers_match_the_langevin_function() {
+    // Only the relative angle matters, so <E> = -J L(J / T).
+    let langevin = |x: f64| 1.0 / x.tanh() - 1.0 / x;
+    let dimer = heisenberg_dimer(1.0);
+    for &temp in [0.3, 1.0, 4.0].iter() {
+        let exact = - langevin(1.0 / temp);
+        let state = State::<HeisenbergSpin>::up_with_size(2);
+        let mut metropolis = MetropolisIntegrator::new(temp).unwrap().with_seed(9);
+        let observables = sample(&mut metropolis, &dimer, state.clone(), 1_000, 100_000);
+        assert_close("metropolis dimer energy", observables.energy(),
+                     observables.energy_error(), exact, 0.0);
+        let mut checkerboard = CheckerboardIntegrator::new(temp, dimer.coloring()).unwrap()
+            .with_seed(9);
+        let observables = sample(&mut checkerboard, &dimer, state, 1_000, 100_000);
+        assert_close("checkerboard dimer energy", observables.energy(),
+                     observables.energy_error(), exact, 0.0);
+    }
+}
+
+
+#[test]
+fn a_heisenberg_spin_in_a_field_with_anisotropy_matches_its_partition_function() {
+    let (field, anisotropy) = (0.7, -1.3);
+    let hamiltonian = hamiltonian!(
+        ZeemanEnergy::new(HeisenbergSpin::up(), field),
+        UniaxialAnisotropy::new(HeisenbergSpin::up(), anisotropy)
+    );
+    let energy = |c: f64| anisotropy * c * c - field * c;
+    for &temp in [0.5, 2.0].iter() {
+        let exact_energy = single_spin_average(energy, energy, temp);
+        let exact_mz = single_spin_average(energy, |c| c, temp);
+        let mut integrator = MetropolisIntegrator::new(temp).unwrap().with_seed(21);
+        let mut state = State::<HeisenbergSpin>::up_with_size(1);
+        let (mut energies, mut mzs) = (Vec::new(), Vec::new());
+        for sweep in 0..201_000 {
+            state = integrator.step(&hamiltonian, &state).unwrap();
+            if sweep >= 1_000 {
+                energies.push(hamiltonian.total_energy(&state));
+                mzs.push(state.magnetization()[2]);
+            }
+        }
+        assert_close("single spin energy", statistics::mean(&energies),
+                     statistics::binning_error(&energies), exact_energy, 0.0);
+        assert_close("single spin magnetization", statistics::mean(&mzs),
+                     statistics::binning_error(&mzs), exact_mz, 0.0);
+    }
+}
+
+
+/// Label an Ising state by its bits.
+fn label(state: &State<IsingSpin>) -> usize {
+    state.spins()
+        .iter()
+        .enumerate()
+        .filter(|&(_, s)| s.magnetization()[2] < 0.0)
+        .map(|(i, _)| 1 << i)
+        .sum()
+}
+
+
+/// Run an integrator on a small Ising ring in a field and check that states
+/// show up as often as Boltzmann says, and that every transition between
+/// two states happens as often as its reverse, as detailed balance demands.
+fn check_detailed_balance<I, T>(mut integrator: I, hamiltonian: &T, nsites: usize, temp: f64)
+    where I: Integrator<IsingSpin, T>,
+          T: EnergyComponent<IsingSpin>
+{
+    let nstates = 1 << nsites;
+    let mut state = State::<IsingSpin>::up_with_size(nsites);
+    let mut weights = vec![0.0; nstates];
+    for (config, weight) in weights.iter_mut().enumerate() {
+        for i in 0..nsites {
+            let spin = if config & (1 << i) == 0 { IsingSpin::Up } else { IsingSpin::Down };
+            state.set_at(i, spin);
+        }
+        *weight = (- hamiltonian.total_energy(&state) / temp).exp();
+    }
+    let z: f64 = weights.iter().sum();
+
+    let sweeps = 200_000;
+    let mut visits = vec![0usize; nstates];
+    let mut flows: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut current = label(&state);
+    for _ in 0..sweeps {
+        state = integrator.step(hamiltonian, &state).unwrap();
+        let next = label(&state);
+        visits[next] += 1;
+        if next != current {
+            *flows.entry((current, next)).or_insert(0) += 1;
+        }
+        current = next;
+    }
+
+    for (config, &count) in visits.iter().enumerate() {
+        let p = weights[config] / z;
+        let expected = p * sweeps as f64;
+        // Successive sweeps are correlated, so be generous with the error.
+        let error = (expected * (1.0 - p)).sqrt() * 3.0;
+        assert!((count as f64 - expected).abs() < SIGMAS * error + 1.0,
+                "state {:b} visited {} times, expected {}", config, count, expected);
+    }
+    for (&(a, b), &forward) in flows.iter() {
+        let backward = flows.get(&(b, a)).cloned().unwrap_or(0);
+        let total = (forward + backward) as f64;
+        assert!((forward as f64 - backward as f64).abs() < SIGMAS * total.sqrt() + 1.0,
+                "{:b} -> {:b} happened {} times but the reverse {}", a, b, forward, backward);
+    }
+}
+
+
+#[test]
+fn every_integrator_satisfies_detailed_balance() {
+    let lattice = LatticeBuilder::new(UnitCell::chain()).extent(4, 1, 1).build().unwrap();
+    let exchange = ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap();
+    let coloring = exchange.coloring();
+    let hamiltonian = hamiltonian!(
+        ExchangeEnergy::from_lattice(&lattice, |_| 1.0).unwrap(),
+        ZeemanEnergy::new(IsingSpin::Up, 0.4)
+    );
+    let temp = 2.0;
+    check_detailed_balance(MetropolisIntegrator::new(temp).unwrap().with_seed(1),
+                           &hamiltonian, 4, temp);
+    check_detailed_balance(CheckerboardIntegrator::new(temp, coloring).unwrap().with_seed(1),
+                           &hamiltonian, 4, temp);
+}